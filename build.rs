@@ -6,7 +6,7 @@ fn main() {
 
     Codegen::new()
         .out_dir("src/protocols")
-        .inputs(&[
+        .inputs([
             "src/protocols/protos/shim.proto",
             "src/protocols/protos/google/protobuf/any.proto",
             "src/protocols/protos/google/protobuf/empty.proto",
@@ -15,6 +15,9 @@ fn main() {
             "src/protocols/protos/github.com/containerd/containerd/api/types/mount.proto",
             "src/protocols/protos/github.com/containerd/containerd/api/types/task/task.proto",
             "src/protocols/protos/github.com/containerd/cgroup/stats/v1/metrics.proto",
+            "src/protocols/protos/github.com/containerd/cgroups/stats/v2/metrics_v2.proto",
+            "src/protocols/protos/github.com/containerd/containerd/runtime/v2/runc/options/oci.proto",
+            "src/protocols/protos/github.com/containerd/containerd/pkg/runtimeoptions/v1/runtimeoptions.proto",
         ])
         .include("src/protocols/protos")
         .rust_protobuf()
@@ -13,13 +13,123 @@
 #ifndef LIB_SHIM_V2_H
 #define LIB_SHIM_V2_H
 
+#include <stddef.h>
 #include <stdint.h>
 
+struct RuncOptions {
+    bool no_pivot_root;
+    bool no_new_keyring;
+    const char *shim_cgroup;
+    unsigned int io_uid;
+    unsigned int io_gid;
+    const char *binary_name;
+    const char *root;
+    const char *criu_path;
+    bool systemd_cgroup;
+    const char *criu_image_path;
+    const char *criu_work_path;
+};
+
+struct Mount {
+    const char *type;
+    const char *source;
+    const char *target;
+    const char **options;
+    size_t options_len;
+};
+
+struct CreateOptions {
+    /* shim options of runc based shims, may be NULL */
+    const struct RuncOptions *runc_options;
+    /* per-container runtime configuration file, e.g. a Kata configuration
+     * toml, may be NULL; exclusive with runc_options */
+    const char *config_path;
+    /* mounts the shim sets up as the container's rootfs, may be NULL */
+    const struct Mount *rootfs;
+    size_t rootfs_len;
+};
+
+struct Rlimit {
+    /* e.g. "RLIMIT_NOFILE" */
+    const char *type;
+    unsigned long long hard;
+    unsigned long long soft;
+};
+
+/* Capability sets, names such as "CAP_KILL" */
+struct Capabilities {
+    const char **bounding;
+    size_t bounding_len;
+    const char **effective;
+    size_t effective_len;
+    const char **inheritable;
+    size_t inheritable_len;
+    const char **permitted;
+    size_t permitted_len;
+    const char **ambient;
+    size_t ambient_len;
+};
+
+/* OCI process spec of an exec, strings and arrays may be NULL */
+struct ExecSpec {
+    const char **args;
+    size_t args_len;
+    /* "KEY=value" entries */
+    const char **env;
+    size_t env_len;
+    const char *cwd;
+    unsigned int uid;
+    unsigned int gid;
+    const unsigned int *additional_gids;
+    size_t additional_gids_len;
+    const char *username;
+    /* may be NULL */
+    const struct Capabilities *capabilities;
+    const struct Rlimit *rlimits;
+    size_t rlimits_len;
+    bool no_new_privileges;
+    const char *apparmor_profile;
+    const char *selinux_label;
+    bool terminal;
+    /* both 0 for no console size */
+    unsigned int console_height;
+    unsigned int console_width;
+    bool has_oom_score_adj;
+    int oom_score_adj;
+};
+
+struct Annotation {
+    char *key;
+    char *value;
+};
+
+/* What shim_v2_inspect_bundle found in a bundle, strings are "" when unset */
+struct BundleInfo {
+    char *oci_version;
+    /* root.path resolved against the bundle */
+    char *rootfs;
+    char *hostname;
+    char *cgroups_path;
+    /* from the CRI annotations of containerd or CRI-O */
+    char *sandbox_id;
+    char *container_type;
+    char *runtime_handler;
+    struct Annotation *annotations;
+    size_t annotations_len;
+};
+
 struct DeleteResponse {
     unsigned int exit_status;
     unsigned int pid;
 };
 
+struct CleanupResponse {
+    unsigned int exit_status;
+    unsigned int pid;
+    /* nanoseconds since the epoch */
+    int64_t exited_at;
+};
+
 enum Status {
     UnknownStatus = 0,
     CreatedStatus,
@@ -67,18 +177,358 @@ struct Stats {
     uint64_t swap_limit;
 };
 
+#define SHIM_V2_STATS_V2_VERSION 1
+
+struct PidsStats {
+    uint64_t current;
+    uint64_t limit;
+};
+
+struct CpuStats {
+    /* values in nanoseconds */
+    uint64_t usage_total;
+    uint64_t usage_kernel;
+    uint64_t usage_user;
+    uint64_t *per_cpu;
+    size_t per_cpu_len;
+    uint64_t throttling_periods;
+    uint64_t throttled_periods;
+    uint64_t throttled_time;
+};
+
+struct MemoryEntry {
+    uint64_t limit;
+    uint64_t usage;
+    uint64_t max;
+    uint64_t failcnt;
+};
+
+struct MemoryStats {
+    uint64_t cache;
+    uint64_t rss;
+    uint64_t rss_huge;
+    uint64_t mapped_file;
+    uint64_t dirty;
+    uint64_t writeback;
+    uint64_t pg_pg_in;
+    uint64_t pg_pg_out;
+    uint64_t pg_fault;
+    uint64_t pg_maj_fault;
+    uint64_t inactive_anon;
+    uint64_t active_anon;
+    uint64_t inactive_file;
+    uint64_t active_file;
+    uint64_t unevictable;
+    uint64_t hierarchical_memory_limit;
+    uint64_t hierarchical_swap_limit;
+    uint64_t total_cache;
+    uint64_t total_rss;
+    uint64_t total_rss_huge;
+    uint64_t total_mapped_file;
+    uint64_t total_dirty;
+    uint64_t total_writeback;
+    uint64_t total_pg_pg_in;
+    uint64_t total_pg_pg_out;
+    uint64_t total_pg_fault;
+    uint64_t total_pg_maj_fault;
+    uint64_t total_inactive_anon;
+    uint64_t total_active_anon;
+    uint64_t total_inactive_file;
+    uint64_t total_active_file;
+    uint64_t total_unevictable;
+    struct MemoryEntry usage;
+    struct MemoryEntry swap;
+    struct MemoryEntry kernel;
+    struct MemoryEntry kernel_tcp;
+};
+
+struct MemoryOomControl {
+    uint64_t oom_kill_disable;
+    uint64_t under_oom;
+    uint64_t oom_kill;
+};
+
+struct HugetlbStat {
+    char *pagesize;
+    uint64_t usage;
+    uint64_t max;
+    uint64_t failcnt;
+};
+
+struct BlkioEntry {
+    char *op;
+    char *device;
+    uint64_t major;
+    uint64_t minor;
+    uint64_t value;
+};
+
+struct BlkioEntries {
+    struct BlkioEntry *entries;
+    size_t len;
+};
+
+struct BlkioStats {
+    struct BlkioEntries io_service_bytes_recursive;
+    struct BlkioEntries io_serviced_recursive;
+    struct BlkioEntries io_queued_recursive;
+    struct BlkioEntries io_service_time_recursive;
+    struct BlkioEntries io_wait_time_recursive;
+    struct BlkioEntries io_merged_recursive;
+    struct BlkioEntries io_time_recursive;
+    struct BlkioEntries sectors_recursive;
+};
+
+struct BlkioOps {
+    uint64_t read;
+    uint64_t write;
+    uint64_t sync;
+    uint64_t async;
+    uint64_t discard;
+    uint64_t total;
+};
+
+/* the blkio tables of one device: bytes (io_service_bytes) and operations
+ * (io_serviced) on every host, the others only with the CFQ or BFQ
+ * scheduler, zero otherwise. service_time and wait_time are in ns, time in
+ * ms; time and sectors are not split by operation. */
+struct BlkioDeviceStats {
+    uint64_t major;
+    uint64_t minor;
+    char *device;
+    struct BlkioOps bytes;
+    struct BlkioOps ops;
+    struct BlkioOps queued;
+    struct BlkioOps service_time;
+    struct BlkioOps wait_time;
+    struct BlkioOps merged;
+    uint64_t time;
+    uint64_t sectors;
+};
+
+struct RdmaEntry {
+    char *device;
+    unsigned int hca_handles;
+    unsigned int hca_objects;
+};
+
+struct RdmaStats {
+    struct RdmaEntry *current;
+    size_t current_len;
+    struct RdmaEntry *limit;
+    size_t limit_len;
+};
+
+struct NetworkStat {
+    char *name;
+    uint64_t rx_bytes;
+    uint64_t rx_packets;
+    uint64_t rx_errors;
+    uint64_t rx_dropped;
+    uint64_t tx_bytes;
+    uint64_t tx_packets;
+    uint64_t tx_errors;
+    uint64_t tx_dropped;
+};
+
+struct CgroupStats {
+    uint64_t nr_sleeping;
+    uint64_t nr_running;
+    uint64_t nr_stopped;
+    uint64_t nr_uninterruptible;
+    uint64_t nr_io_wait;
+};
+
+/* pressure stall information, averages in percent, total in microseconds */
+struct PsiData {
+    double avg10;
+    double avg60;
+    double avg300;
+    uint64_t total;
+};
+
+struct PsiStats {
+    struct PsiData some;
+    struct PsiData full;
+};
+
+/* the has_* flags tell which resources the kernel reported */
+struct Pressure {
+    bool has_cpu;
+    struct PsiStats cpu;
+    bool has_memory;
+    struct PsiStats memory;
+    bool has_io;
+    struct PsiStats io;
+};
+
+/* Stats derived from the previous shim_v2_stats_derived call on the same
+ * connection, or from the caller's sample with shim_v2_stats_derived_from.
+ * Rates are per second and only valid when has_rates is set, it is not on the
+ * first call and after a counter reset, e.g. a restart. */
+struct DerivedStats {
+    bool has_rates;
+    uint64_t interval_ns;
+    /* percent of one CPU, above 100 when several are used */
+    double cpu_percent;
+    uint64_t cpu_nano_cores;
+    uint64_t memory_usage;
+    /* usage minus total_inactive_file */
+    uint64_t memory_working_set;
+    double blkio_read_bps;
+    double blkio_write_bps;
+    double blkio_read_iops;
+    double blkio_write_iops;
+    /* network counters from the runtime's network_stats source, as in
+     * StatsV2 */
+    double net_rx_bps;
+    double net_tx_bps;
+};
+
+/* Counters of one stats call, held by the caller of
+ * shim_v2_stats_derived_from. Zero it before the first call. at_ns is on
+ * CLOCK_MONOTONIC. */
+struct StatsSample {
+    bool valid;
+    uint64_t at_ns;
+    uint64_t cpu_total;
+    uint64_t blkio_read_bytes;
+    uint64_t blkio_write_bytes;
+    uint64_t blkio_read_ops;
+    uint64_t blkio_write_ops;
+    uint64_t net_rx_bytes;
+    uint64_t net_tx_bytes;
+};
+
+struct CacheCounters {
+    /* served from a result younger than the cache TTL */
+    uint64_t hits;
+    /* waited for a call already in flight, at most until the waiter's own
+     * timeout */
+    uint64_t coalesced;
+    /* calls made to the shim */
+    uint64_t calls;
+};
+
+/* stats and state cache counters of a connection, the TTLs are set with
+ * "cache_ttls" in the runtime config */
+struct CacheStats {
+    struct CacheCounters stats;
+    struct CacheCounters state;
+};
+
+/* Complete container metrics. The arrays are owned by the library and must be
+ * released with shim_v2_stats_v2_free before the struct is reused. */
+struct StatsV2 {
+    /* SHIM_V2_STATS_V2_VERSION of the library that filled the struct */
+    unsigned int version;
+    struct PidsStats pids;
+    struct CpuStats cpu;
+    struct MemoryStats memory;
+    struct MemoryOomControl memory_oom_control;
+    struct HugetlbStat *hugetlb;
+    size_t hugetlb_len;
+    struct BlkioStats blkio;
+    struct RdmaStats rdma;
+    /* from the shim or the task's network namespace, per the runtime's
+     * network_stats setting */
+    struct NetworkStat *network;
+    size_t network_len;
+    struct CgroupStats cgroup_stats;
+    struct BlkioDeviceStats *blkio_devices;
+    size_t blkio_devices_len;
+    /* 1 or 2, cgroup v2 values are converted to the v1 layout above */
+    unsigned int cgroup_version;
+    /* from the shim or the task's cgroup, per the runtime's pressure
+     * setting */
+    struct Pressure pressure;
+};
+
+/* Every function taking a container_id fails with -1 when it is NULL, not
+ * UTF-8 or not a containerd identifier: at most 76 letters, digits and '.',
+ * '_' or '-' separators, starting and ending with a letter or digit, with no
+ * two separators in a row. */
 int shim_v2_new(const char *container_id, const char *addr);
+int shim_v2_new_with_runtime(const char *container_id, const char *addr, const char *runtime);
+
+struct KeyValue {
+    const char *key;
+    const char *value;
+};
+
+/* Settings of shim_v2_new_ex, NULL strings are unset. When the namespace is
+ * set, every call on the connection sends it in the containerd-namespace and
+ * containerd-namespace-ttrpc metadata headers. Without it, as with shim_v2_new,
+ * no namespace headers are sent and the shim keeps the namespace it was
+ * started with. The metadata entries are sent with every call. Keys are
+ * lowercase letters, digits and '-', '_' or '.'; repeated keys send several
+ * values. */
+struct ConnectOptions {
+    const char *runtime;
+    const char *containerd_namespace;
+    const struct KeyValue *metadata;
+    size_t metadata_len;
+};
+
+/* options may be NULL */
+int shim_v2_new_ex(const char *container_id, const char *addr, const struct ConnectOptions *options);
 int shim_v2_close(const char *container_id);
 
+int shim_v2_load_runtime_config(const char *path);
+
+/* Every shim call is a span: a child of the calling thread's traceparent
+ * when one is set, a new trace otherwise. The span's traceparent and the
+ * request id are sent in the traceparent and x-request-id metadata headers.
+ * Finished spans, with container id, exec id, RPC name, duration and
+ * outcome, go nowhere, to the log facade or are appended to path as one
+ * OTLP-JSON ExportTraceServiceRequest per line. */
+#define TRACE_EXPORT_OFF 0
+#define TRACE_EXPORT_LOG 1
+#define TRACE_EXPORT_FILE 2
+int shim_v2_set_trace_export(int mode, const char *path);
+
+/* Request id and W3C traceparent of the shim calls the calling thread makes
+ * from now on, either may be NULL; both NULL clears them. Fails with -1 on a
+ * malformed traceparent, keeping the previous ones. */
+int shim_v2_set_trace_context(const char *request_id, const char *traceparent);
+
+/* With the runtime's check_bundle setting, the bundle is checked with
+ * shim_v2_inspect_bundle and its root.path must exist before the shim is
+ * called. */
 int shim_v2_create(const char *container_id, const char *bundle, bool terminal,
                    const char *stdin, const char *stdout, const char *stderr, int *pid);
+/* options may be NULL, the runtime's configured options are sent then */
+int shim_v2_create_ex(const char *container_id, const char *bundle, bool terminal,
+                      const char *stdin, const char *stdout, const char *stderr,
+                      const struct CreateOptions *options, int *pid);
+/* Checks that bundle is a directory with a valid config.json that names a
+ * root.path, which need not exist, and reports what the config describes.
+ * The info is released with shim_v2_inspect_bundle_free. */
+int shim_v2_inspect_bundle(const char *bundle, struct BundleInfo *info);
+void shim_v2_inspect_bundle_free(struct BundleInfo *info);
 int shim_v2_start(const char *container_id, const char *exec_id, int *pid);
 int shim_v2_kill(const char *container_id, const char *exec_id, unsigned int signal, bool all);
 int shim_v2_delete(const char *container_id, const char *exec_id, const struct DeleteResponse *resp);
 int shim_v2_shutdown(const char *container_id);
+/* Runs the delete subcommand of the runtime's shim binary for a shim that
+ * cannot be reached anymore. containerd_namespace is passed as -namespace,
+ * when NULL the namespace of the container's connection is used, or
+ * "default" when it has none. */
+int shim_v2_cleanup_dead_shim(const char *runtime, const char *containerd_namespace,
+                              const char *container_id, const char *bundle,
+                              struct CleanupResponse *resp);
 
+/* spec is the runtime-spec process JSON, it is checked before it is sent to
+ * the shim, e.g. for empty args, a relative cwd or unknown capabilities. */
 int shim_v2_exec(const char *container_id, const char *exec_id, bool terminal,
                  const char *stdin, const char *stdout, const char *stderr, const char *spec);
+/* Exec with the process spec built from spec instead of JSON, spec->terminal
+ * selects whether the process gets a pty. */
+int shim_v2_exec_ex(const char *container_id, const char *exec_id, const char *stdin,
+                    const char *stdout, const char *stderr, const struct ExecSpec *spec);
+/* The runtime-spec JSON of spec, released with shim_v2_exec_spec_json_free. */
+int shim_v2_exec_spec_json(const struct ExecSpec *spec, char **out);
+void shim_v2_exec_spec_json_free(char *data);
 int shim_v2_resize_pty(const char *container_id, const char *exec_id, unsigned int height, unsigned int width);
 
 int shim_v2_pause(const char *container_id);
@@ -86,8 +536,101 @@ int shim_v2_resume(const char *container_id);
 
 int shim_v2_state(const char *container_id, const struct State *state);
 int shim_v2_stats(const char *container_id, const struct Stats *stats);
+/* Like shim_v2_stats, but reads the container's cgroup from the host when the
+ * shim does not answer within 2 seconds. The cgroup is found from the bundle's config.json or
+ * init.pid, *fallback is set when the stats came from the cgroupfs. */
+int shim_v2_stats_with_fallback(const char *container_id, const char *bundle,
+                                struct Stats *stats, bool *fallback);
+/* Stats of n containers queried parallelism at a time with a shared deadline,
+ * 0 selects 16 and 10000 ms. results[i] is filled and errors[i] set to 0 for
+ * each container that answered in time, errors[i] is -1 for the others.
+ * Every call gives up at the deadline, no thread outlives the batch for
+ * long. Returns 0 if all of them answered. */
+int shim_v2_stats_batch(const char **container_ids, size_t n, unsigned int parallelism,
+                        unsigned int deadline_ms, struct Stats *results, int *errors);
+/* *stats is overwritten without being released, on failure it is zeroed, so
+ * shim_v2_stats_v2_free may be called either way. */
+int shim_v2_stats_v2(const char *container_id, struct StatsV2 *stats);
+void shim_v2_stats_v2_free(struct StatsV2 *stats);
+#define SHIM_V2_STATS_SAMPLE_OK 0
+#define SHIM_V2_STATS_SAMPLE_ERROR (-1)
+/* last call of a subscription, user_data may be released */
+#define SHIM_V2_STATS_SAMPLE_END 1
+
+/* Called on the sampler thread. stats is only valid during the call and NULL
+ * unless status is SHIM_V2_STATS_SAMPLE_OK. */
+typedef void (*shim_v2_stats_callback)(const char *container_id, const struct StatsV2 *stats,
+                                       int status, void *user_data);
+
+/* Samples the stats every interval_ms until shim_v2_stats_unsubscribe or
+ * shim_v2_close is called, or the shim stops answering. A sample the shim
+ * does not answer within interval_ms fails, shim_v2_close ends one in
+ * flight at once. */
+int shim_v2_stats_subscribe(const char *container_id, unsigned int interval_ms,
+                            shim_v2_stats_callback callback, void *user_data);
+int shim_v2_stats_unsubscribe(const char *container_id);
+/* Every caller of shim_v2_stats_derived on a connection shares its previous
+ * sample, callers that need rates of their own interval keep a sample and
+ * call shim_v2_stats_derived_from, which replaces *sample on success. */
+int shim_v2_stats_derived(const char *container_id, struct DerivedStats *stats);
+int shim_v2_stats_derived_from(const char *container_id, struct StatsSample *sample,
+                               struct DerivedStats *stats);
+/* On success *out holds the stats as a JSON document, release it with
+ * shim_v2_stats_json_free:
+ * {"schema_version": 1, "cgroup_version": 1 or 2,
+ *  "type_url": "io.containerd.cgroups.v1.Metrics" or "io.containerd.cgroups.v2.Metrics",
+ *  "metrics": {...}}
+ * metrics is keyed by the proto field names of type_url, unset messages are
+ * null. Integers are numbers, except unsigned ones above INT64_MAX, such as
+ * the UINT64_MAX of an unlimited cgroup, which are decimal strings. New
+ * metrics are added as new keys. */
+int shim_v2_stats_json(const char *container_id, char **out);
+void shim_v2_stats_json_free(char *data);
+/* Fails when the runtime's pressure setting leaves no source with PSI. */
+int shim_v2_pressure(const char *container_id, struct Pressure *pressure);
+int shim_v2_cache_stats(const char *container_id, struct CacheStats *stats);
 int shim_v2_pids(const char *container_id, int *pid);
 
+/* Process table of shim_v2_top, cells holds n_processes rows of n_titles
+ * strings. */
+struct ProcessTable {
+    char **titles;
+    size_t n_titles;
+    char **cells;
+    size_t n_processes;
+};
+
+/* ps style process listing. columns is a comma separated list of ps format
+ * specifiers (pid, ppid, uid, user, s, stime, time, rss, comm, cmd, exec_id),
+ * NULL or "" for the ps -ef columns. Details are read from the host's /proc,
+ * cells of processes it cannot see are "-". */
+int shim_v2_top(const char *container_id, const char *columns, struct ProcessTable *table);
+void shim_v2_top_free(struct ProcessTable *table);
+
 int shim_v2_wait(const char *container_id, const char *exec_id, int *exit_status);
 
+#define SHIM_V2_WAIT_EXITED 0
+#define SHIM_V2_WAIT_ERROR (-1)
+#define SHIM_V2_WAIT_CANCELLED 1
+
+/* Called once per wait, on a wait worker thread, or on the thread calling
+ * shim_v2_wait_cancel or shim_v2_close when the wait is cancelled.
+ * exit_status and exited_at, in nanoseconds since the epoch, are 0 unless
+ * status is SHIM_V2_WAIT_EXITED. The strings are only valid during the call. */
+typedef void (*shim_v2_wait_callback)(const char *container_id, const char *exec_id, int status,
+                                      unsigned int exit_status, int64_t exited_at, void *user_data);
+
+/* Like shim_v2_wait, but returns at once and calls callback when the process
+ * exits. The waits run on a pool of worker threads inside the library, one
+ * thread and one connection to the shim per wait, at most 256 of them; a
+ * wait beyond that fails with -1, use shim_v2_wait then. Returns an id for
+ * shim_v2_wait_cancel, which is > 0, or -1. shim_v2_close cancels the
+ * pending waits of the container. */
+long long shim_v2_wait_async(const char *container_id, const char *exec_id,
+                             shim_v2_wait_callback callback, void *user_data);
+/* Cancels a pending wait: its callback runs with SHIM_V2_WAIT_CANCELLED
+ * before this returns 0, and its connection is closed, which frees its
+ * thread. Returns -1 when the wait has completed already. */
+int shim_v2_wait_cancel(long long wait_id);
+
 #endif /* LIB_SHIM_V2_H */
@@ -0,0 +1,185 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::client::get_conn;
+use super::error::{Error, Result};
+use super::stats::CgroupMetrics;
+use super::trace;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shims queried at the same time by a batch of `shim_v2_stats_batch` that
+/// sets none.
+pub const BATCH_PARALLELISM: usize = 16;
+/// How long such a batch may take, containers not done by then fail.
+pub const BATCH_DEADLINE: Duration = Duration::from_secs(10);
+
+/// Stats of many containers, queried over their stored connections with at
+/// most `parallelism` calls in flight. Results are in the order of `ids`.
+/// Every call times out at the deadline, so a stuck shim keeps its worker
+/// no longer than the batch.
+pub fn stats_batch(
+    ids: &[String],
+    parallelism: usize,
+    deadline: Duration,
+) -> Vec<Result<CgroupMetrics>> {
+    let ids: Arc<Vec<String>> = Arc::new(ids.to_vec());
+    let worker_ids = ids.clone();
+    // the workers' calls are spans of the caller's scope
+    let scope = trace::current();
+    run_batch(ids.len(), parallelism, deadline, move |i, deadline| {
+        let mut client = get_conn(&worker_ids[i])?.with_deadline(deadline);
+        if let Some(ref scope) = scope {
+            client = client.with_trace(scope.clone());
+        }
+        client.stats()
+    })
+}
+
+/// Runs `f` for the indexes `0..n` on up to `parallelism` threads. Indexes
+/// not finished at the deadline fail, workers still busy with a call are
+/// left behind and their results dropped, `f` gets the deadline to return
+/// by.
+fn run_batch<T, F>(n: usize, parallelism: usize, deadline: Duration, f: F) -> Vec<Result<T>>
+where
+    T: Send + 'static,
+    F: Fn(usize, Instant) -> Result<T> + Send + Sync + 'static,
+{
+    let deadline = Instant::now() + deadline;
+    let next = Arc::new(AtomicUsize::new(0));
+    let f = Arc::new(f);
+    let (tx, rx) = mpsc::channel();
+
+    for _ in 0..parallelism.max(1).min(n) {
+        let next = next.clone();
+        let f = f.clone();
+        let tx = tx.clone();
+        thread::spawn(move || loop {
+            let i = next.fetch_add(1, Ordering::SeqCst);
+            if i >= n || Instant::now() >= deadline {
+                break;
+            }
+            if tx.send((i, f(i, deadline))).is_err() {
+                break;
+            }
+        });
+    }
+    drop(tx);
+
+    let mut results: Vec<Option<Result<T>>> = (0..n).map(|_| None).collect();
+    let mut done = 0;
+    while done < n {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        match rx.recv_timeout(remaining) {
+            Ok((i, result)) => {
+                results[i] = Some(result);
+                done += 1;
+            }
+            Err(_) => break,
+        }
+    }
+    results
+        .into_iter()
+        .map(|r| r.unwrap_or_else(|| Err(other!("deadline exceeded"))))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::del_conn;
+    use crate::client::runtime::RuntimeHandler;
+    use crate::client::testutil::StuckShim;
+
+    #[test]
+    fn test_run_batch() {
+        let results = run_batch(10, 3, Duration::from_secs(5), |i, _| {
+            if i % 4 == 3 {
+                Err(other!(format!("container {} failed", i)))
+            } else {
+                Ok(i * 2)
+            }
+        });
+        assert_eq!(results.len(), 10);
+        for (i, r) in results.iter().enumerate() {
+            match r {
+                Ok(v) => assert_eq!(*v, i * 2),
+                Err(e) => {
+                    assert_eq!(i % 4, 3);
+                    assert!(e.to_string().contains(&format!("container {}", i)));
+                }
+            }
+        }
+        assert!(run_batch(0, 3, Duration::from_secs(1), |i, _| Ok(i)).is_empty());
+    }
+
+    #[test]
+    fn test_run_batch_parallelism() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let (r, p) = (running.clone(), peak.clone());
+        let results = run_batch(12, 4, Duration::from_secs(5), move |i, _| {
+            let now = r.fetch_add(1, Ordering::SeqCst) + 1;
+            p.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(20));
+            r.fetch_sub(1, Ordering::SeqCst);
+            Ok(i)
+        });
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert!(peak.load(Ordering::SeqCst) <= 4);
+        assert!(peak.load(Ordering::SeqCst) > 1);
+    }
+
+    #[test]
+    fn test_run_batch_deadline() {
+        let start = Instant::now();
+        let results = run_batch(4, 4, Duration::from_millis(100), |i, _| {
+            if i == 2 {
+                thread::sleep(Duration::from_secs(2));
+            }
+            Ok(i)
+        });
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(results[0].is_ok() && results[1].is_ok() && results[3].is_ok());
+        assert!(results[2].as_ref().unwrap_err().to_string().contains("deadline"));
+    }
+
+    #[test]
+    fn test_stats_batch_without_connection() {
+        let ids = vec!["batch-1".to_string(), "batch-2".to_string()];
+        let results = stats_batch(&ids, BATCH_PARALLELISM, BATCH_DEADLINE);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|r| r.is_err()));
+    }
+
+    #[test]
+    fn test_stats_batch_stuck_shim() {
+        let shim = StuckShim::new("batch-stuck");
+        shim.connect("batch-stuck", RuntimeHandler::default());
+        let returned = Arc::new(AtomicUsize::new(0));
+        let r = returned.clone();
+        let start = Instant::now();
+        let results = run_batch(1, 1, Duration::from_millis(200), move |_, deadline| {
+            let result = get_conn("batch-stuck")?.with_deadline(deadline).stats();
+            r.fetch_add(1, Ordering::SeqCst);
+            result
+        });
+        assert!(results[0].is_err());
+        // the worker's call gave up at the deadline as well
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(returned.load(Ordering::SeqCst), 1);
+        assert!(start.elapsed() < Duration::from_secs(2));
+        del_conn("batch-stuck");
+    }
+}
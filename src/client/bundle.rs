@@ -0,0 +1,160 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Checks of an OCI bundle before it is handed to the shim, so a broken
+//! bundle fails here instead of inside the shim or a VM.
+
+use super::error::{Error, Result};
+use super::spec::Spec;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE: &str = "config.json";
+
+// CRI annotations, containerd's first, then CRI-O's
+const SANDBOX_ID_ANNOTATIONS: [&str; 2] = ["io.kubernetes.cri.sandbox-id", "io.kubernetes.cri-o.SandboxID"];
+const CONTAINER_TYPE_ANNOTATIONS: [&str; 2] = [
+    "io.kubernetes.cri.container-type",
+    "io.kubernetes.cri-o.ContainerType",
+];
+const RUNTIME_HANDLER_ANNOTATIONS: [&str; 2] = [
+    "io.kubernetes.cri.runtime-handler",
+    "io.kubernetes.cri-o.RuntimeHandler",
+];
+
+/// What a bundle describes, the annotation values are empty when unset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BundleInfo {
+    pub spec: Spec,
+    /// `root.path` resolved against the bundle.
+    pub rootfs: PathBuf,
+    pub sandbox_id: String,
+    /// `sandbox` or `container`
+    pub container_type: String,
+    pub runtime_handler: String,
+}
+
+/// The bundle's config.json.
+pub fn read_spec(bundle: &Path) -> Result<Spec> {
+    let config = bundle.join(CONFIG_FILE);
+    let data = std::fs::read(&config).map_err(|e| {
+        Error::InvalidArgument(format!("failed to read {}: {}", config.to_string_lossy(), e))
+    })?;
+    Spec::from_json(&data)
+}
+
+fn annotation(spec: &Spec, keys: &[&str]) -> String {
+    keys.iter()
+        .find_map(|key| spec.annotations.get(*key))
+        .cloned()
+        .unwrap_or_default()
+}
+
+/// Checks that the bundle is a directory with a parsable config.json that
+/// names a root. The root itself is not looked at, see
+/// `BundleInfo::check_rootfs`.
+pub fn inspect(bundle: &str) -> Result<BundleInfo> {
+    if bundle.is_empty() {
+        return Err(Error::InvalidArgument("bundle must not be empty".to_string()));
+    }
+    let dir = Path::new(bundle);
+    if !dir.is_dir() {
+        return Err(Error::InvalidArgument(format!(
+            "bundle {} is not a directory",
+            bundle
+        )));
+    }
+    let spec = read_spec(dir)?;
+    let root = spec
+        .root
+        .as_ref()
+        .map(|root| root.path.as_str())
+        .filter(|path| !path.is_empty())
+        .ok_or_else(|| Error::InvalidArgument(format!("{} has no root.path", CONFIG_FILE)))?;
+    Ok(BundleInfo {
+        rootfs: dir.join(root),
+        sandbox_id: annotation(&spec, &SANDBOX_ID_ANNOTATIONS),
+        container_type: annotation(&spec, &CONTAINER_TYPE_ANNOTATIONS),
+        runtime_handler: annotation(&spec, &RUNTIME_HANDLER_ANNOTATIONS),
+        spec,
+    })
+}
+
+impl BundleInfo {
+    /// Checks that the root is a directory on the host. Only runtimes whose
+    /// shim uses the host's rootfs can rely on that, a VM runtime may mount
+    /// it later or not at all.
+    pub fn check_rootfs(&self) -> Result<()> {
+        if !self.rootfs.is_dir() {
+            return Err(Error::InvalidArgument(format!(
+                "root.path {} is not a directory",
+                self.rootfs.to_string_lossy()
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    #[test]
+    fn test_inspect() {
+        let bundle = TempDir::new("bundle");
+        std::fs::create_dir(bundle.join("rootfs")).unwrap();
+        let path = bundle.path().to_str().unwrap();
+        let write_config = |config: &str| {
+            bundle.write(CONFIG_FILE, config);
+        };
+
+        let err = |bundle: &str| match inspect(bundle) {
+            Err(Error::InvalidArgument(msg)) => msg,
+            other => panic!("got {:?}", other),
+        };
+        assert!(err("").contains("must not be empty"));
+        assert!(err("/nonexistent-bundle").contains("not a directory"));
+        assert!(err(path).contains("failed to read"));
+        write_config("{");
+        assert!(err(path).contains("bad config.json"));
+        write_config(r#"{"ociVersion": "1.0.2"}"#);
+        assert!(err(path).contains("no root.path"));
+        write_config(r#"{"root": {"path": "missing"}}"#);
+        let info = inspect(path).unwrap();
+        assert_eq!(info.rootfs, bundle.join("missing"));
+        match info.check_rootfs() {
+            Err(Error::InvalidArgument(msg)) => assert!(msg.contains("missing is not a directory")),
+            other => panic!("got {:?}", other),
+        }
+
+        write_config(
+            r#"{"ociVersion": "1.0.2", "root": {"path": "rootfs"},
+                "annotations": {"io.kubernetes.cri.container-type": "container",
+                                "io.kubernetes.cri.sandbox-id": "sandbox1",
+                                "io.kubernetes.cri-o.SandboxID": "ignored",
+                                "io.kubernetes.cri-o.RuntimeHandler": "kata"}}"#,
+        );
+        let info = inspect(path).unwrap();
+        assert_eq!(info.rootfs, bundle.join("rootfs"));
+        info.check_rootfs().unwrap();
+        assert_eq!(info.sandbox_id, "sandbox1");
+        assert_eq!(info.container_type, "container");
+        assert_eq!(info.runtime_handler, "kata");
+        assert_eq!(info.spec.oci_version, "1.0.2");
+
+        let abs_root = bundle.join("rootfs").to_string_lossy().into_owned();
+        write_config(&format!(r#"{{"root": {{"path": "{}"}}}}"#, abs_root));
+        let info = inspect(path).unwrap();
+        assert_eq!(info.rootfs, bundle.join("rootfs"));
+        assert!(info.sandbox_id.is_empty());
+    }
+}
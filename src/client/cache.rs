@@ -0,0 +1,303 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How often a cached call was answered without a call of its own.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheCounters {
+    /// Served from a result younger than the TTL.
+    pub hits: u64,
+    /// Waited for a call another thread had in flight.
+    pub coalesced: u64,
+    /// Calls actually made.
+    pub calls: u64,
+}
+
+struct Slot<T> {
+    cached: Option<(Instant, T)>,
+    // epoch of the call in flight, callers only join a call of the current
+    // epoch
+    in_flight: Option<u64>,
+    // bumped by invalidate, results of calls started before are not cached
+    epoch: u64,
+    // bumped whenever the in-flight call finishes
+    generation: u64,
+    last: Option<Result<T>>,
+}
+
+/// Single-flight cache of one call: concurrent callers share the call in
+/// flight, and successful results are reused for `ttl`, 0 disables reuse.
+pub struct CallCache<T> {
+    ttl: Duration,
+    slot: Mutex<Slot<T>>,
+    done: Condvar,
+    hits: AtomicU64,
+    coalesced: AtomicU64,
+    calls: AtomicU64,
+}
+
+// finishes the call when dropped, so callers waiting for it wake up even
+// when it panics
+struct Flight<'a, T: Clone> {
+    cache: &'a CallCache<T>,
+    epoch: u64,
+    result: Option<Result<T>>,
+}
+
+impl<T: Clone> Drop for Flight<'_, T> {
+    fn drop(&mut self) {
+        let result = self.result.take().unwrap_or_else(|| Err(other!("call panicked")));
+        self.cache.finish(self.epoch, result);
+    }
+}
+
+impl<T: Clone> CallCache<T> {
+    pub fn new(ttl: Duration) -> Self {
+        CallCache {
+            ttl,
+            slot: Mutex::new(Slot {
+                cached: None,
+                in_flight: None,
+                epoch: 0,
+                generation: 0,
+                last: None,
+            }),
+            done: Condvar::new(),
+            hits: AtomicU64::new(0),
+            coalesced: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+        }
+    }
+
+    /// A caller that joins a call in flight gives up waiting for it at
+    /// `deadline`, the call is left to its own.
+    pub fn get<F: FnOnce() -> Result<T>>(&self, deadline: Option<Instant>, call: F) -> Result<T> {
+        let mut slot = self.slot.lock().unwrap();
+        if let Some((at, value)) = slot.cached.as_ref() {
+            if at.elapsed() < self.ttl {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Ok(value.clone());
+            }
+        }
+        if slot.in_flight == Some(slot.epoch) {
+            self.coalesced.fetch_add(1, Ordering::Relaxed);
+            let generation = slot.generation;
+            let slot = match deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    let (slot, wait) = self
+                        .done
+                        .wait_timeout_while(slot, timeout, |s| s.generation == generation)
+                        .unwrap();
+                    if wait.timed_out() {
+                        return Err(other!("deadline exceeded waiting for the call in flight"));
+                    }
+                    slot
+                }
+                None => self
+                    .done
+                    .wait_while(slot, |s| s.generation == generation)
+                    .unwrap(),
+            };
+            return slot.last.clone().unwrap();
+        }
+        // a call of an older epoch may still be in flight, this one takes
+        // over its waiters
+        let epoch = slot.epoch;
+        slot.in_flight = Some(epoch);
+        drop(slot);
+
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        let mut flight = Flight {
+            cache: self,
+            epoch,
+            result: None,
+        };
+        let result = call();
+        flight.result = Some(result.clone());
+        result
+    }
+
+    fn finish(&self, epoch: u64, result: Result<T>) {
+        let mut slot = self.slot.lock().unwrap();
+        if let Ok(value) = result.as_ref() {
+            if !self.ttl.is_zero() && slot.epoch == epoch {
+                slot.cached = Some((Instant::now(), value.clone()));
+            }
+        }
+        if slot.in_flight == Some(epoch) {
+            slot.in_flight = None;
+            slot.generation += 1;
+            slot.last = Some(result);
+            self.done.notify_all();
+        }
+    }
+
+    /// Drops the cached result, e.g. after a call that changed it. Calls in
+    /// flight already are not cached.
+    pub fn invalidate(&self) {
+        let mut slot = self.slot.lock().unwrap();
+        slot.cached = None;
+        slot.epoch += 1;
+    }
+
+    pub fn counters(&self) -> CacheCounters {
+        CacheCounters {
+            hits: self.hits.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            calls: self.calls.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_cache_ttl() {
+        let cache = CallCache::new(Duration::from_millis(100));
+        assert_eq!(cache.get(None, || Ok(1)).unwrap(), 1);
+        assert_eq!(cache.get(None, || Ok(2)).unwrap(), 1);
+        thread::sleep(Duration::from_millis(150));
+        assert_eq!(cache.get(None, || Ok(3)).unwrap(), 3);
+        cache.invalidate();
+        assert_eq!(cache.get(None, || Ok(4)).unwrap(), 4);
+        assert_eq!(
+            cache.counters(),
+            CacheCounters {
+                hits: 1,
+                coalesced: 0,
+                calls: 3
+            }
+        );
+    }
+
+    #[test]
+    fn test_cache_errors_not_cached() {
+        let cache = CallCache::new(Duration::from_secs(10));
+        assert!(cache.get(None, || Err(Error::ShimError("down".to_string()))).is_err());
+        assert_eq!(cache.get(None, || Ok(1)).unwrap(), 1);
+
+        let uncached = CallCache::new(Duration::from_secs(0));
+        assert_eq!(uncached.get(None, || Ok(1)).unwrap(), 1);
+        assert_eq!(uncached.get(None, || Ok(2)).unwrap(), 2);
+        assert_eq!(uncached.counters().hits, 0);
+    }
+
+    #[test]
+    fn test_cache_coalesces() {
+        let cache = Arc::new(CallCache::new(Duration::from_secs(0)));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    cache.get(None, || {
+                        thread::sleep(Duration::from_millis(200));
+                        Err::<u32, _>(Error::ShimError("slow shim".to_string()))
+                    })
+                })
+            })
+            .collect();
+        for t in threads {
+            let err = t.join().unwrap().unwrap_err();
+            assert!(err.to_string().contains("slow shim"));
+        }
+        let counters = cache.counters();
+        assert_eq!(counters.calls + counters.coalesced, 8);
+        assert!(counters.calls < 8);
+    }
+
+    #[test]
+    fn test_cache_invalidate_in_flight() {
+        let cache = Arc::new(CallCache::new(Duration::from_secs(10)));
+        let stale = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(None, || {
+                    thread::sleep(Duration::from_millis(200));
+                    Ok(1)
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        cache.invalidate();
+        // does not join the call started before the invalidation
+        assert_eq!(cache.get(None, || Ok(2)).unwrap(), 2);
+        assert_eq!(stale.join().unwrap().unwrap(), 1);
+        assert_eq!(cache.get(None, || Ok(3)).unwrap(), 2);
+
+        cache.invalidate();
+        let stale = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(None, || {
+                    thread::sleep(Duration::from_millis(100));
+                    Ok(4)
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        cache.invalidate();
+        stale.join().unwrap().unwrap();
+        // the result of the older epoch was not cached
+        assert_eq!(cache.get(None, || Ok(5)).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_cache_waiter_deadline() {
+        let cache = Arc::new(CallCache::new(Duration::from_secs(10)));
+        let slow = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(None, || {
+                    thread::sleep(Duration::from_millis(500));
+                    Ok(1)
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        let start = Instant::now();
+        let deadline = Some(start + Duration::from_millis(100));
+        assert!(matches!(cache.get(deadline, || Ok(2)), Err(Error::Other(_))));
+        assert!(start.elapsed() < Duration::from_millis(400));
+        assert_eq!(cache.counters().coalesced, 1);
+        // the call in flight finishes and is cached as usual
+        assert_eq!(slow.join().unwrap().unwrap(), 1);
+        assert_eq!(cache.get(deadline, || Ok(3)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_cache_call_panics() {
+        let cache = Arc::new(CallCache::new(Duration::from_secs(10)));
+        let panicking = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.get(None, || -> Result<u32> {
+                    thread::sleep(Duration::from_millis(100));
+                    panic!("shim call panicked")
+                })
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        // the waiter gets an error instead of waiting forever
+        assert!(matches!(cache.get(None, || Ok(1)), Err(Error::Other(_))));
+        assert!(panicking.join().is_err());
+        assert_eq!(cache.get(None, || Ok(2)).unwrap(), 2);
+    }
+}
@@ -0,0 +1,67 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+pub const PROC_ROOT: &str = "/proc";
+lazy_static! {
+    static ref USER_HZ: u64 = match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        hz if hz > 0 => hz as u64,
+        // the value of every Linux ABI
+        _ => 100,
+    };
+}
+
+/// Ticks per second of the clock tick counters in procfs and cgroupfs.
+pub fn user_hz() -> u64 {
+    *USER_HZ
+}
+
+/// Directory of a process' cgroup v2 group under `cgroup_root`, taken from the
+/// `0::<path>` line of `<proc_root>/<pid>/cgroup`.
+pub fn unified_cgroup_dir(proc_root: &Path, cgroup_root: &Path, pid: u32) -> Result<PathBuf> {
+    let cgroup_file = proc_root.join(pid.to_string()).join("cgroup");
+    let data = std::fs::read_to_string(&cgroup_file)?;
+    data.lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .map(|path| cgroup_root.join(path.trim_start_matches('/')))
+        .ok_or_else(|| {
+            Error::Other(format!(
+                "no cgroup v2 entry in {}",
+                cgroup_file.to_string_lossy()
+            ))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    #[test]
+    fn test_unified_cgroup_dir() {
+        let dir = TempDir::new("proc");
+        dir.write("42/cgroup", "0::/kubepods/besteffort/pod1/abc\n");
+        dir.write("43/cgroup", "12:pids:/docker/abc\n1:name=systemd:/docker/abc\n");
+        let proc_root = dir.path();
+
+        assert_eq!(
+            unified_cgroup_dir(proc_root, Path::new(CGROUP_ROOT), 42).unwrap(),
+            Path::new("/sys/fs/cgroup/kubepods/besteffort/pod1/abc")
+        );
+        assert!(unified_cgroup_dir(proc_root, Path::new(CGROUP_ROOT), 43).is_err());
+        assert!(unified_cgroup_dir(proc_root, Path::new(CGROUP_ROOT), 44).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::client::{get_conn, DeleteResponse};
+use super::error::{Error, Result};
+use super::identifiers;
+use super::metadata::DEFAULT_NAMESPACE;
+use super::runtime::get_runtime;
+use crate::protocols;
+use protobuf::Message;
+use std::process::{Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+/// How long a `delete` invocation of the shim binary may run before it is
+/// killed, unless the runtime configures a `cleanup` timeout.
+pub const CLEANUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Runs `<shim> -namespace <namespace> -id <id> -bundle <bundle> delete` for a
+/// shim that is no longer reachable and decodes the `DeleteResponse` it
+/// writes to stdout. Shims refuse to run without a namespace, an empty one
+/// is the namespace of the container's connection, or containerd's default
+/// one when the connection sends none.
+pub fn cleanup_dead_shim(
+    runtime: &str,
+    namespace: &str,
+    container_id: &str,
+    bundle: &str,
+) -> Result<DeleteResponse> {
+    let namespace = if namespace.is_empty() {
+        get_conn(container_id)
+            .ok()
+            .and_then(|store| store.namespace().map(str::to_string))
+            .unwrap_or_else(|| DEFAULT_NAMESPACE.to_string())
+    } else {
+        identifiers::validate_namespace(namespace)?;
+        namespace.to_string()
+    };
+    let handler = get_runtime(runtime)?;
+    let timeout = handler.timeout("cleanup").unwrap_or(CLEANUP_TIMEOUT);
+    run_shim_delete(&handler.binary()?, &namespace, container_id, bundle, timeout)
+}
+
+fn run_shim_delete(
+    binary: &str,
+    namespace: &str,
+    container_id: &str,
+    bundle: &str,
+    timeout: Duration,
+) -> Result<DeleteResponse> {
+    let child = Command::new(binary)
+        .args(["-namespace", namespace, "-id", container_id, "-bundle", bundle, "delete"])
+        .current_dir(bundle)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(other_error!(e, format!("failed to start {}:", binary)))?;
+    let child_pid = child.id() as libc::pid_t;
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+    let output = match rx.recv_timeout(timeout) {
+        Ok(output) => output?,
+        Err(_) => {
+            unsafe {
+                libc::kill(child_pid, libc::SIGKILL);
+            }
+            return Err(other!(format!(
+                "{} delete timed out after {:?}",
+                binary, timeout
+            )));
+        }
+    };
+
+    if !output.status.success() {
+        return Err(Error::ShimError(format!(
+            "{} delete failed with {}: {}",
+            binary,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    let resp = protocols::shim::DeleteResponse::parse_from_bytes(&output.stdout)
+        .map_err(other_error!(e, "failed to decode delete response:"))?;
+    Ok(DeleteResponse::from(resp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    // the shim is valid as long as the directory is
+    fn fake_shim(name: &str, script: &str) -> (TempDir, String) {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = TempDir::new(name);
+        let path = dir.write(name, format!("#!/bin/sh\n{}\n", script));
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let path = path.to_string_lossy().to_string();
+        (dir, path)
+    }
+
+    #[test]
+    fn test_cleanup_dead_shim() {
+        // pid 42, exit_status 137, exited_at 1s
+        let (_dir, shim) = fake_shim(
+            "shim-ok",
+            r"[ $1 = -namespace ] && [ $2 = default ] && [ $7 = delete ] &&
+              printf '\010\052\020\211\001\032\002\010\001'",
+        );
+        let resp = cleanup_dead_shim(&shim, "", "12345", "/").unwrap();
+        assert_eq!(resp.pid, 42);
+        assert_eq!(resp.exit_status, 137);
+        assert_eq!(resp.exited_at, 1_000_000_000);
+
+        let (_dir, shim) = fake_shim(
+            "shim-ns",
+            r"[ $2 = k8s.io ] && printf '\010\052'",
+        );
+        assert_eq!(cleanup_dead_shim(&shim, "k8s.io", "12345", "/").unwrap().pid, 42);
+        assert!(matches!(
+            cleanup_dead_shim(&shim, "k8s/io", "12345", "/"),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_cleanup_dead_shim_failed() {
+        let (_dir, shim) = fake_shim("shim-fail", "echo boom >&2; exit 1");
+        let err = cleanup_dead_shim(&shim, "", "12345", "/").unwrap_err();
+        assert!(err.to_string().contains("boom"));
+        assert!(cleanup_dead_shim("io.containerd.nonexist.v2", "", "12345", "/").is_err());
+    }
+
+    #[test]
+    fn test_cleanup_dead_shim_timeout() {
+        let (_dir, shim) = fake_shim("shim-hang", "exec sleep 5");
+        assert!(run_shim_delete(&shim, "default", "12345", "/", Duration::from_millis(100)).is_err());
+    }
+}
@@ -10,36 +10,109 @@
 // NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
 // See the Mulan PSL v2 for more details.
 
+use super::cgroup::{unified_cgroup_dir, CGROUP_ROOT, PROC_ROOT};
+use super::bundle::inspect;
+use super::cache::{CacheCounters, CallCache};
+use super::derived::{monotonic_now, DerivedStats, Sample};
 use super::error::{Error, Result};
-use crate::protocols::metrics::Metrics;
+use super::identifiers;
+use super::metadata::Metadata;
+use super::options::{runtime_options, CreateOptions};
+use super::psi::Pressure;
+use super::network::task_network;
+use super::runtime::{get_runtime, NetworkStatsSource, PressureSource, RuntimeHandler};
+use super::spec::Process;
+use super::stats::CgroupMetrics;
+use super::subscribe::unsubscribe_stats;
+use super::top::{columns, ProcessTable, Procfs};
+use super::trace::{self, Span, TraceScope};
+use super::wait::cancel_waits;
 use crate::protocols;
-use protobuf::{
-    CodedInputStream,Message,
-};
+use crate::protocols::typeurl;
 use lazy_static::lazy_static;
 use nix::sys::socket::*;
 use protocols::task::Status as shim_v2_status;
 use std::collections::HashMap;
-use std::os::unix::io::RawFd;
+use std::os::unix::io::{IntoRawFd, RawFd};
+use std::os::unix::net::UnixStream;
 use std::path::Path;
 use std::path::MAIN_SEPARATOR;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use ttrpc::Client;
 use ttrpc::context;
 
 #[derive(Clone)]
 pub struct Store {
     conn: Client,
+    // socket of conn, owned by it
+    fd: RawFd,
+    // where conn was made, see `reconnect`
+    addr: String,
     container_id: String,
+    runtime: RuntimeHandler,
+    // previous sample of stats_derived, shared by the clones of the store
+    last_sample: Arc<Mutex<Option<Sample>>>,
+    // calls made through this store give up at the deadline
+    deadline: Option<Instant>,
+    // sent with every call, namespace headers included
+    metadata: Metadata,
+    // parent of the calls' spans, the calling thread's when unset
+    trace: Option<TraceScope>,
+    stats_cache: Arc<CallCache<CgroupMetrics>>,
+    state_cache: Arc<CallCache<State>>,
+}
+
+/// Cache counters of a connection, see `CallCache`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub stats: CacheCounters,
+    pub state: CacheCounters,
 }
 
 #[derive(Debug)]
 pub struct DeleteResponse {
     pub exit_status: u32,
     pub pid: u32,
+    pub exited_at: i64,
 }
 
-#[derive(Debug)]
+impl From<protocols::shim::DeleteResponse> for DeleteResponse {
+    fn from(resp: protocols::shim::DeleteResponse) -> Self {
+        DeleteResponse {
+            exit_status: resp.exit_status,
+            pid: resp.pid,
+            exited_at: resp
+                .exited_at
+                .as_ref()
+                .map(|t| t.seconds * 1_000_000_000 + t.nanos as i64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Exit of a process as the shim's wait reports it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WaitResponse {
+    pub exit_status: u32,
+    /// Nanoseconds since the epoch.
+    pub exited_at: i64,
+}
+
+impl From<protocols::shim::WaitResponse> for WaitResponse {
+    fn from(resp: protocols::shim::WaitResponse) -> Self {
+        WaitResponse {
+            exit_status: resp.exit_status,
+            exited_at: resp
+                .exited_at
+                .as_ref()
+                .map(|t| t.seconds * 1_000_000_000 + t.nanos as i64)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum Status {
     UnknownStatus,
     CreatedStatus,
@@ -50,7 +123,7 @@ pub enum Status {
     PausingStatus,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct State {
     pub id: ::std::string::String,
     pub pid: u32,
@@ -62,13 +135,39 @@ pub struct State {
     pub exit_status: u32,
 }
 
+/// A process of the container as listed by the shim.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    /// Exec id of an exec'd process, empty for the init process or when the
+    /// shim does not report it.
+    pub exec_id: String,
+}
+
+impl ProcessInfo {
+    fn from_proto(in_obj: &protocols::task::ProcessInfo) -> Result<ProcessInfo> {
+        let mut info = ProcessInfo {
+            pid: in_obj.pid,
+            ..Default::default()
+        };
+        // Info is platform specific, only runc style details are understood.
+        if let Some(any) = in_obj.info.as_ref() {
+            if typeurl::is::<protocols::oci::ProcessDetails>(any) {
+                let details: protocols::oci::ProcessDetails = typeurl::unmarshal(any)?;
+                info.exec_id = details.exec_id;
+            }
+        }
+        Ok(info)
+    }
+}
+
 lazy_static! {
     static ref TTRPC_CLIENTS: Mutex<HashMap<String, Store>> = Mutex::new(HashMap::new());
 }
 
 fn unix_sock(r#abstract: bool, socket_path: &str) -> Result<SockAddr> {
     let sockaddr_u = if r#abstract {
-        let sockaddr_h = socket_path.to_owned() + &"\x00".to_string();
+        let sockaddr_h = socket_path.to_owned() + "\x00";
         UnixAddr::new_abstract(sockaddr_h.as_bytes())
     } else {
         UnixAddr::new(socket_path)
@@ -108,6 +207,10 @@ fn connect_to_vsock(address: &str) -> Result<RawFd> {
 }
 
 fn connect_to_unix_socket(abs: bool, address: &str) -> Result<RawFd> {
+    if !abs {
+        let stream = UnixStream::connect(address).map_err(other_error!(e, "failed to connect socket: "))?;
+        return Ok(stream.into_raw_fd());
+    }
     let fd = socket(
         AddressFamily::Unix,
         SockType::Stream,
@@ -121,14 +224,48 @@ fn connect_to_unix_socket(abs: bool, address: &str) -> Result<RawFd> {
     Ok(fd)
 }
 
-pub fn new_conn(container_id: &String, addr: &String) -> Result<()> {
-    let fd = if addr.starts_with("ttrpc+vsock://") {
+/// Settings of a connection made by `new_conn_with_options`.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectOptions {
+    /// Runtime handler whose timeouts the calls use, none when empty.
+    pub runtime: String,
+    /// Namespace and key/values sent with every call.
+    pub metadata: Metadata,
+}
+
+pub fn new_conn(container_id: &str, addr: &str) -> Result<()> {
+    establish_conn(container_id, addr, RuntimeHandler::default(), Metadata::default())
+}
+
+/// Like `new_conn`, but calls on the connection use the timeouts of the given
+/// runtime handler.
+pub fn new_conn_with_runtime(container_id: &str, addr: &str, runtime: &str) -> Result<()> {
+    establish_conn(container_id, addr, get_runtime(runtime)?, Metadata::default())
+}
+
+pub fn new_conn_with_options(container_id: &str, addr: &str, options: ConnectOptions) -> Result<()> {
+    let runtime = if options.runtime.is_empty() {
+        RuntimeHandler::default()
+    } else {
+        get_runtime(&options.runtime)?
+    };
+    establish_conn(container_id, addr, runtime, options.metadata)
+}
+
+fn establish_conn(container_id: &str, addr: &str, runtime: RuntimeHandler, metadata: Metadata) -> Result<()> {
+    identifiers::validate(container_id)?;
+    let fd = connect_to_addr(addr)?;
+    insert_conn(container_id, addr, fd, runtime, metadata)
+}
+
+fn connect_to_addr(addr: &str) -> Result<RawFd> {
+    if addr.starts_with("ttrpc+vsock://") {
         let address = addr.strip_prefix("ttrpc+vsock://").unwrap();
-        connect_to_vsock(address)?
+        connect_to_vsock(address)
     } else if addr.starts_with("ttrpc+unix://") {
         let address = addr.strip_prefix("ttrpc+unix://").unwrap();
         let path = Path::new(&MAIN_SEPARATOR.to_string()).join(address);
-        connect_to_unix_socket(!addr.starts_with("ttrpc+unix://"), &path.to_string_lossy())?
+        connect_to_unix_socket(!addr.starts_with("ttrpc+unix://"), &path.to_string_lossy())
     } else {
         let address = if addr.starts_with("unix://") {
             addr.strip_prefix("unix://").unwrap()
@@ -136,22 +273,40 @@ pub fn new_conn(container_id: &String, addr: &String) -> Result<()> {
             addr
         };
         let path = Path::new(&MAIN_SEPARATOR.to_string()).join(address);
-        connect_to_unix_socket(!addr.starts_with("unix://"), &path.to_string_lossy())?
-    };
+        connect_to_unix_socket(!addr.starts_with("unix://"), &path.to_string_lossy())
+    }
+}
 
+/// Stores a connection over `fd`, a socket connected to `addr` it then owns.
+pub(crate) fn insert_conn(
+    container_id: &str,
+    addr: &str,
+    fd: RawFd,
+    runtime: RuntimeHandler,
+    metadata: Metadata,
+) -> Result<()> {
     let client = ttrpc::Client::new(fd).map_err(|e| Error::Other(format!("failed to create ttrpc client: {:?}", e)))?;
     TTRPC_CLIENTS.lock().unwrap().insert(
-        container_id.clone(),
+        container_id.to_string(),
         Store {
             conn: client,
-            container_id: container_id.clone(),
+            fd,
+            addr: addr.to_string(),
+            container_id: container_id.to_string(),
+            last_sample: Arc::new(Mutex::new(None)),
+            deadline: None,
+            metadata,
+            trace: None,
+            stats_cache: Arc::new(CallCache::new(runtime.cache_ttl("stats"))),
+            state_cache: Arc::new(CallCache::new(runtime.cache_ttl("state"))),
+            runtime,
         },
     );
 
     Ok(())
 }
 
-pub fn get_conn(container_id: &String) -> Result<Store> {
+pub fn get_conn(container_id: &str) -> Result<Store> {
     if TTRPC_CLIENTS.lock().unwrap().contains_key(container_id) {
         Ok(TTRPC_CLIENTS
             .lock()
@@ -164,65 +319,186 @@ pub fn get_conn(container_id: &String) -> Result<Store> {
     }
 }
 
-pub fn del_conn(container_id: &String) {
-    TTRPC_CLIENTS.lock().unwrap().remove(container_id);
+pub fn del_conn(container_id: &str) {
+    let store = TTRPC_CLIENTS.lock().unwrap().remove(container_id);
+    let subscriptions = unsubscribe_stats(container_id);
+    // waits are ended by closing their own connections
+    cancel_waits(container_id);
+    // samplers in a stats call hold clones of the connection, they only
+    // return at once when the socket is gone
+    if subscriptions > 0 {
+        if let Some(store) = store {
+            store.disconnect();
+        }
+    }
 }
 
 struct ValidateTool {}
 
 impl ValidateTool {
-    fn str_empty(self, x: &String) -> Result<Self> {
-        return if x != "" {
+    fn str_empty(self, x: &str) -> Result<Self> {
+        if !x.is_empty() {
             Ok(self)
         } else {
             Err(other!("parameter must not be empty!"))
-        };
+        }
     }
 }
 
 impl Store {
+    fn context(&self, call: &str) -> context::Context {
+        let mut timeout = self.runtime.timeout(call);
+        if let Some(deadline) = self.deadline {
+            // a zero timeout would mean no timeout at all
+            let remaining = deadline
+                .saturating_duration_since(Instant::now())
+                .max(Duration::from_nanos(1));
+            timeout = Some(timeout.map_or(remaining, |t| t.min(remaining)));
+        }
+        context::Context {
+            metadata: self.metadata.to_context(),
+            timeout_nano: timeout.map(|t| t.as_nanos() as i64).unwrap_or(0),
+        }
+    }
+
+    // when a call of `call` made now times out, see `context`
+    fn call_deadline(&self, call: &str) -> Option<Instant> {
+        let timeout = self.runtime.timeout(call).map(|t| Instant::now() + t);
+        match (self.deadline, timeout) {
+            (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+            (deadline, timeout) => deadline.or(timeout),
+        }
+    }
+
+    /// Makes the call `rpc` as a span of the store's trace scope, see
+    /// `trace`.
+    fn call<T>(
+        &self,
+        rpc: &str,
+        exec_id: &str,
+        f: impl FnOnce(context::Context) -> ttrpc::Result<T>,
+    ) -> ttrpc::Result<T> {
+        let scope = self.trace.clone().or_else(trace::current);
+        let span = Span::start(
+            rpc,
+            &self.container_id,
+            exec_id,
+            self.metadata.namespace().unwrap_or_default(),
+            scope.as_ref(),
+        );
+        let mut ctx = self.context(rpc);
+        for (key, value) in span.headers() {
+            ctx.set(key.to_string(), vec![value]);
+        }
+        let result = f(ctx);
+        span.end(result.as_ref().err().map(|e| e.to_string()));
+        result
+    }
+
+    /// A store whose calls time out at `deadline` at the latest.
+    pub fn with_deadline(&self, deadline: Instant) -> Store {
+        Store {
+            deadline: Some(deadline),
+            ..self.clone()
+        }
+    }
+
+    /// A store whose calls also send `metadata`, its values replace those
+    /// of the connection for the same keys. The namespace stays the
+    /// connection's.
+    pub fn with_metadata(&self, metadata: &Metadata) -> Store {
+        Store {
+            metadata: self.metadata.merged(metadata),
+            ..self.clone()
+        }
+    }
+
+    /// A store whose calls are spans of `scope` instead of the calling
+    /// thread's.
+    pub fn with_trace(&self, scope: TraceScope) -> Store {
+        Store {
+            trace: Some(scope),
+            ..self.clone()
+        }
+    }
+
+    /// The namespace sent with the calls, see `Metadata`.
+    pub fn namespace(&self) -> Option<&str> {
+        self.metadata.namespace()
+    }
+
     pub fn create(
         &self,
-        bundle: &String,
+        bundle: &str,
+        terminal: bool,
+        stdin: &str,
+        stdout: &str,
+        stderr: &str,
+    ) -> Result<i32> {
+        self.create_with_options(
+            bundle,
+            terminal,
+            stdin,
+            stdout,
+            stderr,
+            CreateOptions::default(),
+        )
+    }
+
+    /// Creates the task with shim options and rootfs mounts, the options of the
+    /// connection's runtime handler are used when none are given. Runtimes
+    /// with `check_bundle` set have the bundle and its root checked first,
+    /// see `bundle::inspect`.
+    pub fn create_with_options(
+        &self,
+        bundle: &str,
         terminal: bool,
-        stdin: &String,
-        stdout: &String,
-        stderr: &String,
+        stdin: &str,
+        stdout: &str,
+        stderr: &str,
+        options: CreateOptions,
     ) -> Result<i32> {
         ValidateTool {}.str_empty(bundle)?;
+        if self.runtime.check_bundle {
+            inspect(bundle)?.check_rootfs()?;
+        }
 
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::CreateTaskRequest::new();
         req.id = self.container_id.clone();
-        req.bundle = bundle.clone();
+        req.bundle = bundle.to_string();
         req.terminal = terminal;
-        req.stdin = stdin.clone();
-        req.stdout = stdout.clone();
-        req.stderr = stderr.clone();
-
-        let ctx = context::with_timeout(0);
+        req.stdin = stdin.to_string();
+        req.stdout = stdout.to_string();
+        req.stderr = stderr.to_string();
+        req.rootfs = options.rootfs();
+        let shim_options = match options.options {
+            Some(shim_options) => Some(shim_options),
+            None => runtime_options(&self.runtime)?,
+        };
+        req.options = protobuf::MessageField::from_option(shim_options);
 
-        let resp = client
-            .create(ctx, &req)
+        let resp = self
+            .call("create", "", |ctx| client.create(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call create failed"))?;
+        self.state_cache.invalidate();
 
         Ok(resp.pid as i32)
     }
 
-    pub fn start(&self, exec_id: &String) -> Result<i32> {
+    pub fn start(&self, exec_id: &str) -> Result<i32> {
 
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::StartRequest::new();
         req.id = self.container_id.clone();
-        req.exec_id = exec_id.clone();
-
-        let ctx = context::with_timeout(0);
+        req.exec_id = exec_id.to_string();
 
-        let resp = client
-            .start(ctx, &req)
+        let resp = self
+            .call("start", exec_id, |ctx| client.start(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call start failed"))?;
+        self.state_cache.invalidate();
 
         Ok(resp.pid as i32)
     }
@@ -241,33 +517,28 @@ impl Store {
         req.signal = signal;
         req.all = all;
 
-        let ctx = context::with_timeout(0);
-
-        client
-            .kill(ctx, &req)
+        self
+            .call("kill", "", |ctx| client.kill(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call kill failed"))?;
+        self.state_cache.invalidate();
 
         Ok(())
     }
 
-    pub fn delete(&self, exec_id: &String) -> Result<DeleteResponse> {
+    pub fn delete(&self, exec_id: &str) -> Result<DeleteResponse> {
 
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::DeleteRequest::new();
         req.id = self.container_id.clone();
-        req.exec_id = exec_id.clone();
-
-        let ctx = context::with_timeout(0);
+        req.exec_id = exec_id.to_string();
 
-        let resp = client
-            .delete(ctx, &req)
+        let resp = self
+            .call("delete", exec_id, |ctx| client.delete(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call delete failed"))?;
+        self.state_cache.invalidate();
 
-        Ok(DeleteResponse {
-            exit_status: resp.exit_status,
-            pid: resp.pid,
-        })
+        Ok(DeleteResponse::from(resp))
     }
 
     pub fn shutdown(&self) -> Result<()> {
@@ -277,10 +548,8 @@ impl Store {
         let mut req = protocols::shim::ShutdownRequest::new();
         req.id = self.container_id.clone();
 
-        let ctx = context::with_timeout(0);
-
-        client
-            .shutdown(ctx, &req)
+        self
+            .call("shutdown", "", |ctx| client.shutdown(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call shutdown failed"))?;
 
         Ok(())
@@ -288,45 +557,55 @@ impl Store {
 
     pub fn exec(
         &self,
-        exec_id: &String,
+        exec_id: &str,
         terminal: bool,
-        stdin: &String,
-        stdout: &String,
-        stderr: &String,
+        stdin: &str,
+        stdout: &str,
+        stderr: &str,
         spec: &[u8],
     ) -> Result<()> {
         ValidateTool {}
             .str_empty(exec_id)?;
+        // the shim only reports a bad spec as a failed start
+        Process::from_json(spec)?.validate()?;
 
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::ExecProcessRequest::new();
         req.id = self.container_id.clone();
-        req.exec_id = exec_id.clone();
+        req.exec_id = exec_id.to_string();
         req.terminal = terminal;
-        req.stdin = stdin.clone();
-        req.stdout = stdout.clone();
-        req.stderr = stderr.clone();
-        let mut exec_spec: ::protobuf::well_known_types::any::Any =
-            ::protobuf::well_known_types::any::Any::new();
-        exec_spec.value = std::vec::Vec::from(spec);
-        exec_spec.type_url = String::from(
-            "types.containerd.io/opencontainers/runtime-spec/1/Process",
-        );
-        req.spec = protobuf::MessageField::some(exec_spec);
-
-        let ctx = context::with_timeout(0);
-
-        client
-            .exec(ctx, &req)
+        req.stdin = stdin.to_string();
+        req.stdout = stdout.to_string();
+        req.stderr = stderr.to_string();
+        req.spec = protobuf::MessageField::some(typeurl::marshal_json(
+            typeurl::PROCESS_SPEC_TYPE,
+            spec,
+        )?);
+
+        self
+            .call("exec", exec_id, |ctx| client.exec(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call exec failed"))?;
 
         Ok(())
     }
 
+    /// Exec with a typed process spec, `process.terminal` selects whether
+    /// the process gets a pty.
+    pub fn exec_process(
+        &self,
+        exec_id: &str,
+        stdin: &str,
+        stdout: &str,
+        stderr: &str,
+        process: &Process,
+    ) -> Result<()> {
+        self.exec(exec_id, process.terminal, stdin, stdout, stderr, &process.to_json()?)
+    }
+
     pub fn resize_pty(
         &self,
-        exec_id: &String,
+        exec_id: &str,
         height: u32,
         width: u32,
     ) -> Result<()> {
@@ -335,14 +614,12 @@ impl Store {
 
         let mut req = protocols::shim::ResizePtyRequest::new();
         req.id = self.container_id.clone();
-        req.exec_id = exec_id.clone();
+        req.exec_id = exec_id.to_string();
         req.height = height;
         req.width = width;
 
-        let ctx = context::with_timeout(0);
-
-        client
-            .resize_pty(ctx, &req)
+        self
+            .call("resize_pty", exec_id, |ctx| client.resize_pty(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call resize_pty failed"))?;
 
         Ok(())
@@ -355,11 +632,10 @@ impl Store {
         let mut req = protocols::shim::PauseRequest::new();
         req.id = self.container_id.clone();
 
-        let ctx = context::with_timeout(0);
-
-        client
-            .pause(ctx, &req)
+        self
+            .call("pause", "", |ctx| client.pause(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call pause failed"))?;
+        self.state_cache.invalidate();
 
         Ok(())
     }
@@ -371,26 +647,28 @@ impl Store {
         let mut req = protocols::shim::ResumeRequest::new();
         req.id = self.container_id.clone();
 
-        let ctx = context::with_timeout(0);
-
-        client
-            .resume(ctx, &req)
+        self
+            .call("resume", "", |ctx| client.resume(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call resume failed"))?;
+        self.state_cache.invalidate();
 
         Ok(())
     }
 
+    /// State of the container, concurrent callers share one call and results
+    /// are reused for the runtime's `state` cache TTL.
     pub fn state(&self) -> Result<State> {
+        self.state_cache.get(self.call_deadline("state"), || self.fetch_state())
+    }
 
+    fn fetch_state(&self) -> Result<State> {
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::StateRequest::new();
         req.id = self.container_id.clone();
 
-        let ctx = context::with_timeout(0);
-
-        let resp = client
-            .state(ctx, &req)
+        let resp = self
+            .call("state", "", |ctx| client.state(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call state failed"))?;
 
         Ok(State {
@@ -412,53 +690,162 @@ impl Store {
         })
     }
 
-    pub fn stats(&self) -> Result<Metrics> {
+    /// Stats of the container, concurrent callers share one call and results
+    /// are reused for the runtime's `stats` cache TTL.
+    pub fn stats(&self) -> Result<CgroupMetrics> {
+        self.stats_cache.get(self.call_deadline("stats"), || self.fetch_stats())
+    }
+
+    fn fetch_stats(&self) -> Result<CgroupMetrics> {
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::StatsRequest::new();
         req.id= self.container_id.clone();
-        let ctx = context::with_timeout(0);
-
-        let resp = client
-            .stats(ctx, &req)
+        let resp = self
+            .call("stats", "", |ctx| client.stats(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call stats failed"))?;
-        let mut m = Metrics::new();
-        if let Some(any) = resp.stats.as_ref() {
-            let mut input = CodedInputStream::from_bytes(any.value.as_ref());
-            m.merge_from(&mut input).unwrap();
+        match resp.stats.as_ref() {
+            Some(any) => CgroupMetrics::decode(any),
+            None => Ok(CgroupMetrics::default()),
         }
-        Ok(m)
     }
 
+    // bypasses the stats cache, a cached sample would show up as zero rates.
+    // Network counters come from the source StatsV2 uses, the shim's are
+    // kept when it cannot be read.
+    fn sample(&self) -> Result<(protocols::metrics::Metrics, Sample)> {
+        let raw = self.fetch_stats()?;
+        let mut metrics = raw.normalize();
+        if let Ok(network) = self.network_of(&raw) {
+            metrics.network = network;
+        }
+        let cur = Sample::new(&metrics, monotonic_now());
+        Ok((metrics, cur))
+    }
+
+    /// Stats with rates against the previous call of the same connection,
+    /// the first call has none. Callers sharing the connection see each
+    /// other's samples, see `stats_derived_from` to keep one of your own.
+    pub fn stats_derived(&self) -> Result<DerivedStats> {
+        // sampling under the lock keeps the stored sample the newest one
+        let mut last_sample = self.last_sample.lock().unwrap();
+        let (metrics, cur) = self.sample()?;
+        let derived = DerivedStats::new(&metrics, last_sample.as_ref(), &cur);
+        *last_sample = Some(cur);
+        Ok(derived)
+    }
+
+    /// Stats with rates against `prev`, and the sample to pass next time.
+    /// The connection's sample of `stats_derived` is left alone.
+    pub fn stats_derived_from(&self, prev: Option<&Sample>) -> Result<(DerivedStats, Sample)> {
+        let (metrics, cur) = self.sample()?;
+        Ok((DerivedStats::new(&metrics, prev, &cur), cur))
+    }
+
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            stats: self.stats_cache.counters(),
+            state: self.state_cache.counters(),
+        }
+    }
+
+    /// CPU, memory and IO pressure of the container, see `pressure_of`.
+    pub fn pressure(&self) -> Result<Pressure> {
+        let metrics = self.stats()?;
+        self.pressure_of(&metrics)
+    }
+
+    /// Pressure of the container from the source the runtime selects, see
+    /// `PressureSource`.
+    pub fn pressure_of(&self, metrics: &CgroupMetrics) -> Result<Pressure> {
+        match (self.runtime.pressure, metrics.pressure()) {
+            (PressureSource::Shim, pressure) => return pressure.ok_or_else(|| other!("shim sent no pressure")),
+            (PressureSource::Auto, Some(pressure)) => return Ok(pressure),
+            _ => {}
+        }
+        let pid = self.state()?.pid;
+        let dir = unified_cgroup_dir(Path::new(PROC_ROOT), Path::new(CGROUP_ROOT), pid)?;
+        Pressure::read(&dir)
+    }
+
+    /// Network stats of the container from the source the runtime selects,
+    /// see `NetworkStatsSource`.
+    pub fn network_of(&self, metrics: &CgroupMetrics) -> Result<Vec<protocols::metrics::NetworkStat>> {
+        let shim_stats = metrics.network();
+        match self.runtime.network_stats {
+            NetworkStatsSource::Shim => return Ok(shim_stats.to_vec()),
+            NetworkStatsSource::Auto if !shim_stats.is_empty() => return Ok(shim_stats.to_vec()),
+            _ => {}
+        }
+        let pid = self.state()?.pid;
+        task_network(Path::new(PROC_ROOT), pid)
+    }
+
+    /// Pid of the container's first process, see `processes`.
     pub fn pids(&self) -> Result<i32> {
+        self.processes()?
+            .first()
+            .map(|p| p.pid as i32)
+            .ok_or_else(|| other!("container has no processes"))
+    }
+
+    pub fn processes(&self) -> Result<Vec<ProcessInfo>> {
         let c = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::PidsRequest::new();
         req.id = self.container_id.clone();
 
-        let ctx = context::with_timeout(0);
-
-        let resp = c
-            .pids(ctx, &req)
+        let resp = self
+            .call("pids", "", |ctx| c.pids(ctx, &req))
             .map_err(shim_error!(e, "call pids failed"))?;
-        
-        Ok(resp.processes[0].pid as i32)
+
+        resp.processes.iter().map(ProcessInfo::from_proto).collect()
+    }
+
+    /// ps style table of the container's processes with the given columns,
+    /// see `top::Column::parse`, or the `ps -ef` ones when empty.
+    pub fn top(&self, names: &[&str]) -> Result<ProcessTable> {
+        let columns = columns(names)?;
+        Procfs::default().table(&self.processes()?, &columns)
+    }
+
+    pub fn wait(&self, exec_id: &str) -> Result<i32> {
+        self.wait_response(exec_id).map(|resp| resp.exit_status as i32)
     }
 
-    pub fn wait(&self, exec_id: &String) -> Result<i32> {
+    pub fn wait_response(&self, exec_id: &str) -> Result<WaitResponse> {
 
         let client = protocols::shim_ttrpc::TaskClient::new(self.conn.clone());
 
         let mut req = protocols::shim::WaitRequest::new();
         req.id = self.container_id.clone();
-        req.exec_id = exec_id.clone();
-
-        let ctx = context::with_timeout(0);
+        req.exec_id = exec_id.to_string();
 
-        let resp = client
-            .wait(ctx, &req)
+        let resp = self
+            .call("wait", exec_id, |ctx| client.wait(ctx, &req))
             .map_err(shim_error!(e, "ttrpc call wait failed"))?;
+        self.state_cache.invalidate();
+
+        Ok(resp.into())
+    }
 
-        Ok(resp.exit_status as i32)
+    /// A store like this one over a connection of its own, its calls do not
+    /// wait behind those of the others and `disconnect` ends them alone.
+    pub(crate) fn reconnect(&self) -> Result<Store> {
+        let fd = connect_to_addr(&self.addr)?;
+        let conn = ttrpc::Client::new(fd).map_err(|e| Error::Other(format!("failed to create ttrpc client: {:?}", e)))?;
+        Ok(Store {
+            conn,
+            fd,
+            ..self.clone()
+        })
+    }
+
+    // calls in flight on any clone of the store fail once the socket is shut
+    // down, the fd itself is closed with the last clone
+    pub(crate) fn disconnect(&self) {
+        if let Err(e) = shutdown(self.fd, Shutdown::Both) {
+            println!("lib-shim-v2::close::{}:: failed to shut down socket, {}.", self.container_id, e);
+        }
     }
 }
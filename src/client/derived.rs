@@ -0,0 +1,212 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::stats::blkio_devices;
+use crate::protocols::metrics::Metrics;
+use std::time::Duration;
+
+/// Time on the monotonic clock, comparable across threads and connections.
+pub fn monotonic_now() -> Duration {
+    let mut ts = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    Duration::new(ts.tv_sec as u64, ts.tv_nsec as u32)
+}
+
+/// The cumulative counters of one stats call that rates are computed from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Sample {
+    /// See `monotonic_now`.
+    pub at: Duration,
+    pub cpu_total: u64,
+    pub blkio_read_bytes: u64,
+    pub blkio_write_bytes: u64,
+    pub blkio_read_ops: u64,
+    pub blkio_write_ops: u64,
+    pub net_rx_bytes: u64,
+    pub net_tx_bytes: u64,
+}
+
+impl Sample {
+    /// Takes the counters of normalized metrics, see `CgroupMetrics::normalize`.
+    pub fn new(m: &Metrics, at: Duration) -> Sample {
+        let mut sample = Sample {
+            at,
+            cpu_total: m.cpu.usage.total,
+            blkio_read_bytes: 0,
+            blkio_write_bytes: 0,
+            blkio_read_ops: 0,
+            blkio_write_ops: 0,
+            net_rx_bytes: 0,
+            net_tx_bytes: 0,
+        };
+        for dev in blkio_devices(&m.blkio).iter() {
+            sample.blkio_read_bytes += dev.bytes.read;
+            sample.blkio_write_bytes += dev.bytes.write;
+            sample.blkio_read_ops += dev.ops.read;
+            sample.blkio_write_ops += dev.ops.write;
+        }
+        for net in m.network.iter() {
+            sample.net_rx_bytes += net.rx_bytes;
+            sample.net_tx_bytes += net.tx_bytes;
+        }
+        sample
+    }
+
+    fn counters(&self) -> [u64; 7] {
+        [
+            self.cpu_total,
+            self.blkio_read_bytes,
+            self.blkio_write_bytes,
+            self.blkio_read_ops,
+            self.blkio_write_ops,
+            self.net_rx_bytes,
+            self.net_tx_bytes,
+        ]
+    }
+}
+
+/// Values derived from two consecutive samples of a container. Rates are per
+/// second and only set when `has_rates` is, i.e. not for the first sample and
+/// not when a counter went backwards, e.g. because the container restarted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DerivedStats {
+    pub has_rates: bool,
+    pub interval: Duration,
+    /// CPU usage in percent of one CPU, above 100 when several are used.
+    pub cpu_percent: f64,
+    pub cpu_nano_cores: u64,
+    pub memory_usage: u64,
+    /// Usage minus inactive file pages, as the kubelet computes it.
+    pub memory_working_set: u64,
+    pub blkio_read_bps: f64,
+    pub blkio_write_bps: f64,
+    pub blkio_read_iops: f64,
+    pub blkio_write_iops: f64,
+    pub net_rx_bps: f64,
+    pub net_tx_bps: f64,
+}
+
+impl DerivedStats {
+    /// Derives the stats of `cur`, with rates against `prev` if there is one.
+    pub fn new(m: &Metrics, prev: Option<&Sample>, cur: &Sample) -> DerivedStats {
+        let usage = m.memory.usage.usage;
+        let mut stats = DerivedStats {
+            memory_usage: usage,
+            memory_working_set: usage.saturating_sub(m.memory.total_inactive_file),
+            ..Default::default()
+        };
+
+        let prev = match prev {
+            Some(prev) => prev,
+            None => return stats,
+        };
+        let interval = cur.at.saturating_sub(prev.at);
+        let reset = prev
+            .counters()
+            .iter()
+            .zip(cur.counters().iter())
+            .any(|(p, c)| c < p);
+        if reset || interval.is_zero() {
+            return stats;
+        }
+
+        let secs = interval.as_secs_f64();
+        let rate = |p: u64, c: u64| (c - p) as f64 / secs;
+        stats.has_rates = true;
+        stats.interval = interval;
+        stats.cpu_nano_cores = rate(prev.cpu_total, cur.cpu_total) as u64;
+        stats.cpu_percent = stats.cpu_nano_cores as f64 / 1e7;
+        stats.blkio_read_bps = rate(prev.blkio_read_bytes, cur.blkio_read_bytes);
+        stats.blkio_write_bps = rate(prev.blkio_write_bytes, cur.blkio_write_bytes);
+        stats.blkio_read_iops = rate(prev.blkio_read_ops, cur.blkio_read_ops);
+        stats.blkio_write_iops = rate(prev.blkio_write_ops, cur.blkio_write_ops);
+        stats.net_rx_bps = rate(prev.net_rx_bytes, cur.net_rx_bytes);
+        stats.net_tx_bps = rate(prev.net_tx_bytes, cur.net_tx_bytes);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::metrics::{BlkIOEntry, NetworkStat};
+
+    fn metrics(cpu: u64, read: u64, rx: u64) -> Metrics {
+        let mut m = Metrics::new();
+        m.cpu.mut_or_insert_default().usage.mut_or_insert_default().total = cpu;
+        let mem = m.memory.mut_or_insert_default();
+        mem.usage.mut_or_insert_default().usage = 100 << 20;
+        mem.total_inactive_file = 30 << 20;
+        for (op, value) in [("Read", read), ("Write", 0)] {
+            let mut e = BlkIOEntry::new();
+            e.major = 8;
+            e.op = op.to_string();
+            e.value = value;
+            m.blkio.mut_or_insert_default().io_service_bytes_recursive.push(e);
+        }
+        let mut net = NetworkStat::new();
+        net.name = "eth0".to_string();
+        net.rx_bytes = rx;
+        m.network.push(net);
+        m
+    }
+
+    #[test]
+    fn test_derived_stats() {
+        let start = monotonic_now();
+        let m1 = metrics(1_000_000_000, 4096, 1000);
+        let s1 = Sample::new(&m1, start);
+
+        let first = DerivedStats::new(&m1, None, &s1);
+        assert!(!first.has_rates);
+        assert_eq!(first.memory_usage, 100 << 20);
+        assert_eq!(first.memory_working_set, 70 << 20);
+
+        // 1.5 CPUs busy, 8 KiB/s read, 500 B/s received over 2 seconds
+        let m2 = metrics(4_000_000_000, 4096 + 16384, 2000);
+        let s2 = Sample::new(&m2, start + Duration::from_secs(2));
+        let derived = DerivedStats::new(&m2, Some(&s1), &s2);
+        assert!(derived.has_rates);
+        assert_eq!(derived.interval, Duration::from_secs(2));
+        assert_eq!(derived.cpu_nano_cores, 1_500_000_000);
+        assert_eq!(derived.cpu_percent, 150.0);
+        assert_eq!(derived.blkio_read_bps, 8192.0);
+        assert_eq!(derived.blkio_write_bps, 0.0);
+        assert_eq!(derived.net_rx_bps, 500.0);
+    }
+
+    #[test]
+    fn test_derived_stats_reset() {
+        let start = monotonic_now();
+        let s1 = Sample::new(&metrics(4_000_000_000, 4096, 1000), start);
+
+        // the container restarted, its counters start from zero again
+        let m2 = metrics(1_000, 0, 0);
+        let s2 = Sample::new(&m2, start + Duration::from_secs(1));
+        let derived = DerivedStats::new(&m2, Some(&s1), &s2);
+        assert!(!derived.has_rates);
+        assert_eq!(derived.cpu_percent, 0.0);
+        assert_eq!(derived.memory_working_set, 70 << 20);
+
+        let s3 = Sample::new(&m2, start + Duration::from_secs(1));
+        assert!(!DerivedStats::new(&m2, Some(&s2), &s3).has_rates);
+    }
+
+    #[test]
+    fn test_working_set_saturates() {
+        let mut m = Metrics::new();
+        m.memory.mut_or_insert_default().usage.mut_or_insert_default().usage = 10;
+        m.memory.mut_or_insert_default().total_inactive_file = 20;
+        let s = Sample::new(&m, monotonic_now());
+        assert_eq!(DerivedStats::new(&m, None, &s).memory_working_set, 0);
+    }
+}
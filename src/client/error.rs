@@ -13,7 +13,7 @@
 use std::fmt;
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum Error {
     WithoutInit(String),
     InvalidArgument(String),
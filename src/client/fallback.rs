@@ -0,0 +1,640 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Stats read from the container's cgroup on the host, for runc style shims
+//! that do not answer. Only works where the cgroup is visible to the host,
+//! not for VM based runtimes.
+
+use super::bundle::read_spec;
+use super::cgroup::{user_hz, CGROUP_ROOT, PROC_ROOT};
+use super::client::get_conn;
+use super::error::{Error, Result};
+use super::stats::CgroupMetrics;
+use crate::protocols::{metrics, metrics_v2};
+use protobuf::reflect::{ReflectValueBox, RuntimeFieldType, RuntimeType};
+use protobuf::MessageDyn;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+const V1_CONTROLLERS: [&str; 5] = ["pids", "cpu", "cpuacct", "memory", "blkio"];
+
+/// Metrics and whether they were read from the cgroupfs instead of the shim.
+#[derive(Clone, Debug)]
+pub struct CollectedStats {
+    pub metrics: CgroupMetrics,
+    pub fallback: bool,
+}
+
+/// Cgroup directories of a container.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CgroupPaths {
+    V1(HashMap<String, PathBuf>),
+    V2(PathBuf),
+}
+
+pub struct CgroupFs {
+    pub root: PathBuf,
+    pub proc_root: PathBuf,
+}
+
+impl Default for CgroupFs {
+    fn default() -> Self {
+        CgroupFs {
+            root: PathBuf::from(CGROUP_ROOT),
+            proc_root: PathBuf::from(PROC_ROOT),
+        }
+    }
+}
+
+/// How long the shim has to answer before its cgroup is read instead, less
+/// when the runtime's stats timeout is shorter.
+pub const FALLBACK_SHIM_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Stats from the shim, or from the cgroupfs when the shim cannot be reached
+/// or does not answer within `FALLBACK_SHIM_TIMEOUT`.
+pub fn stats_with_fallback(container_id: &str, bundle: &str) -> Result<CollectedStats> {
+    CgroupFs::default().stats_with_fallback(container_id, bundle, FALLBACK_SHIM_TIMEOUT)
+}
+
+/// Path of a systemd cgroup `slice:prefix:name` relative to the hierarchy
+/// root, `kubepods-pod1.slice:cri-containerd:abc` becomes
+/// `kubepods.slice/kubepods-pod1.slice/cri-containerd-abc.scope`.
+fn expand_systemd_path(path: &str) -> Result<PathBuf> {
+    let parts: Vec<&str> = path.split(':').collect();
+    if parts.len() != 3 {
+        return Err(Error::InvalidArgument(format!(
+            "bad systemd cgroup path {}",
+            path
+        )));
+    }
+    let (slice, prefix, name) = (parts[0], parts[1], parts[2]);
+    let mut rel = PathBuf::new();
+    if !slice.is_empty() && slice != "-.slice" {
+        let slice_name = slice.strip_suffix(".slice").ok_or_else(|| {
+            Error::InvalidArgument(format!("bad systemd slice {}", slice))
+        })?;
+        let mut prefix_so_far = String::new();
+        for component in slice_name.split('-') {
+            if !prefix_so_far.is_empty() {
+                prefix_so_far.push('-');
+            }
+            prefix_so_far.push_str(component);
+            rel.push(format!("{}.slice", prefix_so_far));
+        }
+    }
+    if prefix.is_empty() {
+        rel.push(format!("{}.scope", name));
+    } else {
+        rel.push(format!("{}-{}.scope", prefix, name));
+    }
+    Ok(rel)
+}
+
+fn read_file(dir: &Path, name: &str) -> Option<String> {
+    std::fs::read_to_string(dir.join(name)).ok()
+}
+
+// "max" is reported as `max_value`, cgroup v2 style
+fn read_u64(dir: &Path, name: &str, max_value: u64) -> Option<u64> {
+    let data = read_file(dir, name)?;
+    match data.trim() {
+        "max" => Some(max_value),
+        value => value.parse().ok(),
+    }
+}
+
+/// Sets the u64 fields of `msg` named by the keys of a `key value` file.
+fn set_flat_keys(msg: &mut dyn MessageDyn, data: &str, rename: fn(&str) -> String) {
+    let descriptor = msg.descriptor_dyn();
+    for line in data.lines() {
+        let mut fields = line.split_whitespace();
+        let (key, value) = match (fields.next(), fields.next().and_then(|v| v.parse().ok())) {
+            (Some(key), Some(value)) => (key, value),
+            _ => continue,
+        };
+        if let Some(field) = descriptor.field_by_name(&rename(key)) {
+            if let RuntimeFieldType::Singular(RuntimeType::U64) = field.runtime_field_type() {
+                field.set_singular_field(msg, ReflectValueBox::U64(value));
+            }
+        }
+    }
+}
+
+// memory.stat of cgroup v1 names the paging counters differently than the proto
+fn v1_memory_stat_name(key: &str) -> String {
+    let (total, name) = match key.strip_prefix("total_") {
+        Some(name) => ("total_", name),
+        None => ("", key),
+    };
+    let name = match name {
+        "pgpgin" => "pg_pg_in",
+        "pgpgout" => "pg_pg_out",
+        "pgfault" => "pg_fault",
+        "pgmajfault" => "pg_maj_fault",
+        name => name,
+    };
+    format!("{}{}", total, name)
+}
+
+fn same_name(key: &str) -> String {
+    key.to_string()
+}
+
+fn memory_entry(dir: &Path, prefix: &str) -> metrics::MemoryEntry {
+    let mut entry = metrics::MemoryEntry::new();
+    entry.usage = read_u64(dir, &format!("{}.usage_in_bytes", prefix), 0).unwrap_or_default();
+    entry.limit = read_u64(dir, &format!("{}.limit_in_bytes", prefix), 0).unwrap_or_default();
+    entry.max = read_u64(dir, &format!("{}.max_usage_in_bytes", prefix), 0).unwrap_or_default();
+    entry.failcnt = read_u64(dir, &format!("{}.failcnt", prefix), 0).unwrap_or_default();
+    entry
+}
+
+/// Entries of a blkio file, `8:0 Read 4096` lines, the `Total` summary
+/// line is skipped.
+fn blkio_entries(data: &str) -> Vec<metrics::BlkIOEntry> {
+    data.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return None;
+            }
+            let (major, minor) = fields[0].split_once(':')?;
+            let mut entry = metrics::BlkIOEntry::new();
+            entry.major = major.parse().ok()?;
+            entry.minor = minor.parse().ok()?;
+            entry.op = fields[1].to_string();
+            entry.value = fields[2].parse().ok()?;
+            Some(entry)
+        })
+        .collect()
+}
+
+fn read_blkio(dir: &Path, name: &str) -> Vec<metrics::BlkIOEntry> {
+    // the CFQ files are gone with blk-mq, the throttle ones count all IO
+    [
+        format!("blkio.{}_recursive", name),
+        format!("blkio.throttle.{}_recursive", name),
+        format!("blkio.throttle.{}", name),
+    ]
+    .iter()
+    .find_map(|file| read_file(dir, file))
+    .map(|data| blkio_entries(&data))
+    .unwrap_or_default()
+}
+
+fn io_stat_entries(data: &str) -> Vec<metrics_v2::IOEntry> {
+    data.lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let (major, minor) = fields.next()?.split_once(':')?;
+            let mut entry = metrics_v2::IOEntry::new();
+            entry.major = major.parse().ok()?;
+            entry.minor = minor.parse().ok()?;
+            for field in fields {
+                let (key, value) = match field.split_once('=') {
+                    Some((key, value)) => (key, value.parse().unwrap_or_default()),
+                    None => continue,
+                };
+                match key {
+                    "rbytes" => entry.rbytes = value,
+                    "wbytes" => entry.wbytes = value,
+                    "rios" => entry.rios = value,
+                    "wios" => entry.wios = value,
+                    _ => {}
+                }
+            }
+            Some(entry)
+        })
+        .collect()
+}
+
+impl CgroupFs {
+    fn is_unified(&self) -> bool {
+        self.root.join("cgroup.controllers").exists()
+    }
+
+    fn paths_from_relative(&self, rel: &Path) -> CgroupPaths {
+        let rel = rel.strip_prefix("/").unwrap_or(rel);
+        if self.is_unified() {
+            return CgroupPaths::V2(self.root.join(rel));
+        }
+        CgroupPaths::V1(
+            V1_CONTROLLERS
+                .iter()
+                .map(|c| (c.to_string(), self.root.join(c).join(rel)))
+                .collect(),
+        )
+    }
+
+    /// Cgroup of the container as configured in the bundle's config.json.
+    pub fn paths_from_bundle(&self, bundle: &Path) -> Result<CgroupPaths> {
+        let spec = read_spec(bundle)?;
+        let path = spec
+            .linux
+            .as_ref()
+            .map(|linux| linux.cgroups_path.as_str())
+            .filter(|p| !p.is_empty())
+            .ok_or_else(|| other!("config.json has no linux.cgroupsPath"))?;
+        let rel = if !path.starts_with('/') && path.matches(':').count() == 2 {
+            expand_systemd_path(path)?
+        } else {
+            PathBuf::from(path)
+        };
+        Ok(self.paths_from_relative(&rel))
+    }
+
+    /// Cgroup of a process as listed in `/proc/<pid>/cgroup`.
+    pub fn paths_from_pid(&self, pid: u32) -> Result<CgroupPaths> {
+        let data = std::fs::read_to_string(self.proc_root.join(pid.to_string()).join("cgroup"))?;
+        if self.is_unified() {
+            return data
+                .lines()
+                .find_map(|line| line.strip_prefix("0::"))
+                .map(|path| self.paths_from_relative(Path::new(path)))
+                .ok_or_else(|| other!(format!("no cgroup v2 entry for pid {}", pid)));
+        }
+        let mut dirs = HashMap::new();
+        for line in data.lines() {
+            let fields: Vec<&str> = line.splitn(3, ':').collect();
+            if fields.len() != 3 || fields[1].is_empty() {
+                continue;
+            }
+            let rel = fields[2].trim_start_matches('/');
+            // co-mounted controllers share a directory, e.g. cpu,cpuacct
+            let dir = self.root.join(fields[1]).join(rel);
+            for controller in fields[1].split(',') {
+                dirs.insert(controller.to_string(), dir.clone());
+            }
+        }
+        Ok(CgroupPaths::V1(dirs))
+    }
+
+    /// The container's cgroup from the bundle's config.json, or from the
+    /// cgroup of the pid in the bundle's init.pid.
+    pub fn paths(&self, bundle: &Path) -> Result<CgroupPaths> {
+        self.paths_from_bundle(bundle).or_else(|e| {
+            let pid = std::fs::read_to_string(bundle.join("init.pid"))
+                .ok()
+                .and_then(|pid| pid.trim().parse().ok())
+                .ok_or(e)?;
+            self.paths_from_pid(pid)
+        })
+    }
+
+    pub fn collect(&self, paths: &CgroupPaths) -> Result<CgroupMetrics> {
+        match paths {
+            CgroupPaths::V1(dirs) => collect_v1(dirs).map(CgroupMetrics::V1),
+            CgroupPaths::V2(dir) => collect_v2(dir).map(CgroupMetrics::V2),
+        }
+    }
+
+    fn stats_with_fallback(&self, container_id: &str, bundle: &str, timeout: Duration) -> Result<CollectedStats> {
+        let shim_stats = get_conn(container_id)
+            .and_then(|client| client.with_deadline(Instant::now() + timeout).stats());
+        let err = match shim_stats {
+            Ok(metrics) => {
+                return Ok(CollectedStats {
+                    metrics,
+                    fallback: false,
+                })
+            }
+            Err(e) => e,
+        };
+        self.paths(Path::new(bundle))
+            .and_then(|paths| self.collect(&paths))
+            .map(|metrics| CollectedStats {
+                metrics,
+                fallback: true,
+            })
+            .map_err(|e| other!(format!("{}, cgroupfs fallback failed: {}", err, e)))
+    }
+}
+
+fn collect_v1(dirs: &HashMap<String, PathBuf>) -> Result<metrics::Metrics> {
+    let dir = |controller: &str| dirs.get(controller).filter(|d| d.is_dir());
+    if dir("memory").is_none() && dir("cpuacct").is_none() {
+        return Err(other!("cgroup of the container not found"));
+    }
+    let mut m = metrics::Metrics::new();
+
+    if let Some(d) = dir("pids") {
+        let pids = m.pids.mut_or_insert_default();
+        pids.current = read_u64(d, "pids.current", 0).unwrap_or_default();
+        pids.limit = read_u64(d, "pids.max", 0).unwrap_or_default();
+    }
+
+    if let Some(d) = dir("cpuacct") {
+        let usage = m.cpu.mut_or_insert_default().usage.mut_or_insert_default();
+        usage.total = read_u64(d, "cpuacct.usage", 0).unwrap_or_default();
+        usage.per_cpu = read_file(d, "cpuacct.usage_percpu")
+            .map(|data| data.split_whitespace().filter_map(|v| v.parse().ok()).collect())
+            .unwrap_or_default();
+        let nanos_per_tick = 1_000_000_000 / user_hz();
+        for line in read_file(d, "cpuacct.stat").unwrap_or_default().lines() {
+            match line.split_once(' ') {
+                Some(("user", ticks)) => {
+                    usage.user = ticks.trim().parse::<u64>().unwrap_or_default() * nanos_per_tick
+                }
+                Some(("system", ticks)) => {
+                    usage.kernel = ticks.trim().parse::<u64>().unwrap_or_default() * nanos_per_tick
+                }
+                _ => {}
+            }
+        }
+    }
+    if let Some(data) = dir("cpu").and_then(|d| read_file(d, "cpu.stat")) {
+        let throttling = m.cpu.mut_or_insert_default().throttling.mut_or_insert_default();
+        set_flat_keys(throttling, &data, |key| {
+            match key {
+                "nr_periods" => "periods",
+                "nr_throttled" => "throttled_periods",
+                key => key,
+            }
+            .to_string()
+        });
+    }
+
+    if let Some(d) = dir("memory") {
+        let memory = m.memory.mut_or_insert_default();
+        set_flat_keys(
+            memory,
+            &read_file(d, "memory.stat").unwrap_or_default(),
+            v1_memory_stat_name,
+        );
+        memory.usage = Some(memory_entry(d, "memory")).into();
+        memory.swap = Some(memory_entry(d, "memory.memsw")).into();
+        memory.kernel = Some(memory_entry(d, "memory.kmem")).into();
+        memory.kernel_tcp = Some(memory_entry(d, "memory.kmem.tcp")).into();
+        if let Some(data) = read_file(d, "memory.oom_control") {
+            set_flat_keys(m.memory_oom_control.mut_or_insert_default(), &data, same_name);
+        }
+    }
+
+    if let Some(d) = dir("blkio") {
+        let blkio = m.blkio.mut_or_insert_default();
+        blkio.io_service_bytes_recursive = read_blkio(d, "io_service_bytes");
+        blkio.io_serviced_recursive = read_blkio(d, "io_serviced");
+    }
+    Ok(m)
+}
+
+fn collect_v2(dir: &Path) -> Result<metrics_v2::Metrics> {
+    if !dir.is_dir() {
+        return Err(other!(format!(
+            "cgroup {} not found",
+            dir.to_string_lossy()
+        )));
+    }
+    let mut m = metrics_v2::Metrics::new();
+
+    let pids = m.pids.mut_or_insert_default();
+    pids.current = read_u64(dir, "pids.current", 0).unwrap_or_default();
+    pids.limit = read_u64(dir, "pids.max", 0).unwrap_or_default();
+
+    if let Some(data) = read_file(dir, "cpu.stat") {
+        set_flat_keys(m.cpu.mut_or_insert_default(), &data, same_name);
+    }
+
+    let memory = m.memory.mut_or_insert_default();
+    set_flat_keys(memory, &read_file(dir, "memory.stat").unwrap_or_default(), same_name);
+    memory.usage = read_u64(dir, "memory.current", u64::MAX).unwrap_or_default();
+    memory.usage_limit = read_u64(dir, "memory.max", u64::MAX).unwrap_or_default();
+    memory.swap_usage = read_u64(dir, "memory.swap.current", u64::MAX).unwrap_or_default();
+    memory.swap_limit = read_u64(dir, "memory.swap.max", u64::MAX).unwrap_or_default();
+    if let Some(data) = read_file(dir, "memory.events") {
+        set_flat_keys(m.memory_events.mut_or_insert_default(), &data, same_name);
+    }
+
+    if let Some(data) = read_file(dir, "io.stat") {
+        m.io.mut_or_insert_default().usage = io_stat_entries(&data);
+    }
+    Ok(m)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::del_conn;
+    use crate::client::runtime::RuntimeHandler;
+    use crate::client::testutil::{StuckShim, TempDir};
+
+    struct FakeFs {
+        dir: TempDir,
+    }
+
+    impl FakeFs {
+        fn new(name: &str) -> FakeFs {
+            FakeFs {
+                dir: TempDir::new(&format!("cgroupfs-{}", name)),
+            }
+        }
+
+        fn write(&self, path: &str, data: &str) {
+            self.dir.write(path, data);
+        }
+
+        fn cgroupfs(&self) -> CgroupFs {
+            CgroupFs {
+                root: self.dir.join("cgroup"),
+                proc_root: self.dir.join("proc"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_expand_systemd_path() {
+        assert_eq!(
+            expand_systemd_path("kubepods-besteffort-pod1.slice:cri-containerd:abc").unwrap(),
+            Path::new("kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1.slice/cri-containerd-abc.scope")
+        );
+        assert_eq!(
+            expand_systemd_path("system.slice:docker:abc").unwrap(),
+            Path::new("system.slice/docker-abc.scope")
+        );
+        assert_eq!(
+            expand_systemd_path("-.slice::abc").unwrap(),
+            Path::new("abc.scope")
+        );
+        assert!(expand_systemd_path("system:docker:abc").is_err());
+        assert!(expand_systemd_path("system.slice:abc").is_err());
+    }
+
+    #[test]
+    fn test_collect_v1() {
+        let fs = FakeFs::new("v1");
+        let cg = "cgroup/%s/isulad/abc";
+        let at = |controller: &str, file: &str| format!("{}/{}", cg.replace("%s", controller), file);
+        fs.write(&at("pids", "pids.current"), "7\n");
+        fs.write(&at("pids", "pids.max"), "max\n");
+        fs.write(&at("cpuacct", "cpuacct.usage"), "5000000000\n");
+        fs.write(&at("cpuacct", "cpuacct.usage_percpu"), "3000000000 2000000000 \n");
+        fs.write(
+            &at("cpuacct", "cpuacct.stat"),
+            &format!("user {}\nsystem {}\n", user_hz() * 3, user_hz() * 3 / 2),
+        );
+        fs.write(&at("cpu", "cpu.stat"), "nr_periods 10\nnr_throttled 2\nthrottled_time 12345\n");
+        fs.write(
+            &at("memory", "memory.stat"),
+            "cache 4096\nrss 8192\nrss_huge 0\npgfault 100\npgmajfault 3\ntotal_cache 4096\ntotal_rss 8192\ntotal_pgpgin 50\ntotal_inactive_file 1024\nunknown_key 1\n",
+        );
+        fs.write(&at("memory", "memory.usage_in_bytes"), "16384\n");
+        fs.write(&at("memory", "memory.limit_in_bytes"), "9223372036854771712\n");
+        fs.write(&at("memory", "memory.failcnt"), "0\n");
+        fs.write(&at("memory", "memory.memsw.usage_in_bytes"), "16384\n");
+        fs.write(&at("memory", "memory.oom_control"), "oom_kill_disable 0\nunder_oom 0\noom_kill 1\n");
+        fs.write(
+            &at("blkio", "blkio.throttle.io_service_bytes"),
+            "8:0 Read 4096\n8:0 Write 8192\n8:0 Sync 12288\n8:0 Async 0\n8:0 Total 12288\nTotal 12288\n",
+        );
+        fs.write(
+            &at("blkio", "blkio.throttle.io_serviced"),
+            "8:0 Read 1\n8:0 Write 2\n8:0 Total 3\nTotal 3\n",
+        );
+        fs.write("bundle/config.json", r#"{"linux": {"cgroupsPath": "/isulad/abc"}}"#);
+
+        let cgroupfs = fs.cgroupfs();
+        let paths = cgroupfs.paths(&fs.dir.join("bundle")).unwrap();
+        let m = match cgroupfs.collect(&paths).unwrap() {
+            CgroupMetrics::V1(m) => m,
+            CgroupMetrics::V2(_) => panic!("collected cgroup v2 metrics"),
+        };
+        assert_eq!(m.pids.current, 7);
+        assert_eq!(m.pids.limit, 0);
+        assert_eq!(m.cpu.usage.total, 5_000_000_000);
+        assert_eq!(m.cpu.usage.per_cpu, vec![3_000_000_000, 2_000_000_000]);
+        assert_eq!(m.cpu.usage.user, 3_000_000_000);
+        assert_eq!(m.cpu.usage.kernel, 1_500_000_000);
+        assert_eq!(m.cpu.throttling.periods, 10);
+        assert_eq!(m.cpu.throttling.throttled_periods, 2);
+        assert_eq!(m.cpu.throttling.throttled_time, 12345);
+        assert_eq!(m.memory.cache, 4096);
+        assert_eq!(m.memory.rss, 8192);
+        assert_eq!(m.memory.pg_fault, 100);
+        assert_eq!(m.memory.pg_maj_fault, 3);
+        assert_eq!(m.memory.total_pg_pg_in, 50);
+        assert_eq!(m.memory.total_inactive_file, 1024);
+        assert_eq!(m.memory.usage.usage, 16384);
+        assert_eq!(m.memory.usage.limit, 9223372036854771712);
+        assert_eq!(m.memory.swap.usage, 16384);
+        assert_eq!(m.memory_oom_control.oom_kill, 1);
+        assert_eq!(m.blkio.io_service_bytes_recursive.len(), 5);
+        assert_eq!(m.blkio.io_service_bytes_recursive[1].op, "Write");
+        assert_eq!(m.blkio.io_service_bytes_recursive[1].value, 8192);
+        assert_eq!(m.blkio.io_serviced_recursive[2].value, 3);
+    }
+
+    #[test]
+    fn test_collect_v2() {
+        let fs = FakeFs::new("v2");
+        fs.write("cgroup/cgroup.controllers", "cpu io memory pids\n");
+        let cg = "cgroup/kubepods.slice/kubepods-pod1.slice/cri-containerd-abc.scope";
+        fs.write(&format!("{}/pids.current", cg), "3\n");
+        fs.write(&format!("{}/pids.max", cg), "4096\n");
+        fs.write(
+            &format!("{}/cpu.stat", cg),
+            "usage_usec 15000\nuser_usec 10000\nsystem_usec 5000\nnr_periods 20\nnr_throttled 2\nthrottled_usec 300\n",
+        );
+        fs.write(
+            &format!("{}/memory.stat", cg),
+            "anon 1048576\nfile 4096\ninactive_file 2048\npgfault 100\nworkingset_refault_anon 0\n",
+        );
+        fs.write(&format!("{}/memory.current", cg), "1200000\n");
+        fs.write(&format!("{}/memory.max", cg), "max\n");
+        fs.write(&format!("{}/memory.events", cg), "low 0\nhigh 0\nmax 4\noom 1\noom_kill 1\n");
+        fs.write(
+            &format!("{}/io.stat", cg),
+            "253:0 rbytes=28672 wbytes=1110016 rios=7 wios=271 dbytes=0 dios=0\n",
+        );
+        fs.write(
+            "bundle/config.json",
+            r#"{"linux": {"cgroupsPath": "kubepods-pod1.slice:cri-containerd:abc"}}"#,
+        );
+
+        let cgroupfs = fs.cgroupfs();
+        let paths = cgroupfs.paths(&fs.dir.join("bundle")).unwrap();
+        assert_eq!(paths, CgroupPaths::V2(fs.dir.join(cg)));
+        let metrics = cgroupfs.collect(&paths).unwrap();
+        assert_eq!(metrics.cgroup_version(), 2);
+
+        let m = metrics.normalize();
+        assert_eq!(m.pids.current, 3);
+        assert_eq!(m.pids.limit, 4096);
+        assert_eq!(m.cpu.usage.total, 15_000_000);
+        assert_eq!(m.cpu.throttling.throttled_time, 300_000);
+        assert_eq!(m.memory.rss, 1048576);
+        assert_eq!(m.memory.usage.usage, 1200000);
+        assert_eq!(m.memory.usage.limit, u64::MAX);
+        assert_eq!(m.memory.total_inactive_file, 2048);
+        assert_eq!(m.memory_oom_control.oom_kill, 1);
+        assert_eq!(m.blkio.io_serviced_recursive.len(), 3);
+    }
+
+    #[test]
+    fn test_paths_from_init_pid() {
+        let fs = FakeFs::new("pid");
+        fs.write("cgroup/memory/docker/abc/memory.usage_in_bytes", "1\n");
+        fs.write(
+            "proc/42/cgroup",
+            "11:memory:/docker/abc\n4:cpu,cpuacct:/docker/abc\n1:name=systemd:/docker/abc\n0::/\n",
+        );
+        fs.write("bundle/config.json", r#"{"linux": {}}"#);
+        fs.write("bundle/init.pid", "42");
+
+        let cgroupfs = fs.cgroupfs();
+        let dirs = match cgroupfs.paths(&fs.dir.join("bundle")).unwrap() {
+            CgroupPaths::V1(dirs) => dirs,
+            CgroupPaths::V2(_) => panic!("found cgroup v2 paths"),
+        };
+        assert_eq!(dirs["memory"], fs.dir.join("cgroup/memory/docker/abc"));
+        assert_eq!(dirs["cpuacct"], fs.dir.join("cgroup/cpu,cpuacct/docker/abc"));
+        assert_eq!(dirs["cpu"], dirs["cpuacct"]);
+
+        let m = cgroupfs.collect(&CgroupPaths::V1(dirs)).unwrap().normalize();
+        assert_eq!(m.memory.usage.usage, 1);
+
+        fs.write("bundle/init.pid", "43");
+        assert!(cgroupfs.paths(&fs.dir.join("bundle")).is_err());
+        assert!(cgroupfs.collect(&CgroupPaths::V2(fs.dir.join("none"))).is_err());
+    }
+
+    #[test]
+    fn test_stats_with_fallback_stuck_shim() {
+        let fs = FakeFs::new("stuck");
+        fs.write("cgroup/cgroup.controllers", "memory pids\n");
+        fs.write("cgroup/abc/memory.current", "1200000\n");
+        fs.write("bundle/config.json", r#"{"linux": {"cgroupsPath": "/abc"}}"#);
+        let shim = StuckShim::new("fallback-stuck");
+        shim.connect("fallback-stuck", RuntimeHandler::default());
+
+        let start = Instant::now();
+        let stats = fs
+            .cgroupfs()
+            .stats_with_fallback(
+                "fallback-stuck",
+                &fs.dir.join("bundle").to_string_lossy(),
+                Duration::from_millis(200),
+            )
+            .unwrap();
+        // the shim had until its deadline
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert!(stats.fallback);
+        assert_eq!(stats.metrics.normalize().memory.usage.usage, 1200000);
+        del_conn("fallback-stuck");
+    }
+
+    #[test]
+    fn test_stats_with_fallback_without_cgroup() {
+        assert!(stats_with_fallback("fallback-1", "/nonexistent-bundle").is_err());
+    }
+}
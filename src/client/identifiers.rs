@@ -0,0 +1,93 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Container ids and namespaces as containerd's identifiers package accepts
+//! them: alphanumeric components joined by single `.`, `_` or `-`
+//! separators, `^[A-Za-z0-9]+(?:[._-](?:[A-Za-z0-9]+))*$`, at most 76
+//! characters.
+
+use super::error::{Error, Result};
+
+pub const MAX_ID_LENGTH: usize = 76;
+
+fn is_separator(c: char) -> bool {
+    c == '.' || c == '_' || c == '-'
+}
+
+pub fn validate(id: &str) -> Result<()> {
+    check("container id", id)
+}
+
+pub fn validate_namespace(namespace: &str) -> Result<()> {
+    check("namespace", namespace)
+}
+
+fn check(what: &str, id: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(Error::InvalidArgument(format!("invalid {} {:?}: {}", what, id, reason)));
+    if id.is_empty() {
+        return invalid("must not be empty");
+    }
+    if id.len() > MAX_ID_LENGTH {
+        return invalid(&format!("longer than {} characters", MAX_ID_LENGTH));
+    }
+    if let Some(c) = id.chars().find(|c| !c.is_ascii_alphanumeric() && !is_separator(*c)) {
+        return invalid(&format!("{:?} is not allowed", c));
+    }
+    if id.starts_with(is_separator) || id.ends_with(is_separator) {
+        return invalid("must start and end with a letter or digit");
+    }
+    if id.chars().zip(id.chars().skip(1)).any(|(a, b)| is_separator(a) && is_separator(b)) {
+        return invalid("separators must not follow each other");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let valid = [
+            "a",
+            "12345",
+            "abc-def_ghi.jkl",
+            "f8a8f71e0a4b7d2d5b1c0e3f6a9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a3f2e1d",
+            &"a".repeat(MAX_ID_LENGTH),
+        ];
+        for id in valid.iter() {
+            assert!(validate(id).is_ok(), "{:?} should be valid", id);
+        }
+
+        let invalid = [
+            "",
+            "-abc",
+            ".abc",
+            "abc_",
+            "a--b",
+            "a._b",
+            "a b",
+            "a/b",
+            "../a",
+            "ä",
+            "a\n",
+            &"a".repeat(MAX_ID_LENGTH + 1),
+        ];
+        for id in invalid.iter() {
+            assert!(
+                matches!(validate(id), Err(Error::InvalidArgument(_))),
+                "{:?} should be invalid",
+                id
+            );
+        }
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! ttrpc metadata sent with every shim call: the containerd namespace, when
+//! one is set, and caller supplied key/values.
+
+use super::error::{Error, Result};
+use super::identifiers;
+use super::trace::{REQUEST_ID_HEADER, TRACEPARENT_HEADER};
+use std::collections::HashMap;
+
+/// Header of the namespace as containerd's gRPC API names it.
+pub const NAMESPACE_HEADER: &str = "containerd-namespace";
+/// Header of the namespace as containerd sends it over ttrpc, the one
+/// shims built on containerd's namespaces package read.
+pub const TTRPC_NAMESPACE_HEADER: &str = "containerd-namespace-ttrpc";
+/// containerd's namespace when none is given.
+pub const DEFAULT_NAMESPACE: &str = "default";
+
+/// Without a namespace no namespace headers are sent, shims then stay in the
+/// namespace they were started with.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Metadata {
+    namespace: Option<String>,
+    values: HashMap<String, Vec<String>>,
+}
+
+// ttrpc lowercases keys like gRPC does, so only lowercase keys are taken
+fn validate_key(key: &str) -> Result<()> {
+    let invalid = |reason: &str| Err(Error::InvalidArgument(format!("invalid metadata key {:?}: {}", key, reason)));
+    if key.is_empty() {
+        return invalid("must not be empty");
+    }
+    if let Some(c) = key
+        .chars()
+        .find(|c| !c.is_ascii_lowercase() && !c.is_ascii_digit() && !"-_.".contains(*c))
+    {
+        return invalid(&format!("{:?} is not allowed", c));
+    }
+    if key == NAMESPACE_HEADER || key == TTRPC_NAMESPACE_HEADER {
+        return invalid("set the namespace instead");
+    }
+    if key == TRACEPARENT_HEADER || key == REQUEST_ID_HEADER {
+        return invalid("set the trace scope instead");
+    }
+    Ok(())
+}
+
+impl Metadata {
+    /// Metadata of calls in `namespace`, or without namespace headers when
+    /// it is empty.
+    pub fn new(namespace: &str) -> Result<Metadata> {
+        if namespace.is_empty() {
+            return Ok(Metadata::default());
+        }
+        identifiers::validate_namespace(namespace)?;
+        Ok(Metadata {
+            namespace: Some(namespace.to_string()),
+            values: HashMap::new(),
+        })
+    }
+
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.as_deref()
+    }
+
+    /// Appends `value` to the values of `key`.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        validate_key(key)?;
+        if value.contains('\0') {
+            return Err(Error::InvalidArgument(format!(
+                "metadata value of {:?} contains NUL",
+                key
+            )));
+        }
+        self.values.entry(key.to_string()).or_default().push(value.to_string());
+        Ok(())
+    }
+
+    /// Copy with the values of `other` replacing those of the same keys.
+    pub fn merged(&self, other: &Metadata) -> Metadata {
+        let mut merged = self.clone();
+        merged.values.extend(other.values.clone());
+        merged
+    }
+
+    /// The metadata of a ttrpc context, namespace headers included.
+    pub fn to_context(&self) -> HashMap<String, Vec<String>> {
+        let mut md = self.values.clone();
+        if let Some(ref namespace) = self.namespace {
+            for header in [NAMESPACE_HEADER, TTRPC_NAMESPACE_HEADER] {
+                md.insert(header.to_string(), vec![namespace.clone()]);
+            }
+        }
+        md
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let md = Metadata::new("").unwrap();
+        assert_eq!(md, Metadata::default());
+        assert!(md.namespace().is_none());
+        assert!(md.to_context().is_empty());
+
+        let mut conn = Metadata::new("k8s.io").unwrap();
+        conn.add("x-tenant", "a").unwrap();
+        conn.add("x-tenant", "b").unwrap();
+        conn.add("x-zone", "z1").unwrap();
+        let mut call = Metadata::default();
+        call.add("x-zone", "z2").unwrap();
+        let md = conn.merged(&call).to_context();
+        assert_eq!(md[NAMESPACE_HEADER], vec!["k8s.io"]);
+        assert_eq!(md[TTRPC_NAMESPACE_HEADER], vec!["k8s.io"]);
+        assert_eq!(md["x-tenant"], vec!["a", "b"]);
+        assert_eq!(md["x-zone"], vec!["z2"]);
+
+        assert!(matches!(Metadata::new("a/b"), Err(Error::InvalidArgument(_))));
+        let mut md = Metadata::default();
+        for key in ["", "X-Tenant", "a b", NAMESPACE_HEADER, TRACEPARENT_HEADER] {
+            assert!(
+                matches!(md.add(key, "v"), Err(Error::InvalidArgument(_))),
+                "{:?} should be refused",
+                key
+            );
+        }
+        assert!(md.add("key", "a\0b").is_err());
+    }
+}
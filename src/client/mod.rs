@@ -12,4 +12,26 @@
 
 #[macro_use]
 pub mod error;
+#[allow(clippy::module_inception)]
 pub mod client;
+pub mod batch;
+pub mod bundle;
+pub mod cache;
+pub mod cgroup;
+pub mod cleanup;
+pub mod derived;
+pub mod fallback;
+pub mod identifiers;
+pub mod metadata;
+pub mod network;
+pub mod options;
+pub mod psi;
+pub mod runtime;
+pub mod spec;
+pub mod stats;
+pub mod subscribe;
+#[cfg(test)]
+pub mod testutil;
+pub mod top;
+pub mod trace;
+pub mod wait;
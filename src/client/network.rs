@@ -0,0 +1,100 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Interface counters of a task's network namespace, read through procfs so
+//! the namespace need not be entered.
+
+use super::error::Result;
+use crate::protocols::metrics::NetworkStat;
+use std::path::Path;
+
+const LOOPBACK: &str = "lo";
+
+/// Counters of `/proc/<pid>/net/dev`, which shows the namespace of `pid`.
+pub fn parse_net_dev(data: &str) -> Vec<NetworkStat> {
+    // two header lines, then `name: 8 receive and 8 transmit counters`
+    data.lines()
+        .skip(2)
+        .filter_map(|line| {
+            let (name, counters) = line.split_once(':')?;
+            let name = name.trim();
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .map(|c| c.parse().ok())
+                .collect::<Option<_>>()?;
+            if name == LOOPBACK || counters.len() < 16 {
+                return None;
+            }
+            let mut stat = NetworkStat::new();
+            stat.name = name.to_string();
+            stat.rx_bytes = counters[0];
+            stat.rx_packets = counters[1];
+            stat.rx_errors = counters[2];
+            stat.rx_dropped = counters[3];
+            stat.tx_bytes = counters[8];
+            stat.tx_packets = counters[9];
+            stat.tx_errors = counters[10];
+            stat.tx_dropped = counters[11];
+            Some(stat)
+        })
+        .collect()
+}
+
+/// Counters of the network namespace of `pid`, see `parse_net_dev`.
+pub fn task_network(proc_root: &Path, pid: u32) -> Result<Vec<NetworkStat>> {
+    let data = std::fs::read_to_string(proc_root.join(pid.to_string()).join("net").join("dev"))?;
+    Ok(parse_net_dev(&data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo:    1400      14    0    0    0     0          0         0     1400      14    0    0    0     0       0          0
+  eth0: 1016282    2419    1    2    0     0          0         0   203488    2121    3    4    0     0       0          0
+  net1:      90       1    0    0    0     0          0         0        0       0    0    0    0     0       0          0
+";
+
+    #[test]
+    fn test_parse_net_dev() {
+        let stats = parse_net_dev(NET_DEV);
+        assert_eq!(stats.len(), 2);
+        let eth0 = &stats[0];
+        assert_eq!(eth0.name, "eth0");
+        assert_eq!(
+            (eth0.rx_bytes, eth0.rx_packets, eth0.rx_errors, eth0.rx_dropped),
+            (1016282, 2419, 1, 2)
+        );
+        assert_eq!(
+            (eth0.tx_bytes, eth0.tx_packets, eth0.tx_errors, eth0.tx_dropped),
+            (203488, 2121, 3, 4)
+        );
+        assert_eq!(stats[1].name, "net1");
+        assert!(parse_net_dev("").is_empty());
+    }
+
+    #[test]
+    fn test_task_network() {
+        let dir = TempDir::new("netns");
+        let proc_root = dir.path();
+        dir.write("42/net/dev", NET_DEV);
+
+        let stats = task_network(proc_root, 42).unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!((stats[0].name.as_str(), stats[0].rx_bytes), ("eth0", 1016282));
+        assert!(task_network(proc_root, 43).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use super::runtime::RuntimeHandler;
+use crate::protocols;
+use crate::protocols::typeurl;
+use protobuf::well_known_types::any::Any;
+use serde::Deserialize;
+
+pub use crate::protocols::typeurl::{RUNC_OPTIONS_TYPE, RUNTIME_OPTIONS_TYPE};
+
+/// Shim options of runc based shims, sent as `CreateTaskRequest.options`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RuncOptions {
+    pub no_pivot_root: bool,
+    pub no_new_keyring: bool,
+    pub shim_cgroup: String,
+    pub io_uid: u32,
+    pub io_gid: u32,
+    pub binary_name: String,
+    pub root: String,
+    pub criu_path: String,
+    pub systemd_cgroup: bool,
+    pub criu_image_path: String,
+    pub criu_work_path: String,
+}
+
+impl RuncOptions {
+    pub fn to_any(&self) -> Result<Any> {
+        let mut options = protocols::oci::Options::new();
+        options.no_pivot_root = self.no_pivot_root;
+        options.no_new_keyring = self.no_new_keyring;
+        options.shim_cgroup = self.shim_cgroup.clone();
+        options.io_uid = self.io_uid;
+        options.io_gid = self.io_gid;
+        options.binary_name = self.binary_name.clone();
+        options.root = self.root.clone();
+        options.criu_path = self.criu_path.clone();
+        options.systemd_cgroup = self.systemd_cgroup;
+        options.criu_image_path = self.criu_image_path.clone();
+        options.criu_work_path = self.criu_work_path.clone();
+        typeurl::marshal(&options)
+    }
+}
+
+/// CRI runtime options of VM runtimes such as Kata, naming the configuration
+/// file the shim uses for one container.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RuntimeOptions {
+    pub type_url: String,
+    pub config_path: String,
+}
+
+impl RuntimeOptions {
+    pub fn to_any(&self) -> Result<Any> {
+        let mut options = protocols::runtimeoptions::Options::new();
+        options.type_url = self.type_url.clone();
+        options.config_path = self.config_path.clone();
+        typeurl::marshal(&options)
+    }
+}
+
+/// A rootfs mount the shim performs itself, e.g. an overlay or a block device
+/// a VM runtime hot-plugs.
+#[derive(Clone, Debug, Default)]
+pub struct Mount {
+    pub r#type: String,
+    pub source: String,
+    pub target: String,
+    pub options: Vec<String>,
+}
+
+impl Mount {
+    fn to_proto(&self) -> protocols::mount::Mount {
+        let mut mount = protocols::mount::Mount::new();
+        mount.type_ = self.r#type.clone();
+        mount.source = self.source.clone();
+        mount.target = self.target.clone();
+        mount.options = self.options.clone();
+        mount
+    }
+}
+
+/// Optional parts of a create request.
+#[derive(Clone, Debug, Default)]
+pub struct CreateOptions {
+    /// Shim options, the runtime handler's options are used when `None`.
+    pub options: Option<Any>,
+    pub rootfs: Vec<Mount>,
+}
+
+impl CreateOptions {
+    pub(crate) fn rootfs(&self) -> Vec<protocols::mount::Mount> {
+        self.rootfs.iter().map(|m| m.to_proto()).collect()
+    }
+}
+
+/// Create options configured for a runtime handler, `None` if the handler has
+/// no `options_type`.
+pub fn runtime_options(handler: &RuntimeHandler) -> Result<Option<Any>> {
+    let fields = serde_json::Value::Object(handler.options.clone());
+    if handler.options_type.is_empty() {
+        return Ok(None);
+    }
+    match typeurl::resolve(&handler.options_type) {
+        Ok(RUNC_OPTIONS_TYPE) => {
+            let options: RuncOptions = serde_json::from_value(fields)
+                .map_err(|e| Error::InvalidArgument(format!("bad runc options: {}", e)))?;
+            options.to_any().map(Some)
+        }
+        Ok(RUNTIME_OPTIONS_TYPE) => {
+            let mut options: RuntimeOptions = serde_json::from_value(fields)
+                .map_err(|e| Error::InvalidArgument(format!("bad runtime options: {}", e)))?;
+            if options.config_path.is_empty() {
+                options.config_path = handler.config_path.clone();
+            }
+            options.to_any().map(Some)
+        }
+        _ => Err(Error::InvalidArgument(format!(
+            "unsupported options type {}",
+            handler.options_type
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::Message;
+
+    #[test]
+    fn test_runc_options_to_any() {
+        let options = RuncOptions {
+            binary_name: "runc".to_string(),
+            systemd_cgroup: true,
+            io_uid: 1000,
+            ..Default::default()
+        };
+        let any = options.to_any().unwrap();
+        assert_eq!(any.type_url, RUNC_OPTIONS_TYPE);
+        // io_uid = 1000, binary_name = "runc", systemd_cgroup = true
+        assert_eq!(
+            any.value,
+            vec![0x20, 0xe8, 0x07, 0x32, 0x04, b'r', b'u', b'n', b'c', 0x48, 0x01]
+        );
+    }
+
+    #[test]
+    fn test_runtime_options_to_any() {
+        let options = RuntimeOptions {
+            type_url: String::new(),
+            config_path: "/etc/kata-containers/configuration-qemu.toml".to_string(),
+        };
+        let any = options.to_any().unwrap();
+
+        // containerd's CRI plugin sends the handler's ConfigPath as this Any,
+        // reproduce with containerd v1.7:
+        //
+        //   any, _ := typeurl.MarshalAny(&runtimeoptions.Options{
+        //       ConfigPath: "/etc/kata-containers/configuration-qemu.toml",
+        //   })
+        //   b, _ := proto.Marshal(protobuf.FromAny(any))
+        //   fmt.Printf("% x\n", b)
+        let expected = [
+            0x0a, 0x19, 0x72, 0x75, 0x6e, 0x74, 0x69, 0x6d, 0x65, 0x6f, 0x70, 0x74,
+            0x69, 0x6f, 0x6e, 0x73, 0x2e, 0x76, 0x31, 0x2e, 0x4f, 0x70, 0x74, 0x69,
+            0x6f, 0x6e, 0x73, 0x12, 0x2e, 0x12, 0x2c, 0x2f, 0x65, 0x74, 0x63, 0x2f,
+            0x6b, 0x61, 0x74, 0x61, 0x2d, 0x63, 0x6f, 0x6e, 0x74, 0x61, 0x69, 0x6e,
+            0x65, 0x72, 0x73, 0x2f, 0x63, 0x6f, 0x6e, 0x66, 0x69, 0x67, 0x75, 0x72,
+            0x61, 0x74, 0x69, 0x6f, 0x6e, 0x2d, 0x71, 0x65, 0x6d, 0x75, 0x2e, 0x74,
+            0x6f, 0x6d, 0x6c,
+        ];
+        assert_eq!(any.write_to_bytes().unwrap(), expected.to_vec());
+
+        let options = RuntimeOptions {
+            type_url: "io.containerd.kata.v2.options".to_string(),
+            config_path: "/k.toml".to_string(),
+        };
+        let mut expected = vec![0x0a, 0x1d];
+        expected.extend_from_slice(b"io.containerd.kata.v2.options");
+        expected.extend_from_slice(&[0x12, 0x07]);
+        expected.extend_from_slice(b"/k.toml");
+        assert_eq!(options.to_any().unwrap().value, expected);
+    }
+
+    #[test]
+    fn test_create_options_rootfs() {
+        let options = CreateOptions {
+            options: None,
+            rootfs: vec![Mount {
+                r#type: "overlay".to_string(),
+                source: "overlay".to_string(),
+                target: String::new(),
+                options: vec![
+                    "lowerdir=/l".to_string(),
+                    "upperdir=/u".to_string(),
+                    "workdir=/w".to_string(),
+                ],
+            }],
+        };
+        let rootfs = options.rootfs();
+        assert_eq!(rootfs.len(), 1);
+        assert_eq!(rootfs[0].type_, "overlay");
+        assert_eq!(rootfs[0].options[2], "workdir=/w");
+    }
+
+    #[test]
+    fn test_runtime_options() {
+        let mut handler = RuntimeHandler::default();
+        assert!(runtime_options(&handler).unwrap().is_none());
+
+        handler.options_type = RUNC_OPTIONS_TYPE.to_string();
+        handler.options.insert(
+            "root".to_string(),
+            serde_json::Value::String("/run/runc".to_string()),
+        );
+        let any = runtime_options(&handler).unwrap().unwrap();
+        let options = protocols::oci::Options::parse_from_bytes(&any.value).unwrap();
+        assert_eq!(options.root, "/run/runc");
+
+        handler
+            .options
+            .insert("io_uid".to_string(), serde_json::Value::Bool(true));
+        assert!(runtime_options(&handler).is_err());
+
+        handler.options_type = RUNTIME_OPTIONS_TYPE.to_string();
+        handler.options.clear();
+        handler.config_path = "/etc/kata-containers/configuration.toml".to_string();
+        let any = runtime_options(&handler).unwrap().unwrap();
+        assert_eq!(any.type_url, RUNTIME_OPTIONS_TYPE);
+        let options = protocols::runtimeoptions::Options::parse_from_bytes(&any.value).unwrap();
+        assert_eq!(options.config_path, "/etc/kata-containers/configuration.toml");
+
+        handler.options_type = "unknown.Options".to_string();
+        assert!(runtime_options(&handler).is_err());
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use crate::protocols::metrics_v2;
+use std::path::Path;
+use std::str::FromStr;
+
+/// One line of a `*.pressure` file, averages in percent and total stall time
+/// in microseconds.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PsiData {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: u64,
+}
+
+impl PsiData {
+    fn from_proto(in_obj: &metrics_v2::PSIData) -> PsiData {
+        PsiData {
+            avg10: in_obj.avg10,
+            avg60: in_obj.avg60,
+            avg300: in_obj.avg300,
+            total: in_obj.total,
+        }
+    }
+
+    fn parse(fields: &str) -> Result<PsiData> {
+        let mut data = PsiData::default();
+        for field in fields.split_whitespace() {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or_else(|| other!(format!("bad pressure field {}", field)))?;
+            match key {
+                "avg10" => data.avg10 = parse_value(field, value)?,
+                "avg60" => data.avg60 = parse_value(field, value)?,
+                "avg300" => data.avg300 = parse_value(field, value)?,
+                "total" => data.total = parse_value(field, value)?,
+                _ => {}
+            }
+        }
+        Ok(data)
+    }
+}
+
+fn parse_value<T: FromStr>(field: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| other!(format!("bad pressure value {}", field)))
+}
+
+/// Pressure stall information of one resource. `full` is zero for CPU on
+/// kernels before 5.13.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PsiStats {
+    pub some: PsiData,
+    pub full: PsiData,
+}
+
+impl PsiStats {
+    fn from_proto(in_obj: &metrics_v2::PSIStats) -> PsiStats {
+        PsiStats {
+            some: PsiData::from_proto(&in_obj.some),
+            full: PsiData::from_proto(&in_obj.full),
+        }
+    }
+
+    /// Parses the content of a cgroup v2 `*.pressure` file.
+    pub fn parse(data: &str) -> Result<PsiStats> {
+        let mut stats = PsiStats::default();
+        for line in data.lines() {
+            match line.split_once(' ') {
+                Some(("some", fields)) => stats.some = PsiData::parse(fields)?,
+                Some(("full", fields)) => stats.full = PsiData::parse(fields)?,
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+/// CPU, memory and IO pressure of a container, `None` where it is not
+/// available.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Pressure {
+    pub cpu: Option<PsiStats>,
+    pub memory: Option<PsiStats>,
+    pub io: Option<PsiStats>,
+}
+
+impl Pressure {
+    /// Pressure reported by the shim in cgroup v2 metrics, `None` if the shim
+    /// sent none.
+    pub fn from_metrics(m: &metrics_v2::Metrics) -> Option<Pressure> {
+        let pressure = Pressure {
+            cpu: m.cpu.psi.as_ref().map(PsiStats::from_proto),
+            memory: m.memory.psi.as_ref().map(PsiStats::from_proto),
+            io: m.io.psi.as_ref().map(PsiStats::from_proto),
+        };
+        if pressure.is_empty() {
+            None
+        } else {
+            Some(pressure)
+        }
+    }
+
+    /// Reads the `*.pressure` files of a cgroup v2 directory, files that are
+    /// missing, e.g. with PSI disabled, are left `None`.
+    pub fn read(cgroup_dir: &Path) -> Result<Pressure> {
+        let read = |name: &str| -> Result<Option<PsiStats>> {
+            match std::fs::read_to_string(cgroup_dir.join(name)) {
+                Ok(data) => PsiStats::parse(&data).map(Some),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                Err(e) => Err(Error::from(e)),
+            }
+        };
+        Ok(Pressure {
+            cpu: read("cpu.pressure")?,
+            memory: read("memory.pressure")?,
+            io: read("io.pressure")?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cpu.is_none() && self.memory.is_none() && self.io.is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    const MEMORY_PRESSURE: &str = "\
+some avg10=1.53 avg60=0.87 avg300=0.22 total=2130495
+full avg10=0.91 avg60=0.42 avg300=0.10 total=1042783
+";
+
+    #[test]
+    fn test_parse_psi() {
+        let stats = PsiStats::parse(MEMORY_PRESSURE).unwrap();
+        assert_eq!(stats.some.avg10, 1.53);
+        assert_eq!(stats.some.avg300, 0.22);
+        assert_eq!(stats.some.total, 2130495);
+        assert_eq!(stats.full.avg60, 0.42);
+        assert_eq!(stats.full.total, 1042783);
+
+        // cpu.pressure before 5.13 has no full line
+        let stats = PsiStats::parse("some avg10=0.00 avg60=0.00 avg300=0.00 total=0\n").unwrap();
+        assert_eq!(stats.full, PsiData::default());
+
+        assert!(PsiStats::parse("some avg10=x avg60=0.00 avg300=0.00 total=0").is_err());
+        assert!(PsiStats::parse("some avg10").is_err());
+    }
+
+    #[test]
+    fn test_read_pressure() {
+        let dir = TempDir::new("psi");
+        dir.write("memory.pressure", MEMORY_PRESSURE);
+        dir.write(
+            "io.pressure",
+            "some avg10=4.00 avg60=2.00 avg300=1.00 total=99\nfull avg10=3.00 avg60=1.50 avg300=0.50 total=77\n",
+        );
+
+        let pressure = Pressure::read(dir.path()).unwrap();
+        assert!(pressure.cpu.is_none());
+        assert_eq!(pressure.memory.unwrap().full.total, 1042783);
+        assert_eq!(pressure.io.unwrap().some.avg10, 4.0);
+    }
+
+    #[test]
+    fn test_pressure_from_metrics() {
+        let mut m = metrics_v2::Metrics::new();
+        assert!(Pressure::from_metrics(&m).is_none());
+
+        let psi = m.cpu.mut_or_insert_default().psi.mut_or_insert_default();
+        psi.some.mut_or_insert_default().avg60 = 12.5;
+        psi.full.mut_or_insert_default().total = 100;
+        let pressure = Pressure::from_metrics(&m).unwrap();
+        let cpu = pressure.cpu.unwrap();
+        assert_eq!(cpu.some.avg60, 12.5);
+        assert_eq!(cpu.full.total, 100);
+        assert!(pressure.memory.is_none());
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::RwLock;
+use std::time::Duration;
+
+/// Key of `timeouts` used when a call has no timeout of its own.
+pub const DEFAULT_TIMEOUT_KEY: &str = "default";
+
+// Calls that block until a process exits only time out when configured
+// explicitly.
+const UNBOUNDED_CALLS: [&str; 1] = ["wait"];
+
+/// One runtime handler, e.g. `kata` or `runc`, as configured in the runtime
+/// config file:
+///
+/// ```json
+/// {
+///     "runtimes": {
+///         "kata": {
+///             "runtime_type": "io.containerd.kata.v2",
+///             "shim_binary": "/usr/bin/containerd-shim-kata-v2",
+///             "options_type": "io.containerd.kata.v2.options",
+///             "options": {},
+///             "config_path": "/etc/kata-containers/configuration.toml",
+///             "timeouts": {"default": 0, "create": 60000, "cleanup": 10000},
+///             "cache_ttls": {"stats": 1000, "state": 500},
+///             "network_stats": "shim",
+///             "pressure": "shim",
+///             "check_bundle": false,
+///             "privileged_without_host_devices": true
+///         }
+///     }
+/// }
+/// ```
+///
+/// Timeouts are in milliseconds and keyed by call name, 0 means no timeout.
+/// Cache TTLs are in milliseconds too, `stats` and `state` results are reused
+/// for that long, 0 or no entry turns the cache off.
+///
+/// `network_stats` selects where network stats come from, see
+/// `NetworkStatsSource`, and `pressure` where pressure stall information
+/// does, see `PressureSource`.
+///
+/// `check_bundle` has create check the bundle's config.json and that its
+/// root exists on the host before the shim is called, see `bundle::inspect`.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct RuntimeHandler {
+    pub runtime_type: String,
+    pub shim_binary: String,
+    pub options_type: String,
+    pub options: serde_json::Map<String, serde_json::Value>,
+    pub config_path: String,
+    pub timeouts: HashMap<String, u64>,
+    pub cache_ttls: HashMap<String, u64>,
+    pub privileged_without_host_devices: bool,
+    pub network_stats: NetworkStatsSource,
+    pub pressure: PressureSource,
+    pub check_bundle: bool,
+}
+
+/// Source of a container's network stats.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NetworkStatsSource {
+    /// Whatever the shim reports, the only choice for VM based runtimes
+    /// whose task pids are not host pids.
+    #[default]
+    Shim,
+    /// The task's network namespace read through the host's procfs.
+    Netns,
+    /// The shim's stats, or the network namespace ones when it sent none.
+    Auto,
+}
+
+/// Source of a container's pressure stall information.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PressureSource {
+    /// Whatever the shim reports, the only choice for VM based runtimes
+    /// whose cgroups are not on the host.
+    #[default]
+    Shim,
+    /// The `*.pressure` files of the task's cgroup v2 group on the host.
+    Cgroupfs,
+    /// The shim's pressure, or the cgroupfs one when it sent none.
+    Auto,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RuntimeConfig {
+    #[serde(default)]
+    runtimes: HashMap<String, RuntimeHandler>,
+}
+
+lazy_static! {
+    static ref RUNTIMES: RwLock<HashMap<String, RuntimeHandler>> = RwLock::new(HashMap::new());
+}
+
+/// Resolves the shim binary for a runtime name the same way containerd does:
+/// `io.containerd.kata.v2` becomes `containerd-shim-kata-v2`, absolute paths are
+/// used as they are.
+pub fn binary_name(runtime: &str) -> Result<String> {
+    if Path::new(runtime).is_absolute() {
+        return Ok(runtime.to_string());
+    }
+    let parts: Vec<&str> = runtime.split('.').collect();
+    if parts.len() < 2 || parts.iter().any(|p| p.is_empty()) {
+        return Err(Error::InvalidArgument(format!(
+            "invalid runtime name {}",
+            runtime
+        )));
+    }
+    Ok(format!(
+        "containerd-shim-{}-{}",
+        parts[parts.len() - 2],
+        parts[parts.len() - 1]
+    ))
+}
+
+impl RuntimeHandler {
+    fn from_runtime_type(runtime_type: &str) -> Self {
+        RuntimeHandler {
+            runtime_type: runtime_type.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Shim binary to run, the configured one or the containerd name derived
+    /// from `runtime_type`.
+    pub fn binary(&self) -> Result<String> {
+        if !self.shim_binary.is_empty() {
+            return Ok(self.shim_binary.clone());
+        }
+        binary_name(&self.runtime_type)
+    }
+
+    /// Timeout of the named call, falling back to the `default` entry. `None`
+    /// means the call waits forever.
+    pub fn timeout(&self, call: &str) -> Option<Duration> {
+        self.timeouts
+            .get(call)
+            .or_else(|| {
+                if UNBOUNDED_CALLS.contains(&call) {
+                    None
+                } else {
+                    self.timeouts.get(DEFAULT_TIMEOUT_KEY)
+                }
+            })
+            .filter(|ms| **ms != 0)
+            .map(|ms| Duration::from_millis(*ms))
+    }
+
+    /// How long results of the named call may be reused.
+    pub fn cache_ttl(&self, call: &str) -> Duration {
+        Duration::from_millis(self.cache_ttls.get(call).copied().unwrap_or(0))
+    }
+}
+
+fn parse_runtime_config(data: &str) -> Result<HashMap<String, RuntimeHandler>> {
+    let config: RuntimeConfig = serde_json::from_str(data)
+        .map_err(|e| Error::InvalidArgument(format!("bad runtime config: {}", e)))?;
+    for (name, handler) in config.runtimes.iter() {
+        if handler.runtime_type.is_empty() {
+            return Err(Error::InvalidArgument(format!(
+                "runtime {} has no runtime_type",
+                name
+            )));
+        }
+    }
+    Ok(config.runtimes)
+}
+
+/// Replaces the registered runtime handlers with the ones in a JSON config file.
+pub fn load_runtime_config(path: &str) -> Result<()> {
+    let data = std::fs::read_to_string(path)?;
+    let runtimes = parse_runtime_config(&data)?;
+    *RUNTIMES.write().unwrap() = runtimes;
+    Ok(())
+}
+
+/// Registers or replaces a single runtime handler.
+pub fn register_runtime(name: &str, handler: RuntimeHandler) {
+    RUNTIMES
+        .write()
+        .unwrap()
+        .insert(name.to_string(), handler);
+}
+
+/// Looks a runtime up by handler name (`kata`) or by runtime type
+/// (`io.containerd.kata.v2`). Runtime types that are not configured get a
+/// handler with containerd defaults.
+pub fn get_runtime(runtime: &str) -> Result<RuntimeHandler> {
+    let runtimes = RUNTIMES.read().unwrap();
+    if let Some(handler) = runtimes.get(runtime) {
+        return Ok(handler.clone());
+    }
+    if let Some(handler) = runtimes.values().find(|h| h.runtime_type == runtime) {
+        return Ok(handler.clone());
+    }
+    binary_name(runtime)?;
+    Ok(RuntimeHandler::from_runtime_type(runtime))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_binary_name() {
+        assert_eq!(
+            binary_name("io.containerd.kata.v2").unwrap(),
+            "containerd-shim-kata-v2"
+        );
+        assert_eq!(
+            binary_name("io.containerd.runc.v2").unwrap(),
+            "containerd-shim-runc-v2"
+        );
+        assert_eq!(
+            binary_name("/usr/bin/containerd-shim-kata-v2").unwrap(),
+            "/usr/bin/containerd-shim-kata-v2"
+        );
+        assert!(binary_name("kata").is_err());
+        assert!(binary_name("io.containerd.").is_err());
+    }
+
+    #[test]
+    fn test_parse_runtime_config() {
+        let runtimes = parse_runtime_config(
+            r#"{
+                "runtimes": {
+                    "kata": {
+                        "runtime_type": "io.containerd.kata.v2",
+                        "config_path": "/etc/kata-containers/configuration.toml",
+                        "timeouts": {"default": 5000, "create": 0},
+                        "cache_ttls": {"stats": 1000},
+                        "privileged_without_host_devices": true
+                    },
+                    "runc": {
+                        "runtime_type": "io.containerd.runc.v2",
+                        "shim_binary": "/usr/local/bin/containerd-shim-runc-v2",
+                        "options_type": "containerd.runc.v1.Options",
+                        "options": {"systemd_cgroup": true},
+                        "network_stats": "auto",
+                        "pressure": "auto",
+                        "check_bundle": true
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let kata = &runtimes["kata"];
+        assert_eq!(kata.binary().unwrap(), "containerd-shim-kata-v2");
+        assert_eq!(kata.timeout("state"), Some(Duration::from_secs(5)));
+        assert_eq!(kata.timeout("create"), None);
+        assert_eq!(kata.timeout("wait"), None);
+        assert!(kata.privileged_without_host_devices);
+        assert_eq!(kata.cache_ttl("stats"), Duration::from_secs(1));
+        assert_eq!(kata.cache_ttl("state"), Duration::from_secs(0));
+        assert_eq!(kata.network_stats, NetworkStatsSource::Shim);
+        assert_eq!(kata.pressure, PressureSource::Shim);
+
+        let runc = &runtimes["runc"];
+        assert_eq!(runc.binary().unwrap(), "/usr/local/bin/containerd-shim-runc-v2");
+        assert_eq!(runc.timeout("state"), None);
+        assert_eq!(runc.options["systemd_cgroup"], serde_json::Value::Bool(true));
+        assert_eq!(runc.network_stats, NetworkStatsSource::Auto);
+        assert_eq!(runc.pressure, PressureSource::Auto);
+        assert!(runc.check_bundle);
+        assert!(!kata.check_bundle);
+
+        assert!(parse_runtime_config(r#"{"runtimes": {"x": {}}}"#).is_err());
+        assert!(parse_runtime_config("runtimes").is_err());
+        assert!(parse_runtime_config(
+            r#"{"runtimes": {"x": {"runtime_type": "io.containerd.runc.v2", "network_stats": "host"}}}"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_get_runtime() {
+        register_runtime(
+            "test-kata",
+            RuntimeHandler {
+                runtime_type: "io.containerd.testkata.v2".to_string(),
+                config_path: "/etc/test-kata.toml".to_string(),
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(
+            get_runtime("test-kata").unwrap().config_path,
+            "/etc/test-kata.toml"
+        );
+        assert_eq!(
+            get_runtime("io.containerd.testkata.v2").unwrap().config_path,
+            "/etc/test-kata.toml"
+        );
+        assert_eq!(
+            get_runtime("io.containerd.other.v2").unwrap().binary().unwrap(),
+            "containerd-shim-other-v2"
+        );
+        assert!(get_runtime("unknown").is_err());
+    }
+}
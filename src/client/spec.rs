@@ -0,0 +1,501 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Typed parts of the OCI runtime spec, serialized with the field names and
+//! omitted empty fields of the Go runtime-spec package.
+
+use super::error::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const CAPABILITIES: [&str; 41] = [
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+const RLIMITS: [&str; 16] = [
+    "RLIMIT_AS",
+    "RLIMIT_CORE",
+    "RLIMIT_CPU",
+    "RLIMIT_DATA",
+    "RLIMIT_FSIZE",
+    "RLIMIT_LOCKS",
+    "RLIMIT_MEMLOCK",
+    "RLIMIT_MSGQUEUE",
+    "RLIMIT_NICE",
+    "RLIMIT_NOFILE",
+    "RLIMIT_NPROC",
+    "RLIMIT_RSS",
+    "RLIMIT_RTPRIO",
+    "RLIMIT_RTTIME",
+    "RLIMIT_SIGPENDING",
+    "RLIMIT_STACK",
+];
+
+const OOM_SCORE_ADJ_RANGE: std::ops::RangeInclusive<i32> = -1000..=1000;
+
+fn invalid(field: &str, reason: &str) -> Error {
+    Error::InvalidArgument(format!("process.{}: {}", field, reason))
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// The parts of a bundle's config.json the library looks at, unknown fields
+/// are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Spec {
+    pub oci_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process: Option<Process>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub root: Option<Root>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub hostname: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<Mount>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub annotations: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub linux: Option<Linux>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Root {
+    /// Absolute or relative to the bundle.
+    pub path: String,
+    #[serde(skip_serializing_if = "is_false")]
+    pub readonly: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mount {
+    pub destination: String,
+    #[serde(rename = "type", skip_serializing_if = "String::is_empty")]
+    pub r#type: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub source: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Linux {
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub cgroups_path: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<Namespace>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Namespace {
+    /// e.g. `network` or `pid`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub path: String,
+}
+
+impl Spec {
+    pub fn from_json(data: &[u8]) -> Result<Spec> {
+        serde_json::from_slice(data)
+            .map_err(|e| Error::InvalidArgument(format!("bad config.json: {}", e)))
+    }
+}
+
+/// `process` of the runtime spec, also the payload of an exec.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Process {
+    #[serde(skip_serializing_if = "is_false")]
+    pub terminal: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub console_size: Option<ConsoleSize>,
+    pub user: User,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    pub cwd: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rlimits: Vec<Rlimit>,
+    #[serde(skip_serializing_if = "is_false")]
+    pub no_new_privileges: bool,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub apparmor_profile: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub oom_score_adj: Option<i32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub selinux_label: String,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConsoleSize {
+    pub height: u32,
+    pub width: u32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub umask: Option<u32>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub additional_gids: Vec<u32>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub username: String,
+}
+
+/// Capability sets, names such as `CAP_KILL`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Capabilities {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub bounding: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub effective: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub inheritable: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub permitted: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub ambient: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rlimit {
+    /// e.g. `RLIMIT_NOFILE`
+    #[serde(rename = "type")]
+    pub r#type: String,
+    pub hard: u64,
+    pub soft: u64,
+}
+
+impl Process {
+    pub fn from_json(data: &[u8]) -> Result<Process> {
+        serde_json::from_slice(data)
+            .map_err(|e| Error::InvalidArgument(format!("bad process spec: {}", e)))
+    }
+
+    pub fn to_json(&self) -> Result<Vec<u8>> {
+        serde_json::to_vec(self).map_err(other_error!(e, "failed to encode process spec:"))
+    }
+
+    /// Checks what a runtime would reject, the error names the first bad
+    /// field, e.g. `process.env[2]`.
+    pub fn validate(&self) -> Result<()> {
+        if self.args.is_empty() {
+            return Err(invalid("args", "must not be empty"));
+        }
+        if self.args[0].is_empty() {
+            return Err(invalid("args[0]", "must not be empty"));
+        }
+        if !self.cwd.starts_with('/') {
+            return Err(invalid("cwd", &format!("{:?} is not an absolute path", self.cwd)));
+        }
+        for (i, entry) in self.env.iter().enumerate() {
+            match entry.split_once('=') {
+                Some((key, _)) if !key.is_empty() && !entry.contains('\0') => {}
+                _ => {
+                    return Err(invalid(
+                        &format!("env[{}]", i),
+                        &format!("{:?} is not KEY=value", entry),
+                    ))
+                }
+            }
+        }
+        if let Some(caps) = &self.capabilities {
+            let sets = [
+                ("bounding", &caps.bounding),
+                ("effective", &caps.effective),
+                ("inheritable", &caps.inheritable),
+                ("permitted", &caps.permitted),
+                ("ambient", &caps.ambient),
+            ];
+            for (set, names) in sets.iter() {
+                if let Some((i, name)) = names
+                    .iter()
+                    .enumerate()
+                    .find(|(_, name)| !CAPABILITIES.contains(&name.as_str()))
+                {
+                    return Err(invalid(
+                        &format!("capabilities.{}[{}]", set, i),
+                        &format!("unknown capability {:?}", name),
+                    ));
+                }
+            }
+        }
+        for (i, rlimit) in self.rlimits.iter().enumerate() {
+            let field = format!("rlimits[{}]", i);
+            if !RLIMITS.contains(&rlimit.r#type.as_str()) {
+                return Err(invalid(&field, &format!("unknown rlimit {:?}", rlimit.r#type)));
+            }
+            if rlimit.soft > rlimit.hard {
+                return Err(invalid(
+                    &field,
+                    &format!("soft limit {} is above hard limit {}", rlimit.soft, rlimit.hard),
+                ));
+            }
+            if self.rlimits[..i].iter().any(|r| r.r#type == rlimit.r#type) {
+                return Err(invalid(&field, &format!("duplicate rlimit {}", rlimit.r#type)));
+            }
+        }
+        if let Some(adj) = self.oom_score_adj {
+            if !OOM_SCORE_ADJ_RANGE.contains(&adj) {
+                return Err(invalid("oomScoreAdj", &format!("{} is out of [-1000, 1000]", adj)));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_to_json() {
+        let process = Process {
+            args: vec!["sh".to_string()],
+            cwd: "/".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            String::from_utf8(process.to_json().unwrap()).unwrap(),
+            r#"{"user":{"uid":0,"gid":0},"args":["sh"],"cwd":"/"}"#
+        );
+
+        let process = Process {
+            terminal: true,
+            console_size: Some(ConsoleSize {
+                height: 24,
+                width: 80,
+            }),
+            user: User {
+                uid: 1000,
+                gid: 1000,
+                umask: Some(0o022),
+                additional_gids: vec![10],
+                username: String::new(),
+            },
+            args: vec!["top".to_string(), "-b".to_string()],
+            env: vec!["PATH=/usr/bin".to_string()],
+            cwd: "/root".to_string(),
+            capabilities: Some(Capabilities {
+                bounding: vec!["CAP_KILL".to_string()],
+                ..Default::default()
+            }),
+            rlimits: vec![Rlimit {
+                r#type: "RLIMIT_NOFILE".to_string(),
+                hard: 1024,
+                soft: 512,
+            }],
+            no_new_privileges: true,
+            apparmor_profile: "docker-default".to_string(),
+            oom_score_adj: Some(-500),
+            selinux_label: "system_u:system_r:container_t:s0".to_string(),
+        };
+        let json: serde_json::Value = serde_json::from_slice(&process.to_json().unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "terminal": true,
+                "consoleSize": {"height": 24, "width": 80},
+                "user": {"uid": 1000, "gid": 1000, "umask": 18, "additionalGids": [10]},
+                "args": ["top", "-b"],
+                "env": ["PATH=/usr/bin"],
+                "cwd": "/root",
+                "capabilities": {"bounding": ["CAP_KILL"]},
+                "rlimits": [{"type": "RLIMIT_NOFILE", "hard": 1024, "soft": 512}],
+                "noNewPrivileges": true,
+                "apparmorProfile": "docker-default",
+                "oomScoreAdj": -500,
+                "selinuxLabel": "system_u:system_r:container_t:s0"
+            })
+        );
+        assert_eq!(Process::from_json(&process.to_json().unwrap()).unwrap(), process);
+    }
+
+    #[test]
+    fn test_validate() {
+        let valid = || Process {
+            args: vec!["sh".to_string()],
+            env: vec!["PATH=/bin".to_string(), "EMPTY=".to_string()],
+            cwd: "/".to_string(),
+            capabilities: Some(Capabilities {
+                bounding: vec!["CAP_KILL".to_string(), "CAP_BPF".to_string()],
+                ..Default::default()
+            }),
+            rlimits: vec![Rlimit {
+                r#type: "RLIMIT_NOFILE".to_string(),
+                hard: 1024,
+                soft: 1024,
+            }],
+            oom_score_adj: Some(-1000),
+            ..Default::default()
+        };
+        assert!(valid().validate().is_ok());
+
+        let rlimit = |r#type: &str, hard, soft| Rlimit {
+            r#type: r#type.to_string(),
+            hard,
+            soft,
+        };
+        type Mutation = Box<dyn Fn(&mut Process)>;
+        let cases: Vec<(&str, Mutation, &str)> = vec![
+            ("no args", Box::new(|p| p.args.clear()), "process.args:"),
+            ("empty arg0", Box::new(|p| p.args[0].clear()), "process.args[0]:"),
+            ("empty cwd", Box::new(|p| p.cwd.clear()), "process.cwd:"),
+            ("relative cwd", Box::new(|p| p.cwd = "tmp".to_string()), "process.cwd:"),
+            ("env without =", Box::new(|p| p.env.push("PATH".to_string())), "process.env[2]:"),
+            ("env without key", Box::new(|p| p.env[1] = "=x".to_string()), "process.env[1]:"),
+            ("env with nul", Box::new(|p| p.env[0] = "A=\0".to_string()), "process.env[0]:"),
+            (
+                "unknown capability",
+                Box::new(|p| {
+                    let caps = p.capabilities.as_mut().unwrap();
+                    caps.ambient = vec!["CAP_KILL".to_string(), "kill".to_string()];
+                }),
+                "process.capabilities.ambient[1]:",
+            ),
+            (
+                "unknown rlimit",
+                Box::new(move |p| p.rlimits.push(rlimit("RLIMIT_FOO", 1, 1))),
+                "process.rlimits[1]:",
+            ),
+            (
+                "soft above hard",
+                Box::new(move |p| p.rlimits[0] = rlimit("RLIMIT_NOFILE", 1024, 4096)),
+                "process.rlimits[0]: soft limit",
+            ),
+            (
+                "duplicate rlimit",
+                Box::new(move |p| p.rlimits.push(rlimit("RLIMIT_NOFILE", 1, 1))),
+                "process.rlimits[1]: duplicate",
+            ),
+            ("oom score adj", Box::new(|p| p.oom_score_adj = Some(1001)), "process.oomScoreAdj:"),
+        ];
+        for (name, mutate, want) in cases {
+            let mut process = valid();
+            mutate(&mut process);
+            match process.validate() {
+                Err(Error::InvalidArgument(msg)) => {
+                    assert!(msg.starts_with(want), "{}: {:?} does not start with {:?}", name, msg, want)
+                }
+                other => panic!("{}: got {:?}", name, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_spec_from_json() {
+        let spec = Spec::from_json(
+            br#"{"ociVersion": "1.0.2",
+                "process": {"user": {"uid": 0, "gid": 0}, "args": ["/pause"], "cwd": "/"},
+                "root": {"path": "rootfs", "readonly": true},
+                "hostname": "pod",
+                "mounts": [{"destination": "/proc", "type": "proc", "source": "proc"}],
+                "annotations": {"io.kubernetes.cri.container-type": "sandbox"},
+                "linux": {"cgroupsPath": "/kubepods/pod1/abc",
+                          "namespaces": [{"type": "pid"}, {"type": "network", "path": "/var/run/netns/cni-1"}],
+                          "resources": {"devices": []}}}"#,
+        )
+        .unwrap();
+        assert_eq!(spec.oci_version, "1.0.2");
+        assert_eq!(spec.process.unwrap().args, vec!["/pause"]);
+        assert_eq!(spec.root.as_ref().unwrap().path, "rootfs");
+        assert!(spec.root.unwrap().readonly);
+        assert_eq!(spec.mounts[0].r#type, "proc");
+        assert_eq!(spec.annotations["io.kubernetes.cri.container-type"], "sandbox");
+        let linux = spec.linux.unwrap();
+        assert_eq!(linux.cgroups_path, "/kubepods/pod1/abc");
+        assert_eq!(linux.namespaces[1].path, "/var/run/netns/cni-1");
+
+        assert!(matches!(
+            Spec::from_json(b"{\"root\": \"rootfs\"}"),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn test_process_from_json() {
+        // as written by runc exec --process
+        let process = Process::from_json(
+            br#"{"terminal":false,"user":{"uid":0,"gid":0},"args":["ls","-l"],
+                "env":["PATH=/bin","TERM=xterm"],"cwd":"/",
+                "capabilities":{"bounding":["CAP_CHOWN"],"effective":["CAP_CHOWN"]},
+                "noNewPrivileges":true,"commandLine":"ignored"}"#,
+        )
+        .unwrap();
+        assert_eq!(process.args, vec!["ls", "-l"]);
+        assert_eq!(process.env.len(), 2);
+        assert_eq!(process.capabilities.unwrap().effective, vec!["CAP_CHOWN"]);
+        assert!(process.no_new_privileges);
+        assert_eq!(process.oom_score_adj, None);
+
+        assert!(matches!(
+            Process::from_json(b"{\"args\": \"sh\"}"),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,707 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::error::{Error, Result};
+use super::psi::Pressure;
+use crate::protocols::metrics::{self, BlkIOEntry, BlkIOStat, Metrics};
+use crate::protocols::json;
+use crate::protocols::metrics_v2;
+use crate::protocols::typeurl;
+use protobuf::well_known_types::any::Any;
+use protobuf::Message;
+
+pub use crate::protocols::typeurl::{METRICS_V1_TYPE, METRICS_V2_TYPE};
+
+/// Version of the stats JSON envelope, bumped only for incompatible changes.
+/// New metrics appear as new keys of `metrics` without a bump.
+pub const STATS_JSON_SCHEMA_VERSION: u64 = 1;
+
+/// Metrics as reported for the container's cgroup hierarchy.
+#[derive(Clone, Debug)]
+pub enum CgroupMetrics {
+    V1(Metrics),
+    V2(metrics_v2::Metrics),
+}
+
+impl Default for CgroupMetrics {
+    fn default() -> Self {
+        CgroupMetrics::V1(Metrics::new())
+    }
+}
+
+impl CgroupMetrics {
+    /// Decodes `StatsResponse.stats` according to its type url.
+    pub fn decode(any: &Any) -> Result<CgroupMetrics> {
+        // Shims that predate cgroup v2 support may leave the type url empty.
+        if any.type_url.is_empty() {
+            return Metrics::parse_from_bytes(&any.value)
+                .map(CgroupMetrics::V1)
+                .map_err(other_error!(e, "failed to decode stats:"));
+        }
+        match typeurl::resolve(&any.type_url)? {
+            METRICS_V1_TYPE => typeurl::unmarshal(any).map(CgroupMetrics::V1),
+            METRICS_V2_TYPE => typeurl::unmarshal(any).map(CgroupMetrics::V2),
+            _ => Err(Error::InvalidArgument(format!(
+                "unsupported stats type {}",
+                any.type_url
+            ))),
+        }
+    }
+
+    pub fn cgroup_version(&self) -> u32 {
+        match self {
+            CgroupMetrics::V1(_) => 1,
+            CgroupMetrics::V2(_) => 2,
+        }
+    }
+
+    /// Pressure stall information the shim sent along, only cgroup v2
+    /// metrics carry it.
+    pub fn pressure(&self) -> Option<Pressure> {
+        match self {
+            CgroupMetrics::V1(_) => None,
+            CgroupMetrics::V2(m) => Pressure::from_metrics(m),
+        }
+    }
+
+    /// Per interface network stats the shim sent, only cgroup v1 metrics
+    /// carry them.
+    pub fn network(&self) -> &[metrics::NetworkStat] {
+        match self {
+            CgroupMetrics::V1(m) => &m.network,
+            CgroupMetrics::V2(_) => &[],
+        }
+    }
+
+    /// Serializes the metrics as they were decoded:
+    ///
+    /// ```json
+    /// {
+    ///     "schema_version": 1,
+    ///     "cgroup_version": 2,
+    ///     "type_url": "io.containerd.cgroups.v2.Metrics",
+    ///     "metrics": {"pids": {"current": 3, "limit": 4096}, "cpu": {...}, ...}
+    /// }
+    /// ```
+    ///
+    /// `metrics` follows the proto message of `type_url`, see
+    /// `protocols::json` for the mapping. Counters above `i64::MAX`, like
+    /// the `u64::MAX` of an unlimited cgroup, are decimal strings.
+    pub fn to_json(&self) -> Result<String> {
+        let (type_url, metrics) = match self {
+            CgroupMetrics::V1(m) => (METRICS_V1_TYPE, json::to_value(m)?),
+            CgroupMetrics::V2(m) => (METRICS_V2_TYPE, json::to_value(m)?),
+        };
+        let envelope = serde_json::json!({
+            "schema_version": STATS_JSON_SCHEMA_VERSION,
+            "cgroup_version": self.cgroup_version(),
+            "type_url": type_url,
+            "metrics": metrics,
+        });
+        serde_json::to_string(&envelope).map_err(other_error!(e, "failed to encode stats:"))
+    }
+
+    pub fn from_json(data: &str) -> Result<CgroupMetrics> {
+        let envelope: serde_json::Value = serde_json::from_str(data)
+            .map_err(|e| Error::InvalidArgument(format!("bad stats JSON: {}", e)))?;
+        if envelope["schema_version"] != STATS_JSON_SCHEMA_VERSION {
+            return Err(Error::InvalidArgument(format!(
+                "unsupported stats schema version {}",
+                envelope["schema_version"]
+            )));
+        }
+        let type_url = envelope["type_url"].as_str().unwrap_or_default();
+        match typeurl::resolve(type_url)? {
+            METRICS_V1_TYPE => {
+                let mut m = Metrics::new();
+                json::from_value(&envelope["metrics"], &mut m)?;
+                Ok(CgroupMetrics::V1(m))
+            }
+            METRICS_V2_TYPE => {
+                let mut m = metrics_v2::Metrics::new();
+                json::from_value(&envelope["metrics"], &mut m)?;
+                Ok(CgroupMetrics::V2(m))
+            }
+            _ => Err(Error::InvalidArgument(format!(
+                "unsupported stats type {}",
+                type_url
+            ))),
+        }
+    }
+
+    /// Stats of either hierarchy in the cgroup v1 layout. cgroup v2 values
+    /// are converted to the v1 units, e.g. CPU times from usec to nsec, and
+    /// v2 only counters without a v1 counterpart are dropped.
+    pub fn normalize(&self) -> Metrics {
+        match self {
+            CgroupMetrics::V1(m) => m.clone(),
+            CgroupMetrics::V2(m) => normalize_v2(m),
+        }
+    }
+}
+
+fn blkio_entry(e: &metrics_v2::IOEntry, op: &str, value: u64) -> BlkIOEntry {
+    let mut entry = BlkIOEntry::new();
+    entry.op = op.to_string();
+    entry.major = e.major;
+    entry.minor = e.minor;
+    entry.value = value;
+    entry
+}
+
+fn rdma_entries(entries: &[metrics_v2::RdmaEntry]) -> Vec<metrics::RdmaEntry> {
+    entries
+        .iter()
+        .map(|e| {
+            let mut entry = metrics::RdmaEntry::new();
+            entry.device = e.device.clone();
+            entry.hca_handles = e.hca_handles;
+            entry.hca_objects = e.hca_objects;
+            entry
+        })
+        .collect()
+}
+
+fn normalize_v2(in_obj: &metrics_v2::Metrics) -> Metrics {
+    let mut m = Metrics::new();
+
+    let pids = m.pids.mut_or_insert_default();
+    pids.current = in_obj.pids.current;
+    pids.limit = in_obj.pids.limit;
+
+    let cpu = m.cpu.mut_or_insert_default();
+    let usage = cpu.usage.mut_or_insert_default();
+    usage.total = in_obj.cpu.usage_usec.saturating_mul(1000);
+    usage.kernel = in_obj.cpu.system_usec.saturating_mul(1000);
+    usage.user = in_obj.cpu.user_usec.saturating_mul(1000);
+    let throttling = cpu.throttling.mut_or_insert_default();
+    throttling.periods = in_obj.cpu.nr_periods;
+    throttling.throttled_periods = in_obj.cpu.nr_throttled;
+    throttling.throttled_time = in_obj.cpu.throttled_usec.saturating_mul(1000);
+
+    let v2 = &in_obj.memory;
+    let mem = m.memory.mut_or_insert_default();
+    mem.cache = v2.file;
+    mem.total_cache = v2.file;
+    mem.rss = v2.anon;
+    mem.total_rss = v2.anon;
+    mem.rss_huge = v2.anon_thp;
+    mem.total_rss_huge = v2.anon_thp;
+    mem.mapped_file = v2.file_mapped;
+    mem.total_mapped_file = v2.file_mapped;
+    mem.dirty = v2.file_dirty;
+    mem.total_dirty = v2.file_dirty;
+    mem.writeback = v2.file_writeback;
+    mem.total_writeback = v2.file_writeback;
+    mem.pg_fault = v2.pgfault;
+    mem.total_pg_fault = v2.pgfault;
+    mem.pg_maj_fault = v2.pgmajfault;
+    mem.total_pg_maj_fault = v2.pgmajfault;
+    mem.inactive_anon = v2.inactive_anon;
+    mem.total_inactive_anon = v2.inactive_anon;
+    mem.active_anon = v2.active_anon;
+    mem.total_active_anon = v2.active_anon;
+    mem.inactive_file = v2.inactive_file;
+    mem.total_inactive_file = v2.inactive_file;
+    mem.active_file = v2.active_file;
+    mem.total_active_file = v2.active_file;
+    mem.unevictable = v2.unevictable;
+    mem.total_unevictable = v2.unevictable;
+    mem.hierarchical_memory_limit = v2.usage_limit;
+    mem.hierarchical_swap_limit = v2.swap_limit;
+    let usage = mem.usage.mut_or_insert_default();
+    usage.usage = v2.usage;
+    usage.limit = v2.usage_limit;
+    usage.max = v2.max_usage;
+    let swap = mem.swap.mut_or_insert_default();
+    swap.usage = v2.swap_usage;
+    swap.limit = v2.swap_limit;
+    swap.max = v2.swap_max_usage;
+    mem.kernel.mut_or_insert_default().usage = v2.kernel_stack.saturating_add(v2.slab);
+
+    m.memory_oom_control.mut_or_insert_default().oom_kill = in_obj.memory_events.oom_kill;
+
+    let blkio = m.blkio.mut_or_insert_default();
+    for e in in_obj.io.usage.iter() {
+        blkio.io_service_bytes_recursive.extend([
+            blkio_entry(e, "Read", e.rbytes),
+            blkio_entry(e, "Write", e.wbytes),
+            blkio_entry(e, "Total", e.rbytes.saturating_add(e.wbytes)),
+        ]);
+        blkio.io_serviced_recursive.extend([
+            blkio_entry(e, "Read", e.rios),
+            blkio_entry(e, "Write", e.wios),
+            blkio_entry(e, "Total", e.rios.saturating_add(e.wios)),
+        ]);
+    }
+
+    m.hugetlb = in_obj
+        .hugetlb
+        .iter()
+        .map(|h| {
+            let mut hugetlb = metrics::HugetlbStat::new();
+            hugetlb.pagesize = h.pagesize.clone();
+            hugetlb.usage = h.current;
+            hugetlb.max = h.max;
+            hugetlb
+        })
+        .collect();
+
+    let rdma = m.rdma.mut_or_insert_default();
+    rdma.current = rdma_entries(&in_obj.rdma.current);
+    rdma.limit = rdma_entries(&in_obj.rdma.limit);
+
+    m
+}
+
+/// Counters of one blkio table split by cgroup v1 operation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlkioOps {
+    pub read: u64,
+    pub write: u64,
+    pub sync: u64,
+    pub r#async: u64,
+    pub discard: u64,
+    pub total: u64,
+}
+
+impl BlkioOps {
+    fn add(&mut self, op: &str, value: u64) {
+        // cgroup v1 writes the ops capitalized, e.g. "Read", older shims
+        // lowercase them.
+        let counter = match op.to_ascii_lowercase().as_str() {
+            "read" => &mut self.read,
+            "write" => &mut self.write,
+            "sync" => &mut self.sync,
+            "async" => &mut self.r#async,
+            "discard" => &mut self.discard,
+            "total" => &mut self.total,
+            _ => return,
+        };
+        *counter = counter.saturating_add(value);
+    }
+}
+
+/// The blkio tables of one block device. Only the throttle tables, bytes
+/// and ops, are filled on every host, the others come from the CFQ or BFQ
+/// scheduler and stay zero without one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlkioDevice {
+    pub major: u64,
+    pub minor: u64,
+    pub device: String,
+    pub bytes: BlkioOps,
+    pub ops: BlkioOps,
+    pub queued: BlkioOps,
+    /// Nanoseconds.
+    pub service_time: BlkioOps,
+    /// Nanoseconds.
+    pub wait_time: BlkioOps,
+    pub merged: BlkioOps,
+    /// Milliseconds of disk time, not split by operation.
+    pub time: u64,
+    /// Not split by operation.
+    pub sectors: u64,
+}
+
+fn device_entry<'a>(devices: &'a mut Vec<BlkioDevice>, e: &BlkIOEntry) -> &'a mut BlkioDevice {
+    let i = match devices
+        .iter()
+        .position(|d| d.major == e.major && d.minor == e.minor)
+    {
+        Some(i) => i,
+        None => {
+            devices.push(BlkioDevice {
+                major: e.major,
+                minor: e.minor,
+                ..Default::default()
+            });
+            devices.len() - 1
+        }
+    };
+    let device = &mut devices[i];
+    if device.device.is_empty() {
+        device.device = e.device.clone();
+    }
+    device
+}
+
+// the counters of a device one blkio table adds to
+type BlkioTable = fn(&mut BlkioDevice) -> &mut BlkioOps;
+
+/// Per device (major:minor) breakdown of the blkio tables, in the order the
+/// devices first appear.
+pub fn blkio_devices(blkio: &BlkIOStat) -> Vec<BlkioDevice> {
+    let mut devices = Vec::new();
+    let tables: [(&[BlkIOEntry], BlkioTable); 6] = [
+        (&blkio.io_service_bytes_recursive, |d| &mut d.bytes),
+        (&blkio.io_serviced_recursive, |d| &mut d.ops),
+        (&blkio.io_queued_recursive, |d| &mut d.queued),
+        (&blkio.io_service_time_recursive, |d| &mut d.service_time),
+        (&blkio.io_wait_time_recursive, |d| &mut d.wait_time),
+        (&blkio.io_merged_recursive, |d| &mut d.merged),
+    ];
+    for (entries, table) in tables {
+        for e in entries.iter() {
+            table(device_entry(&mut devices, e)).add(&e.op, e.value);
+        }
+    }
+    for e in blkio.io_time_recursive.iter() {
+        let device = device_entry(&mut devices, e);
+        device.time = device.time.saturating_add(e.value);
+    }
+    for e in blkio.sectors_recursive.iter() {
+        let device = device_entry(&mut devices, e);
+        device.sectors = device.sectors.saturating_add(e.value);
+    }
+    devices
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // blkio.throttle.io_service_bytes_recursive and
+    // blkio.throttle.io_serviced_recursive of a container on a 5.10 host.
+    const SERVICE_BYTES: &str = "\
+8:0 Read 28672
+8:0 Write 1110016
+8:0 Sync 1126400
+8:0 Async 12288
+8:0 Discard 0
+8:0 Total 1138688
+253:0 Read 28672
+253:0 Write 1110016
+253:0 Sync 1126400
+253:0 Async 12288
+253:0 Discard 0
+253:0 Total 1138688
+Total 2277376";
+
+    const SERVICED: &str = "\
+8:0 Read 7
+8:0 Write 262
+8:0 Sync 266
+8:0 Async 3
+8:0 Discard 0
+8:0 Total 269
+253:0 Read 7
+253:0 Write 271
+253:0 Sync 275
+253:0 Async 3
+253:0 Discard 0
+253:0 Total 278
+Total 547";
+
+    // The CFQ tables of 8:0, only kernels before 5.0 have the scheduler.
+    // Device mapper devices have none. Times are in ns, io_time in ms.
+    const QUEUED: &str = "\
+8:0 Read 0
+8:0 Write 1
+8:0 Sync 1
+8:0 Async 0
+8:0 Discard 0
+8:0 Total 1
+Total 1";
+
+    const SERVICE_TIME: &str = "\
+8:0 Read 2104882
+8:0 Write 41839120
+8:0 Sync 43507466
+8:0 Async 436536
+8:0 Discard 0
+8:0 Total 43944002
+Total 43944002";
+
+    const WAIT_TIME: &str = "\
+8:0 Read 118524
+8:0 Write 9207345
+8:0 Sync 9290130
+8:0 Async 35739
+8:0 Discard 0
+8:0 Total 9325869
+Total 9325869";
+
+    const MERGED: &str = "\
+8:0 Read 0
+8:0 Write 12
+8:0 Sync 12
+8:0 Async 0
+8:0 Discard 0
+8:0 Total 12
+Total 12";
+
+    const TIME: &str = "8:0 44";
+
+    const SECTORS: &str = "8:0 2224";
+
+    // Parses a blkio file the way containerd's cgroups package does, the
+    // summary line without a device is skipped and io_time and sectors
+    // have no op.
+    fn parse_blkio(data: &str) -> Vec<BlkIOEntry> {
+        data.lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split_whitespace().collect();
+                let (dev, op, value) = match fields[..] {
+                    [dev, op, value] => (dev, op, value),
+                    [dev, value] if dev.contains(':') => (dev, "", value),
+                    _ => return None,
+                };
+                let dev: Vec<&str> = dev.split(':').collect();
+                let mut e = BlkIOEntry::new();
+                e.major = dev[0].parse().unwrap();
+                e.minor = dev[1].parse().unwrap();
+                e.op = op.to_string();
+                e.value = value.parse().unwrap();
+                Some(e)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_blkio_devices() {
+        let mut blkio = BlkIOStat::new();
+        blkio.io_service_bytes_recursive = parse_blkio(SERVICE_BYTES);
+        blkio.io_serviced_recursive = parse_blkio(SERVICED);
+        blkio.io_queued_recursive = parse_blkio(QUEUED);
+        blkio.io_service_time_recursive = parse_blkio(SERVICE_TIME);
+        blkio.io_wait_time_recursive = parse_blkio(WAIT_TIME);
+        blkio.io_merged_recursive = parse_blkio(MERGED);
+        blkio.io_time_recursive = parse_blkio(TIME);
+        blkio.sectors_recursive = parse_blkio(SECTORS);
+
+        let devices = blkio_devices(&blkio);
+        assert_eq!(devices.len(), 2);
+        assert_eq!((devices[0].major, devices[0].minor), (8, 0));
+        assert_eq!(
+            devices[0].bytes,
+            BlkioOps {
+                read: 28672,
+                write: 1110016,
+                sync: 1126400,
+                r#async: 12288,
+                discard: 0,
+                total: 1138688,
+            }
+        );
+        assert_eq!(devices[0].ops.read, 7);
+        assert_eq!(devices[0].ops.write, 262);
+        assert_eq!(devices[0].queued.write, 1);
+        assert_eq!(
+            devices[0].service_time,
+            BlkioOps {
+                read: 2104882,
+                write: 41839120,
+                sync: 43507466,
+                r#async: 436536,
+                discard: 0,
+                total: 43944002,
+            }
+        );
+        assert_eq!(devices[0].wait_time.total, 9325869);
+        assert_eq!(devices[0].merged.write, 12);
+        assert_eq!(devices[0].time, 44);
+        assert_eq!(devices[0].sectors * 512, devices[0].bytes.total);
+        assert_eq!((devices[1].major, devices[1].minor), (253, 0));
+        assert_eq!(devices[1].ops.write, 271);
+        assert_eq!(devices[1].ops.total, 278);
+        assert_eq!(devices[1].service_time, BlkioOps::default());
+        assert_eq!(devices[1].sectors, 0);
+        for d in devices.iter() {
+            assert_eq!(d.bytes.read + d.bytes.write, d.bytes.total);
+            assert_eq!(d.bytes.sync + d.bytes.r#async, d.bytes.total);
+        }
+    }
+
+    fn v2_sample() -> metrics_v2::Metrics {
+        let mut m = metrics_v2::Metrics::new();
+        let pids = m.pids.mut_or_insert_default();
+        pids.current = 3;
+        pids.limit = 4096;
+        let cpu = m.cpu.mut_or_insert_default();
+        cpu.usage_usec = 15000;
+        cpu.user_usec = 10000;
+        cpu.system_usec = 5000;
+        cpu.nr_periods = 20;
+        cpu.nr_throttled = 2;
+        cpu.throttled_usec = 300;
+        let mem = m.memory.mut_or_insert_default();
+        mem.anon = 1048576;
+        mem.file = 4096;
+        mem.kernel_stack = 16384;
+        mem.slab = 8192;
+        mem.inactive_file = 2048;
+        mem.pgfault = 100;
+        mem.usage = 1200000;
+        mem.usage_limit = 268435456;
+        mem.swap_usage = 0;
+        mem.swap_limit = 536870912;
+        mem.max_usage = 2000000;
+        mem.swap_max_usage = 4096;
+        m.memory_events.mut_or_insert_default().oom_kill = 1;
+        let mut io = metrics_v2::IOEntry::new();
+        io.major = 253;
+        io.rbytes = 28672;
+        io.wbytes = 1110016;
+        io.rios = 7;
+        io.wios = 271;
+        m.io.mut_or_insert_default().usage.push(io);
+        let mut hugetlb = metrics_v2::HugeTlbStat::new();
+        hugetlb.pagesize = "2MB".to_string();
+        hugetlb.current = 2097152;
+        m.hugetlb.push(hugetlb);
+        m
+    }
+
+    #[test]
+    fn test_decode_metrics() {
+        let mut v1 = Metrics::new();
+        v1.pids.mut_or_insert_default().current = 5;
+        let mut any = Any::new();
+        any.type_url = METRICS_V1_TYPE.to_string();
+        any.value = v1.write_to_bytes().unwrap();
+        let decoded = CgroupMetrics::decode(&any).unwrap();
+        assert_eq!(decoded.cgroup_version(), 1);
+        assert_eq!(decoded.normalize().pids.current, 5);
+
+        any.type_url = format!("type.googleapis.com/{}", METRICS_V2_TYPE);
+        any.value = v2_sample().write_to_bytes().unwrap();
+        let decoded = CgroupMetrics::decode(&any).unwrap();
+        assert_eq!(decoded.cgroup_version(), 2);
+        assert_eq!(decoded.normalize().pids.current, 3);
+
+        // memory {max_usage: 7, psi {some {total: 9}}} with the field numbers
+        // of upstream's cgroup2 stats
+        any.value = vec![
+            0x22, 0x0a, 0xa0, 0x02, 0x07, 0xb2, 0x02, 0x04, 0x0a, 0x02, 0x20, 0x09,
+        ];
+        match CgroupMetrics::decode(&any).unwrap() {
+            CgroupMetrics::V2(m) => {
+                assert_eq!(m.memory.max_usage, 7);
+                assert_eq!(m.memory.psi.some.total, 9);
+            }
+            _ => panic!("decoded as cgroup v1"),
+        }
+
+        any.value = vec![0xff, 0xff];
+        assert!(CgroupMetrics::decode(&any).is_err());
+        any.type_url = "io.containerd.cgroups.v3.Metrics".to_string();
+        assert!(CgroupMetrics::decode(&any).is_err());
+    }
+
+    #[test]
+    fn test_stats_json_v1() {
+        let mut v1 = Metrics::new();
+        v1.pids.mut_or_insert_default().current = 5;
+        v1.memory.mut_or_insert_default().total_inactive_file = 2048;
+        v1.memory.mut_or_insert_default().usage.mut_or_insert_default().limit = u64::MAX;
+        let mut entry = BlkIOEntry::new();
+        entry.op = "Write".to_string();
+        entry.major = 8;
+        entry.value = 512;
+        v1.blkio.mut_or_insert_default().io_serviced_recursive.push(entry);
+
+        let data = CgroupMetrics::V1(v1.clone()).to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(value["schema_version"], 1);
+        assert_eq!(value["cgroup_version"], 1);
+        assert_eq!(value["type_url"], METRICS_V1_TYPE);
+        assert_eq!(value["metrics"]["pids"]["current"], 5);
+        assert_eq!(value["metrics"]["memory"]["total_inactive_file"], 2048);
+        assert_eq!(value["metrics"]["memory"]["usage"]["limit"], "18446744073709551615");
+        assert_eq!(value["metrics"]["memory"]["swap"], serde_json::Value::Null);
+        assert_eq!(value["metrics"]["blkio"]["io_serviced_recursive"][0]["op"], "Write");
+        assert_eq!(value["metrics"]["hugetlb"], serde_json::json!([]));
+
+        match CgroupMetrics::from_json(&data).unwrap() {
+            CgroupMetrics::V1(decoded) => assert_eq!(decoded, v1),
+            CgroupMetrics::V2(_) => panic!("decoded as cgroup v2"),
+        }
+    }
+
+    #[test]
+    fn test_stats_json_v2() {
+        let data = CgroupMetrics::V2(v2_sample()).to_json().unwrap();
+        let value: serde_json::Value = serde_json::from_str(&data).unwrap();
+        assert_eq!(value["cgroup_version"], 2);
+        assert_eq!(value["type_url"], METRICS_V2_TYPE);
+        assert_eq!(value["metrics"]["cpu"]["usage_usec"], 15000);
+        assert_eq!(value["metrics"]["memory"]["anon"], 1048576);
+        assert_eq!(value["metrics"]["memory_events"]["oom_kill"], 1);
+        assert_eq!(value["metrics"]["io"]["usage"][0]["wios"], 271);
+        assert_eq!(value["metrics"]["hugetlb"][0]["pagesize"], "2MB");
+
+        match CgroupMetrics::from_json(&data).unwrap() {
+            CgroupMetrics::V2(decoded) => assert_eq!(decoded, v2_sample()),
+            CgroupMetrics::V1(_) => panic!("decoded as cgroup v1"),
+        }
+
+        // keys added by newer versions are ignored
+        let mut newer = value.clone();
+        newer["metrics"]["cpu"]["added_later"] = serde_json::json!(1);
+        assert!(CgroupMetrics::from_json(&newer.to_string()).is_ok());
+
+        let mut bad = value.clone();
+        bad["schema_version"] = serde_json::json!(2);
+        assert!(CgroupMetrics::from_json(&bad.to_string()).is_err());
+        let mut bad = value;
+        bad["type_url"] = serde_json::json!("io.containerd.cgroups.v3.Metrics");
+        assert!(CgroupMetrics::from_json(&bad.to_string()).is_err());
+        assert!(CgroupMetrics::from_json("stats").is_err());
+    }
+
+    #[test]
+    fn test_normalize_v2() {
+        let m = CgroupMetrics::V2(v2_sample()).normalize();
+
+        assert_eq!(m.pids.current, 3);
+        assert_eq!(m.pids.limit, 4096);
+        assert_eq!(m.cpu.usage.total, 15_000_000);
+        assert_eq!(m.cpu.usage.user, 10_000_000);
+        assert_eq!(m.cpu.usage.kernel, 5_000_000);
+        assert_eq!(m.cpu.throttling.throttled_periods, 2);
+        assert_eq!(m.cpu.throttling.throttled_time, 300_000);
+        assert_eq!(m.memory.usage.usage, 1200000);
+        assert_eq!(m.memory.usage.limit, 268435456);
+        assert_eq!(m.memory.usage.max, 2000000);
+        assert_eq!(m.memory.swap.limit, 536870912);
+        assert_eq!(m.memory.swap.max, 4096);
+        assert_eq!(m.memory.rss, 1048576);
+        assert_eq!(m.memory.cache, 4096);
+        assert_eq!(m.memory.total_inactive_file, 2048);
+        assert_eq!(m.memory.pg_fault, 100);
+        assert_eq!(m.memory.kernel.usage, 24576);
+        assert_eq!(m.memory_oom_control.oom_kill, 1);
+        assert_eq!(m.hugetlb[0].usage, 2097152);
+
+        let devices = blkio_devices(&m.blkio);
+        assert_eq!(devices.len(), 1);
+        assert_eq!((devices[0].major, devices[0].minor), (253, 0));
+        assert_eq!(devices[0].bytes.read, 28672);
+        assert_eq!(devices[0].bytes.write, 1110016);
+        assert_eq!(devices[0].bytes.total, 1138688);
+        assert_eq!(devices[0].ops.write, 271);
+    }
+
+    #[test]
+    fn test_blkio_devices_lowercase_ops() {
+        let mut blkio = BlkIOStat::new();
+        blkio.io_service_bytes_recursive = parse_blkio("8:16 read 10\n8:16 write 20\n8:16 total 30");
+
+        let devices = blkio_devices(&blkio);
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].bytes.read, 10);
+        assert_eq!(devices[0].bytes.write, 20);
+        assert_eq!(devices[0].bytes.total, 30);
+        assert_eq!(devices[0].ops, BlkioOps::default());
+    }
+}
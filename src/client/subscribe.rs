@@ -0,0 +1,260 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+use super::client::get_conn;
+use super::error::{Error, Result};
+use super::stats::CgroupMetrics;
+use super::trace;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Failed stats calls in a row after which the shim is taken as gone.
+pub const SUBSCRIBE_MAX_FAILURES: u32 = 3;
+
+/// What a stats subscription delivers on every tick.
+#[derive(Debug)]
+pub enum StatsEvent {
+    Stats(CgroupMetrics),
+    Error(Error),
+    /// The last event of a subscription, sent once it stopped.
+    End,
+}
+
+// senders keyed by subscription id, dropping one stops its sampler
+type Subscriptions = HashMap<String, Vec<(u64, Sender<()>)>>;
+
+lazy_static! {
+    // a sampler that stopped on its own removes its sender
+    static ref SUBSCRIPTIONS: Mutex<Subscriptions> = Mutex::new(HashMap::new());
+}
+
+static NEXT_SUBSCRIPTION_ID: AtomicU64 = AtomicU64::new(1);
+
+fn remove_subscription(container_id: &str, id: u64) {
+    let mut subscriptions = SUBSCRIPTIONS.lock().unwrap();
+    if let Some(subscribed) = subscriptions.get_mut(container_id) {
+        subscribed.retain(|(subscription_id, _)| *subscription_id != id);
+        if subscribed.is_empty() {
+            subscriptions.remove(container_id);
+        }
+    }
+}
+
+/// Samples the container's stats every `interval` on a thread of its own
+/// until `unsubscribe_stats` is called, the connection is closed or
+/// `SUBSCRIBE_MAX_FAILURES` calls failed in a row. Each call times out after
+/// one interval.
+pub fn subscribe_stats<F>(container_id: &str, interval: Duration, deliver: F) -> Result<()>
+where
+    F: FnMut(StatsEvent) + Send + 'static,
+{
+    if interval.is_zero() {
+        return Err(Error::InvalidArgument("interval must not be 0".to_string()));
+    }
+    get_conn(container_id)?;
+
+    let (tx, rx) = mpsc::channel();
+    let subscription_id = NEXT_SUBSCRIPTION_ID.fetch_add(1, Ordering::Relaxed);
+    SUBSCRIPTIONS
+        .lock()
+        .unwrap()
+        .entry(container_id.to_string())
+        .or_default()
+        .push((subscription_id, tx));
+    let id = container_id.to_string();
+    let scope = trace::current();
+    thread::spawn(move || {
+        // the thread is the subscription's own, its calls and those of
+        // `deliver` are spans of the subscriber's scope
+        trace::set_current(scope);
+        // closing the connection ends a call in flight at once
+        run_sampler(
+            rx,
+            interval,
+            || {
+                get_conn(&id)
+                    .ok()
+                    .map(|client| client.with_deadline(Instant::now() + interval).stats())
+            },
+            deliver,
+        );
+        remove_subscription(&id, subscription_id);
+    });
+    Ok(())
+}
+
+/// Stops all stats subscriptions of a container, returns how many there
+/// were.
+pub fn unsubscribe_stats(container_id: &str) -> usize {
+    SUBSCRIPTIONS
+        .lock()
+        .unwrap()
+        .remove(container_id)
+        .map_or(0, |subscribed| subscribed.len())
+}
+
+// `sample` returns None once the connection is gone.
+fn run_sampler<S, F>(stop: Receiver<()>, interval: Duration, mut sample: S, mut deliver: F)
+where
+    S: FnMut() -> Option<Result<CgroupMetrics>>,
+    F: FnMut(StatsEvent),
+{
+    let mut failures = 0;
+    loop {
+        match sample() {
+            None => break,
+            Some(Ok(metrics)) => {
+                failures = 0;
+                deliver(StatsEvent::Stats(metrics));
+            }
+            Some(Err(e)) => {
+                failures += 1;
+                deliver(StatsEvent::Error(e));
+                if failures >= SUBSCRIBE_MAX_FAILURES {
+                    break;
+                }
+            }
+        }
+        match stop.recv_timeout(interval) {
+            Err(RecvTimeoutError::Timeout) => {}
+            _ => break,
+        }
+    }
+    deliver(StatsEvent::End);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::del_conn;
+    use crate::client::runtime::RuntimeHandler;
+    use crate::client::testutil::StuckShim;
+    use std::sync::{Arc, Mutex};
+
+    fn events() -> (Arc<Mutex<Vec<String>>>, impl FnMut(StatsEvent)) {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        (seen, move |event| {
+            sink.lock().unwrap().push(match event {
+                StatsEvent::Stats(_) => "stats".to_string(),
+                StatsEvent::Error(_) => "error".to_string(),
+                StatsEvent::End => "end".to_string(),
+            })
+        })
+    }
+
+    #[test]
+    fn test_sampler_stops() {
+        let (tx, rx) = mpsc::channel();
+        let (seen, deliver) = events();
+        let handle = thread::spawn(move || {
+            run_sampler(
+                rx,
+                Duration::from_millis(10),
+                || Some(Ok(CgroupMetrics::default())),
+                deliver,
+            )
+        });
+        thread::sleep(Duration::from_millis(100));
+        let stopped = Instant::now();
+        drop(tx);
+        handle.join().unwrap();
+        assert!(stopped.elapsed() < Duration::from_millis(50));
+
+        let seen = seen.lock().unwrap();
+        assert!(seen.len() > 2);
+        assert_eq!(seen.last().unwrap(), "end");
+        assert!(seen[..seen.len() - 1].iter().all(|e| e == "stats"));
+    }
+
+    #[test]
+    fn test_sampler_shim_gone() {
+        let (_tx, rx) = mpsc::channel();
+        let (seen, deliver) = events();
+        let mut calls = 0;
+        run_sampler(
+            rx,
+            Duration::from_millis(1),
+            || {
+                calls += 1;
+                match calls {
+                    1 => Some(Ok(CgroupMetrics::default())),
+                    2 => Some(Err(Error::ShimError("timeout".to_string()))),
+                    3 => Some(Ok(CgroupMetrics::default())),
+                    _ => Some(Err(Error::ShimError("closed".to_string()))),
+                }
+            },
+            deliver,
+        );
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec!["stats", "error", "stats", "error", "error", "error", "end"]
+        );
+
+        let (_tx, rx) = mpsc::channel();
+        let (seen, deliver) = events();
+        run_sampler(rx, Duration::from_millis(1), || None, deliver);
+        assert_eq!(*seen.lock().unwrap(), vec!["end"]);
+    }
+
+    #[test]
+    fn test_remove_subscription() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, _rx2) = mpsc::channel();
+        SUBSCRIPTIONS
+            .lock()
+            .unwrap()
+            .insert("subscribe-2".to_string(), vec![(1001, tx1), (1002, tx2)]);
+        remove_subscription("subscribe-2", 1002);
+        assert_eq!(SUBSCRIPTIONS.lock().unwrap()["subscribe-2"].len(), 1);
+        assert!(rx1.try_recv().is_err());
+        remove_subscription("subscribe-2", 1001);
+        assert!(!SUBSCRIPTIONS.lock().unwrap().contains_key("subscribe-2"));
+        remove_subscription("subscribe-2", 1001);
+    }
+
+    #[test]
+    fn test_subscribe_stats_stuck_shim() {
+        let shim = StuckShim::new("subscribe-stuck");
+        shim.connect("subscribe-stuck", RuntimeHandler::default());
+        let (tx, rx) = mpsc::channel();
+        let deliver = move |event| {
+            let _ = tx.send(matches!(event, StatsEvent::End));
+        };
+        // a call gives up after one interval
+        subscribe_stats("subscribe-stuck", Duration::from_millis(100), deliver.clone()).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(false));
+        unsubscribe_stats("subscribe-stuck");
+        while !rx.recv_timeout(Duration::from_secs(1)).unwrap() {}
+
+        // closing the connection ends the call in flight
+        subscribe_stats("subscribe-stuck", Duration::from_secs(30), deliver).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        let closed = Instant::now();
+        del_conn("subscribe-stuck");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(false));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(1)), Ok(true));
+        assert!(closed.elapsed() < Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_subscribe_stats_without_connection() {
+        assert!(subscribe_stats("subscribe-1", Duration::from_secs(1), |_| {}).is_err());
+        assert!(subscribe_stats("subscribe-1", Duration::from_secs(0), |_| {}).is_err());
+        unsubscribe_stats("subscribe-1");
+    }
+}
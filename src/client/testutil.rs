@@ -0,0 +1,96 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Fixtures shared by the tests.
+
+use super::client::insert_conn;
+use super::metadata::Metadata;
+use super::runtime::RuntimeHandler;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+/// An empty directory under the system temp dir, removed with its contents
+/// when dropped, so it goes away when the test fails as well.
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let path = std::env::temp_dir().join(format!(
+            "lib-shim-v2-{}-{}-{}",
+            name,
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+
+    /// Writes `data` to `path` below the directory, creating its parents.
+    pub fn write<D: AsRef<[u8]>>(&self, path: &str, data: D) -> PathBuf {
+        let path = self.path.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.path);
+    }
+}
+
+/// A shim socket that accepts connections and never answers, like a shim
+/// that is alive but stuck.
+pub struct StuckShim {
+    dir: TempDir,
+}
+
+impl StuckShim {
+    pub fn new(name: &str) -> StuckShim {
+        let dir = TempDir::new(name);
+        let listener = UnixListener::bind(dir.join("shim.sock")).unwrap();
+        // the connections are held open without being read, until the test
+        // process exits
+        thread::spawn(move || {
+            let mut conns = Vec::new();
+            for conn in listener.incoming() {
+                conns.push(conn);
+            }
+        });
+        StuckShim { dir }
+    }
+
+    /// Stores a connection to the shim as `new_conn` would, with the given
+    /// runtime handler.
+    pub fn connect(&self, container_id: &str, runtime: RuntimeHandler) {
+        let fd = UnixStream::connect(self.dir.join("shim.sock")).unwrap().into_raw_fd();
+        let addr = format!("unix://{}", self.dir.join("shim.sock").display());
+        insert_conn(container_id, &addr, fd, runtime, Metadata::default()).unwrap();
+    }
+}
@@ -0,0 +1,408 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! ps style process table of a container, the pids listed by the shim with
+//! details read from the host's procfs. Processes the host cannot see, e.g.
+//! inside a VM, only have their pid and exec id filled.
+
+use super::cgroup::{user_hz, PROC_ROOT};
+use super::client::ProcessInfo;
+use super::error::{Error, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const PASSWD_FILE: &str = "/etc/passwd";
+/// Columns of `ps -ef`.
+pub const TOP_DEFAULT_COLUMNS: [&str; 6] = ["user", "pid", "ppid", "stime", "time", "cmd"];
+
+/// Details of a process from `/proc/<pid>`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcDetails {
+    pub ppid: u32,
+    /// Effective uid, `user` is its name in the host's passwd or empty.
+    pub uid: u32,
+    pub user: String,
+    pub state: char,
+    /// Seconds since the epoch.
+    pub start_time: u64,
+    pub cpu_time: Duration,
+    /// Resident set size in bytes.
+    pub rss: u64,
+    pub comm: String,
+    pub cmdline: Vec<String>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Process {
+    pub pid: u32,
+    pub exec_id: String,
+    pub details: Option<ProcDetails>,
+}
+
+/// Titles and one row of cells per process, in the order of the columns.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProcessTable {
+    pub titles: Vec<String>,
+    pub processes: Vec<Vec<String>>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Column {
+    Pid,
+    Ppid,
+    Uid,
+    User,
+    State,
+    StartTime,
+    CpuTime,
+    Rss,
+    Comm,
+    Cmd,
+    ExecId,
+}
+
+impl Column {
+    /// Column of a ps format specifier.
+    pub fn parse(name: &str) -> Result<Column> {
+        Ok(match name {
+            "pid" => Column::Pid,
+            "ppid" => Column::Ppid,
+            "uid" => Column::Uid,
+            "user" => Column::User,
+            "s" | "state" => Column::State,
+            "stime" | "start" => Column::StartTime,
+            "time" => Column::CpuTime,
+            "rss" => Column::Rss,
+            "comm" => Column::Comm,
+            "cmd" | "args" | "command" => Column::Cmd,
+            "exec_id" => Column::ExecId,
+            _ => {
+                return Err(Error::InvalidArgument(format!(
+                    "unknown process column {:?}",
+                    name
+                )))
+            }
+        })
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Column::Pid => "PID",
+            Column::Ppid => "PPID",
+            Column::Uid => "UID",
+            Column::User => "USER",
+            Column::State => "S",
+            Column::StartTime => "STIME",
+            Column::CpuTime => "TIME",
+            Column::Rss => "RSS",
+            Column::Comm => "COMMAND",
+            Column::Cmd => "CMD",
+            Column::ExecId => "EXEC_ID",
+        }
+    }
+
+    /// The cell of `process`, `-` where procfs had no details.
+    pub fn cell(self, process: &Process) -> String {
+        let d = match (self, process.details.as_ref()) {
+            (Column::Pid, _) => return process.pid.to_string(),
+            (Column::ExecId, _) => return process.exec_id.clone(),
+            (_, None) => return "-".to_string(),
+            (_, Some(d)) => d,
+        };
+        match self {
+            Column::Ppid => d.ppid.to_string(),
+            Column::Uid => d.uid.to_string(),
+            Column::User if d.user.is_empty() => d.uid.to_string(),
+            Column::User => d.user.clone(),
+            Column::State => d.state.to_string(),
+            Column::StartTime => format_time(d.start_time),
+            Column::CpuTime => format_duration(d.cpu_time),
+            Column::Rss => (d.rss / 1024).to_string(),
+            Column::Comm => d.comm.clone(),
+            // like ps, processes without a command line show their name
+            Column::Cmd if d.cmdline.is_empty() => format!("[{}]", d.comm),
+            Column::Cmd => d.cmdline.join(" "),
+            Column::Pid | Column::ExecId => unreachable!(),
+        }
+    }
+}
+
+pub fn columns(names: &[&str]) -> Result<Vec<Column>> {
+    if names.is_empty() {
+        return columns(&TOP_DEFAULT_COLUMNS);
+    }
+    names.iter().map(|name| Column::parse(name)).collect()
+}
+
+/// `[DD-]HH:MM:SS`, as the TIME column of ps.
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    let (days, hours, mins, secs) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}-{:02}:{:02}:{:02}", days, hours, mins, secs)
+    } else {
+        format!("{:02}:{:02}:{:02}", hours, mins, secs)
+    }
+}
+
+/// RFC 3339 UTC time of seconds since the epoch.
+fn format_time(epoch_secs: u64) -> String {
+    let (days, secs) = ((epoch_secs / 86400) as i64, epoch_secs % 86400);
+    // civil date from days since the epoch, H. Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+pub struct Procfs {
+    pub proc_root: PathBuf,
+    pub passwd: PathBuf,
+}
+
+impl Default for Procfs {
+    fn default() -> Self {
+        Procfs {
+            proc_root: PathBuf::from(PROC_ROOT),
+            passwd: PathBuf::from(PASSWD_FILE),
+        }
+    }
+}
+
+impl Procfs {
+    fn boot_time(&self) -> Result<u64> {
+        std::fs::read_to_string(self.proc_root.join("stat"))?
+            .lines()
+            .find_map(|line| line.strip_prefix("btime "))
+            .and_then(|btime| btime.trim().parse().ok())
+            .ok_or_else(|| other!("no btime in procfs stat"))
+    }
+
+    fn users(&self) -> HashMap<u32, String> {
+        std::fs::read_to_string(&self.passwd)
+            .unwrap_or_default()
+            .lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.split(':').collect();
+                let uid = fields.get(2)?.parse().ok()?;
+                Some((uid, fields[0].to_string()))
+            })
+            .collect()
+    }
+
+    fn details(&self, pid: u32, boot_time: u64, users: &HashMap<u32, String>) -> Result<ProcDetails> {
+        let dir = self.proc_root.join(pid.to_string());
+        let mut d = ProcDetails::default();
+
+        // the name may contain spaces and parens, it ends at the last ')'
+        let stat = std::fs::read_to_string(dir.join("stat"))?;
+        let bad_stat = || other!(format!("bad procfs stat of pid {}", pid));
+        let (open, close) = match (stat.find('('), stat.rfind(')')) {
+            (Some(open), Some(close)) if open < close => (open, close),
+            _ => return Err(bad_stat()),
+        };
+        d.comm = stat[open + 1..close].to_string();
+        let fields: Vec<&str> = stat[close + 1..].split_whitespace().collect();
+        if fields.len() < 20 {
+            return Err(bad_stat());
+        }
+        let field = |i: usize| fields[i].parse::<u64>().map_err(|_| bad_stat());
+        d.state = fields[0].chars().next().unwrap_or('?');
+        d.ppid = field(1)? as u32;
+        let hz = user_hz();
+        d.cpu_time = Duration::from_millis((field(11)? + field(12)?) * 1000 / hz);
+        d.start_time = boot_time + field(19)? / hz;
+
+        let status = std::fs::read_to_string(dir.join("status"))?;
+        for line in status.lines() {
+            let mut values = line.split_whitespace().skip(1);
+            if line.starts_with("Uid:") {
+                // real, effective, saved, fs
+                d.uid = values.nth(1).and_then(|uid| uid.parse().ok()).unwrap_or_default();
+            } else if line.starts_with("VmRSS:") {
+                d.rss = values.next().and_then(|kb| kb.parse::<u64>().ok()).unwrap_or_default() * 1024;
+            }
+        }
+        d.user = users.get(&d.uid).cloned().unwrap_or_default();
+
+        d.cmdline = std::fs::read(dir.join("cmdline"))?
+            .split(|b| *b == 0)
+            .filter(|arg| !arg.is_empty())
+            .map(|arg| String::from_utf8_lossy(arg).into_owned())
+            .collect();
+        Ok(d)
+    }
+
+    /// The processes the shim listed, with the details procfs has of them.
+    pub fn processes(&self, infos: &[ProcessInfo]) -> Result<Vec<Process>> {
+        let boot_time = self.boot_time()?;
+        let users = self.users();
+        Ok(infos
+            .iter()
+            .map(|info| Process {
+                pid: info.pid,
+                exec_id: info.exec_id.clone(),
+                details: self.details(info.pid, boot_time, &users).ok(),
+            })
+            .collect())
+    }
+
+    pub fn table(&self, infos: &[ProcessInfo], columns: &[Column]) -> Result<ProcessTable> {
+        let processes = self.processes(infos)?;
+        Ok(ProcessTable {
+            titles: columns.iter().map(|c| c.title().to_string()).collect(),
+            processes: processes
+                .iter()
+                .map(|p| columns.iter().map(|c| c.cell(p)).collect())
+                .collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    // the procfs is valid as long as the directory is
+    fn fake_procfs(name: &str) -> (TempDir, Procfs) {
+        let root = TempDir::new(&format!("top-{}", name));
+        let write = |path: &str, data: &[u8]| {
+            root.write(path, data);
+        };
+        // in ticks: pid 100 ran 2.5s user and 0.5s system and started 1h
+        // after boot, pid 101 ran 100h and 1m and started 1s after boot
+        let hz = user_hz();
+        write("proc/stat", b"cpu  1 2 3 4\nbtime 1700000000\nprocesses 10\n");
+        write(
+            "proc/100/stat",
+            format!(
+                "100 (sh) S 90 100 100 0 -1 4194560 100 0 0 0 {} {} 0 0 20 0 1 0 {} 4321 100 0\n",
+                hz * 5 / 2,
+                hz / 2,
+                hz * 3600
+            )
+            .as_bytes(),
+        );
+        write("proc/100/status", b"Name:\tsh\nUid:\t0\t0\t0\t0\nVmRSS:\t    1024 kB\n");
+        write("proc/100/cmdline", b"/bin/sh\0-c\0sleep 1000\0");
+        write(
+            "proc/101/stat",
+            format!(
+                "101 (my (odd) proc) Z 100 100 100 0 -1 4194560 0 0 0 0 {} {} 0 0 20 0 1 0 {} 0 0 0\n",
+                hz * 360000,
+                hz * 60,
+                hz
+            )
+            .as_bytes(),
+        );
+        write("proc/101/status", b"Name:\tmy (odd) proc\nUid:\t1000\t1001\t1000\t1000\n");
+        write("proc/101/cmdline", b"");
+        write("passwd", b"root:x:0:0:root:/root:/bin/sh\nuser:x:1000:1000::/home/user:/bin/sh\n");
+        let procfs = Procfs {
+            proc_root: root.join("proc"),
+            passwd: root.join("passwd"),
+        };
+        (root, procfs)
+    }
+
+    fn infos() -> Vec<ProcessInfo> {
+        vec![
+            ProcessInfo {
+                pid: 100,
+                exec_id: String::new(),
+            },
+            ProcessInfo {
+                pid: 101,
+                exec_id: "exec1".to_string(),
+            },
+            ProcessInfo {
+                pid: 102,
+                exec_id: String::new(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_processes() {
+        let (_root, procfs) = fake_procfs("processes");
+        let processes = procfs.processes(&infos()).unwrap();
+        assert_eq!(
+            processes[0].details,
+            Some(ProcDetails {
+                ppid: 90,
+                uid: 0,
+                user: "root".to_string(),
+                state: 'S',
+                start_time: 1_700_003_600,
+                cpu_time: Duration::from_secs(3),
+                rss: 1024 * 1024,
+                comm: "sh".to_string(),
+                cmdline: vec!["/bin/sh".to_string(), "-c".to_string(), "sleep 1000".to_string()],
+            })
+        );
+        let odd = processes[1].details.as_ref().unwrap();
+        assert_eq!(odd.comm, "my (odd) proc");
+        assert_eq!(odd.state, 'Z');
+        assert_eq!(odd.uid, 1001);
+        assert!(odd.user.is_empty());
+        assert_eq!(odd.rss, 0);
+        assert_eq!(processes[1].exec_id, "exec1");
+        assert_eq!(processes[2].details, None);
+    }
+
+    #[test]
+    fn test_table() {
+        let (_root, procfs) = fake_procfs("table");
+        let table = procfs.table(&infos(), &columns(&[]).unwrap()).unwrap();
+        assert_eq!(table.titles, vec!["USER", "PID", "PPID", "STIME", "TIME", "CMD"]);
+        assert_eq!(
+            table.processes,
+            vec![
+                vec!["root", "100", "90", "2023-11-14T23:13:20Z", "00:00:03", "/bin/sh -c sleep 1000"],
+                vec!["1001", "101", "100", "2023-11-14T22:13:21Z", "4-04:01:00", "[my (odd) proc]"],
+                vec!["-", "102", "-", "-", "-", "-"],
+            ]
+        );
+
+        let cols = columns(&["pid", "exec_id", "s", "rss", "uid", "comm"]).unwrap();
+        let table = procfs.table(&infos(), &cols).unwrap();
+        assert_eq!(table.titles, vec!["PID", "EXEC_ID", "S", "RSS", "UID", "COMMAND"]);
+        assert_eq!(table.processes[0], vec!["100", "", "S", "1024", "0", "sh"]);
+        assert_eq!(table.processes[1], vec!["101", "exec1", "Z", "0", "1001", "my (odd) proc"]);
+
+        assert!(matches!(columns(&["pid", "pcpu"]), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_time(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_time(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_duration(Duration::from_secs(59)), "00:00:59");
+        assert_eq!(format_duration(Duration::from_secs(86400 + 3661)), "1-01:01:01");
+    }
+}
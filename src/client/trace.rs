@@ -0,0 +1,456 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Trace context of shim calls: every call is a span, a child of the
+//! caller's W3C `traceparent` when one is given, whose context and the
+//! caller's request id travel in the ttrpc metadata. Finished spans go to
+//! the `log` facade or to a file of OTLP-JSON lines.
+
+use super::error::{Error, Result};
+use lazy_static::lazy_static;
+use serde_json::json;
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+pub const SHIM_SERVICE: &str = "containerd.task.v2.Task";
+const SCOPE_NAME: &str = "lib-shim-v2";
+const FLAG_SAMPLED: u8 = 0x01;
+// OTLP SpanKind and StatusCode values
+const SPAN_KIND_CLIENT: u8 = 3;
+const STATUS_CODE_OK: u8 = 1;
+const STATUS_CODE_ERROR: u8 = 2;
+
+fn random_bytes(buf: &mut [u8]) {
+    // W3C ids must not be all zero
+    while buf.iter().all(|b| *b == 0) {
+        let n = unsafe { libc::getrandom(buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0) };
+        if n < buf.len() as isize {
+            // no entropy source, ids need only be unique
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            for (i, b) in buf.iter_mut().enumerate() {
+                *b = (nanos >> (8 * (i % 16))) as u8 ^ (std::process::id() >> (8 * (i % 4))) as u8;
+            }
+        }
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str, out: &mut [u8]) -> bool {
+    if s.len() != out.len() * 2 || !s.bytes().all(|c| c.is_ascii_digit() || (b'a'..=b'f').contains(&c)) {
+        return false;
+    }
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+    }
+    true
+}
+
+/// A W3C trace context, `00-<trace id>-<span id>-<flags>`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub flags: u8,
+}
+
+impl TraceParent {
+    pub fn parse(header: &str) -> Result<TraceParent> {
+        let invalid = || Error::InvalidArgument(format!("invalid traceparent {:?}", header));
+        let fields: Vec<&str> = header.trim().split('-').collect();
+        // later versions may append fields, version ff is forbidden
+        let mut version = [0u8; 1];
+        if fields.len() < 4 || !from_hex(fields[0], &mut version) || version[0] == 0xff {
+            return Err(invalid());
+        }
+        if version[0] == 0 && fields.len() != 4 {
+            return Err(invalid());
+        }
+        let mut parent = TraceParent {
+            trace_id: [0; 16],
+            span_id: [0; 8],
+            flags: 0,
+        };
+        let mut flags = [0u8; 1];
+        if !from_hex(fields[1], &mut parent.trace_id)
+            || !from_hex(fields[2], &mut parent.span_id)
+            || !from_hex(fields[3], &mut flags)
+            || parent.trace_id.iter().all(|b| *b == 0)
+            || parent.span_id.iter().all(|b| *b == 0)
+        {
+            return Err(invalid());
+        }
+        parent.flags = flags[0];
+        Ok(parent)
+    }
+
+    /// The first span of a new trace.
+    pub fn root() -> TraceParent {
+        let mut trace_id = [0; 16];
+        random_bytes(&mut trace_id);
+        TraceParent::in_trace(trace_id)
+    }
+
+    fn in_trace(trace_id: [u8; 16]) -> TraceParent {
+        let mut span_id = [0; 8];
+        random_bytes(&mut span_id);
+        TraceParent {
+            trace_id,
+            span_id,
+            flags: FLAG_SAMPLED,
+        }
+    }
+
+    /// A new span of the same trace.
+    pub fn child(&self) -> TraceParent {
+        TraceParent {
+            flags: self.flags,
+            ..TraceParent::in_trace(self.trace_id)
+        }
+    }
+
+    pub fn to_header(&self) -> String {
+        format!(
+            "00-{}-{}-{:02x}",
+            to_hex(&self.trace_id),
+            to_hex(&self.span_id),
+            self.flags
+        )
+    }
+}
+
+/// What the caller of a shim call passes down: its request id and span,
+/// either may be unset.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TraceScope {
+    pub request_id: String,
+    pub parent: Option<TraceParent>,
+}
+
+impl TraceScope {
+    pub fn new(request_id: &str, traceparent: &str) -> Result<TraceScope> {
+        if request_id.contains('\0') {
+            return Err(Error::InvalidArgument("request id contains NUL".to_string()));
+        }
+        let parent = if traceparent.is_empty() {
+            None
+        } else {
+            Some(TraceParent::parse(traceparent)?)
+        };
+        Ok(TraceScope {
+            request_id: request_id.to_string(),
+            parent,
+        })
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<TraceScope>> = const { RefCell::new(None) };
+}
+
+/// Sets the scope of the shim calls the calling thread makes through stores
+/// without one of their own, `None` clears it.
+pub fn set_current(scope: Option<TraceScope>) {
+    CURRENT.with(|current| *current.borrow_mut() = scope);
+}
+
+pub fn current() -> Option<TraceScope> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// `resize_pty` -> `ResizePty`
+fn method_name(rpc: &str) -> String {
+    rpc.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|c| c.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+/// One shim call.
+#[derive(Clone, Debug)]
+pub struct Span {
+    /// Method as the runtime config names it, `resize_pty`.
+    pub rpc: String,
+    pub container_id: String,
+    pub exec_id: String,
+    /// Empty when the connection sends none.
+    pub namespace: String,
+    pub request_id: String,
+    pub context: TraceParent,
+    pub parent_span_id: Option<[u8; 8]>,
+    pub start: SystemTime,
+    pub duration: Duration,
+    /// `None` for a successful call.
+    pub error: Option<String>,
+    started: Instant,
+}
+
+impl Span {
+    pub fn start(rpc: &str, container_id: &str, exec_id: &str, namespace: &str, scope: Option<&TraceScope>) -> Span {
+        let parent = scope.and_then(|s| s.parent);
+        Span {
+            rpc: rpc.to_string(),
+            container_id: container_id.to_string(),
+            exec_id: exec_id.to_string(),
+            namespace: namespace.to_string(),
+            request_id: scope.map(|s| s.request_id.clone()).unwrap_or_default(),
+            context: parent.map_or_else(TraceParent::root, |p| p.child()),
+            parent_span_id: parent.map(|p| p.span_id),
+            start: SystemTime::now(),
+            duration: Duration::default(),
+            error: None,
+            started: Instant::now(),
+        }
+    }
+
+    /// Headers carrying the span to the shim.
+    pub fn headers(&self) -> Vec<(&'static str, String)> {
+        let mut headers = vec![(TRACEPARENT_HEADER, self.context.to_header())];
+        if !self.request_id.is_empty() {
+            headers.push((REQUEST_ID_HEADER, self.request_id.clone()));
+        }
+        headers
+    }
+
+    /// Records the outcome and exports the span.
+    pub fn end(mut self, error: Option<String>) {
+        self.duration = self.started.elapsed();
+        self.error = error;
+        export(&self);
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}/{}", SHIM_SERVICE, method_name(&self.rpc))
+    }
+
+    /// The span as an OTLP-JSON `ExportTraceServiceRequest`, ids in hex and
+    /// times as strings of nanoseconds.
+    pub fn to_otlp_json(&self) -> serde_json::Value {
+        let start = self.start.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let attribute = |key: &str, value: &str| json!({"key": key, "value": {"stringValue": value}});
+        let mut attributes = vec![
+            attribute("rpc.system", "ttrpc"),
+            attribute("rpc.service", SHIM_SERVICE),
+            attribute("rpc.method", &method_name(&self.rpc)),
+            attribute("container.id", &self.container_id),
+        ];
+        if !self.namespace.is_empty() {
+            attributes.push(attribute("containerd.namespace", &self.namespace));
+        }
+        if !self.exec_id.is_empty() {
+            attributes.push(attribute("exec.id", &self.exec_id));
+        }
+        if !self.request_id.is_empty() {
+            attributes.push(attribute("request.id", &self.request_id));
+        }
+        let status = match self.error {
+            None => json!({"code": STATUS_CODE_OK}),
+            Some(ref e) => json!({"code": STATUS_CODE_ERROR, "message": e}),
+        };
+        json!({"resourceSpans": [{
+            "resource": {"attributes": [attribute("service.name", SCOPE_NAME)]},
+            "scopeSpans": [{
+                "scope": {"name": SCOPE_NAME},
+                "spans": [{
+                    "traceId": to_hex(&self.context.trace_id),
+                    "spanId": to_hex(&self.context.span_id),
+                    "parentSpanId": self.parent_span_id.map(|id| to_hex(&id)).unwrap_or_default(),
+                    "name": self.name(),
+                    "kind": SPAN_KIND_CLIENT,
+                    "startTimeUnixNano": start.as_nanos().to_string(),
+                    "endTimeUnixNano": (start + self.duration).as_nanos().to_string(),
+                    "attributes": attributes,
+                    "status": status,
+                }],
+            }],
+        }]})
+    }
+}
+
+/// Where finished spans go.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum SpanExport {
+    #[default]
+    Off,
+    /// An info record of target `shim_v2::trace` per span.
+    Log,
+    /// One OTLP-JSON line per span appended to the file.
+    File(PathBuf),
+}
+
+struct Exporter {
+    export: SpanExport,
+    file: Option<Arc<File>>,
+}
+
+lazy_static! {
+    static ref EXPORTER: Mutex<Exporter> = Mutex::new(Exporter {
+        export: SpanExport::Off,
+        file: None,
+    });
+}
+
+pub fn set_export(export: SpanExport) -> Result<()> {
+    let file = match export {
+        SpanExport::File(ref path) => Some(Arc::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| other!(format!("failed to open {}: {}", path.to_string_lossy(), e)))?,
+        )),
+        _ => None,
+    };
+    *EXPORTER.lock().unwrap() = Exporter { export, file };
+    Ok(())
+}
+
+// the lock is only held to look the exporter up, spans of concurrent calls
+// are written in parallel
+fn export(span: &Span) {
+    let (log, file) = {
+        let exporter = EXPORTER.lock().unwrap();
+        (exporter.export == SpanExport::Log, exporter.file.clone())
+    };
+    if log {
+        log::info!(
+            target: "shim_v2::trace",
+            "{} container={} exec={} request_id={} trace_id={} span_id={} duration={:?} outcome={}",
+            span.name(),
+            span.container_id,
+            span.exec_id,
+            span.request_id,
+            to_hex(&span.context.trace_id),
+            to_hex(&span.context.span_id),
+            span.duration,
+            span.error.as_deref().unwrap_or("ok")
+        );
+    } else if let Some(file) = file {
+        // one write per line, appends of concurrent spans do not interleave;
+        // a lost span must not fail the call
+        let line = format!("{}\n", span.to_otlp_json());
+        let _ = (&*file).write_all(line.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::testutil::TempDir;
+
+    const HEADER: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_traceparent() {
+        let parent = TraceParent::parse(HEADER).unwrap();
+        assert_eq!(parent.to_header(), HEADER);
+        assert_eq!(parent.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+        // a later version with more fields
+        assert!(TraceParent::parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00-x").is_ok());
+
+        for header in [
+            "",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-x",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        ] {
+            assert!(
+                matches!(TraceParent::parse(header), Err(Error::InvalidArgument(_))),
+                "{:?} should be invalid",
+                header
+            );
+        }
+
+        let child = parent.child();
+        assert_eq!((child.trace_id, child.flags), (parent.trace_id, parent.flags));
+        assert_ne!(child.span_id, parent.span_id);
+        assert_ne!(TraceParent::root().trace_id, TraceParent::root().trace_id);
+    }
+
+    #[test]
+    fn test_span() {
+        let scope = TraceScope::new("req-1", HEADER).unwrap();
+        let mut span = Span::start("resize_pty", "c1", "e1", "k8s.io", Some(&scope));
+        assert_eq!(span.name(), "containerd.task.v2.Task/ResizePty");
+        let headers = span.headers();
+        assert_eq!(headers[0].0, TRACEPARENT_HEADER);
+        assert!(headers[0].1.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"));
+        assert_eq!(headers[1], (REQUEST_ID_HEADER, "req-1".to_string()));
+
+        span.duration = Duration::from_millis(2);
+        span.error = Some("ttrpc call resize_pty failed".to_string());
+        let json = span.to_otlp_json();
+        let otlp = &json["resourceSpans"][0]["scopeSpans"][0]["spans"][0];
+        assert_eq!(otlp["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(otlp["parentSpanId"], "00f067aa0ba902b7");
+        assert_eq!(otlp["kind"], 3);
+        assert_eq!(otlp["status"]["code"], 2);
+        let start: u128 = otlp["startTimeUnixNano"].as_str().unwrap().parse().unwrap();
+        let end: u128 = otlp["endTimeUnixNano"].as_str().unwrap().parse().unwrap();
+        assert_eq!(end - start, 2_000_000);
+        let attributes = otlp["attributes"].as_array().unwrap();
+        assert!(attributes.contains(&json!({"key": "exec.id", "value": {"stringValue": "e1"}})));
+
+        let span = Span::start("state", "c1", "", "default", None);
+        assert_eq!(span.headers().len(), 1);
+        assert!(span.parent_span_id.is_none());
+        assert!(TraceScope::new("", "garbage").is_err());
+    }
+
+    #[test]
+    fn test_export_file() {
+        let dir = TempDir::new("spans");
+        let path = dir.join("spans.json");
+        set_export(SpanExport::File(path.clone())).unwrap();
+        Span::start("state", "c1", "", "default", None).end(None);
+        Span::start("kill", "c1", "", "default", None).end(Some("failed".to_string()));
+        set_export(SpanExport::Off).unwrap();
+        Span::start("pids", "c1", "", "default", None).end(None);
+
+        let data = std::fs::read_to_string(&path).unwrap();
+        let names: Vec<String> = data
+            .lines()
+            .map(|line| {
+                let v: serde_json::Value = serde_json::from_str(line).unwrap();
+                v["resourceSpans"][0]["scopeSpans"][0]["spans"][0]["name"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            names,
+            vec!["containerd.task.v2.Task/State", "containerd.task.v2.Task/Kill"]
+        );
+        assert!(set_export(SpanExport::File(PathBuf::from("/nonexistent/spans.json"))).is_err());
+    }
+}
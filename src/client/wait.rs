@@ -0,0 +1,315 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Wait calls made on a pool of worker threads, their outcome is delivered
+//! to a completion instead of the caller blocking.
+//!
+//! The ttrpc client blocks a thread per call in flight, so every wait in the
+//! shim takes a worker. The pool grows up to `MAX_WAIT_WORKERS`, a wait
+//! beyond that fails at once rather than waiting for a worker that may only
+//! come free when some other process exits. Idle workers exit after
+//! `WORKER_IDLE_TIMEOUT`. Each wait makes its call over a connection of its
+//! own, cancelling the wait closes it, which frees the worker.
+
+use super::client::{get_conn, Store, WaitResponse};
+use super::error::{Error, Result};
+use super::trace;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const WORKER_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+/// Most waits in the shim at a time, with one thread each.
+pub const MAX_WAIT_WORKERS: usize = 256;
+
+/// How a wait completed, every wait completes exactly once.
+#[derive(Debug)]
+pub enum WaitEvent {
+    Exited(WaitResponse),
+    Error(Error),
+    /// By `cancel_wait` or by closing the connection.
+    Cancelled,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+type Completion = Box<dyn FnOnce(WaitEvent) + Send>;
+
+#[derive(Default)]
+struct Workers {
+    running: usize,
+    idle: usize,
+}
+
+struct Pool {
+    max_workers: usize,
+    jobs: Mutex<Sender<Job>>,
+    queue: Arc<Mutex<Receiver<Job>>>,
+    workers: Arc<Mutex<Workers>>,
+}
+
+impl Pool {
+    fn new(max_workers: usize) -> Pool {
+        let (tx, rx) = mpsc::channel();
+        Pool {
+            max_workers,
+            jobs: Mutex::new(tx),
+            queue: Arc::new(Mutex::new(rx)),
+            workers: Arc::new(Mutex::new(Workers::default())),
+        }
+    }
+
+    // fails when every worker is busy, a job is never queued behind them
+    fn execute(&self, job: Job) -> Result<()> {
+        let mut workers = self.workers.lock().unwrap();
+        if workers.idle > 0 {
+            workers.idle -= 1;
+        } else if workers.running < self.max_workers {
+            let (queue, state) = (self.queue.clone(), self.workers.clone());
+            thread::Builder::new()
+                .name("shim-v2-wait".to_string())
+                .spawn(move || worker(queue, state))
+                .map_err(other_error!(e, "failed to spawn wait worker:"))?;
+            workers.running += 1;
+        } else {
+            return Err(other!(format!("all {} wait workers are busy", self.max_workers)));
+        }
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(job)
+            .map_err(other_error!(e, "failed to queue wait:"))
+    }
+}
+
+fn worker(queue: Arc<Mutex<Receiver<Job>>>, workers: Arc<Mutex<Workers>>) {
+    loop {
+        let job = queue.lock().unwrap().recv_timeout(WORKER_IDLE_TIMEOUT);
+        match job {
+            Ok(job) => {
+                job();
+                workers.lock().unwrap().idle += 1;
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                // none idle means a job was just given to this worker
+                let mut workers = workers.lock().unwrap();
+                if workers.idle > 0 {
+                    workers.idle -= 1;
+                    workers.running -= 1;
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => return,
+        }
+    }
+}
+
+struct Pending {
+    container_id: String,
+    completion: Completion,
+    // the wait's own connection once its call is made
+    conn: Option<Store>,
+}
+
+lazy_static! {
+    static ref POOL: Pool = Pool::new(MAX_WAIT_WORKERS);
+    static ref WAITS: Mutex<HashMap<u64, Pending>> = Mutex::new(HashMap::new());
+}
+
+static NEXT_WAIT_ID: AtomicU64 = AtomicU64::new(1);
+
+// runs the completion of a pending wait outside the lock, it may start or
+// cancel waits itself
+fn complete(id: u64, event: WaitEvent) -> bool {
+    let pending = WAITS.lock().unwrap().remove(&id);
+    match pending {
+        Some(pending) => {
+            if let (WaitEvent::Cancelled, Some(conn)) = (&event, pending.conn.as_ref()) {
+                conn.disconnect();
+            }
+            (pending.completion)(event);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Waits for the exit of the process `exec_id`, the init process when empty,
+/// on a worker and delivers the outcome there. Returns the id `cancel_wait`
+/// takes, ids are never 0. Fails when `MAX_WAIT_WORKERS` waits are in the
+/// shim already.
+pub fn wait_async<F>(container_id: &str, exec_id: &str, deliver: F) -> Result<u64>
+where
+    F: FnOnce(WaitEvent) + Send + 'static,
+{
+    wait_on(&POOL, container_id, exec_id, deliver)
+}
+
+fn wait_on<F>(pool: &Pool, container_id: &str, exec_id: &str, deliver: F) -> Result<u64>
+where
+    F: FnOnce(WaitEvent) + Send + 'static,
+{
+    let mut store = get_conn(container_id)?;
+    // the worker's call is a span of the caller's scope
+    if let Some(scope) = trace::current() {
+        store = store.with_trace(scope);
+    }
+    let id = NEXT_WAIT_ID.fetch_add(1, Ordering::Relaxed);
+    WAITS.lock().unwrap().insert(
+        id,
+        Pending {
+            container_id: container_id.to_string(),
+            completion: Box::new(deliver),
+            conn: None,
+        },
+    );
+    let exec_id = exec_id.to_string();
+    let job = Box::new(move || {
+        let conn = match store.reconnect() {
+            Ok(conn) => conn,
+            Err(e) => {
+                complete(id, WaitEvent::Error(e));
+                return;
+            }
+        };
+        match WAITS.lock().unwrap().get_mut(&id) {
+            Some(pending) => pending.conn = Some(conn.clone()),
+            // cancelled before the call was made
+            None => return,
+        }
+        let event = match conn.wait_response(&exec_id) {
+            Ok(resp) => WaitEvent::Exited(resp),
+            Err(e) => WaitEvent::Error(e),
+        };
+        complete(id, event);
+    });
+    if let Err(e) = pool.execute(job) {
+        WAITS.lock().unwrap().remove(&id);
+        return Err(e);
+    }
+    Ok(id)
+}
+
+/// Completes the wait with `WaitEvent::Cancelled` on the calling thread,
+/// false when it is not pending anymore. Its call ends with its connection.
+pub fn cancel_wait(id: u64) -> bool {
+    complete(id, WaitEvent::Cancelled)
+}
+
+/// Cancels the pending waits of the container, returns how many there were.
+pub fn cancel_waits(container_id: &str) -> usize {
+    let ids: Vec<u64> = WAITS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(_, pending)| pending.container_id == container_id)
+        .map(|(id, _)| *id)
+        .collect();
+    ids.into_iter().filter(|id| cancel_wait(*id)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::del_conn;
+    use crate::client::runtime::RuntimeHandler;
+    use crate::client::testutil::StuckShim;
+    use std::sync::mpsc::channel;
+    use std::time::Instant;
+
+    #[test]
+    fn test_pool() {
+        let pool = Pool::new(2);
+        let (tx, rx) = channel();
+        let (release, released) = channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        for i in 0..2 {
+            let (tx, released) = (tx.clone(), released.clone());
+            pool.execute(Box::new(move || {
+                released.lock().unwrap().recv().unwrap();
+                tx.send(i).unwrap();
+            }))
+            .unwrap();
+        }
+        // a full pool refuses the job instead of queuing it
+        assert!(matches!(pool.execute(Box::new(|| {})), Err(Error::Other(_))));
+        assert_eq!(pool.workers.lock().unwrap().running, 2);
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        let mut done: Vec<i32> = (0..2).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        done.sort_unstable();
+        assert_eq!(done, vec![0, 1]);
+        thread::sleep(Duration::from_millis(20));
+        // both workers are idle and reused for the next job
+        assert_eq!(pool.workers.lock().unwrap().idle, 2);
+        pool.execute(Box::new(move || tx.send(2).unwrap())).unwrap();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 2);
+        assert_eq!(pool.workers.lock().unwrap().running, 2);
+    }
+
+    #[test]
+    fn test_cancel() {
+        assert!(matches!(wait_async("wait-no-conn", "", |_| {}), Err(Error::Other(_))));
+
+        let (tx, rx) = channel();
+        for id in [1001, 1002] {
+            let tx = tx.clone();
+            WAITS.lock().unwrap().insert(
+                id,
+                Pending {
+                    container_id: "wait-cancel".to_string(),
+                    completion: Box::new(move |event| tx.send((id, event)).unwrap()),
+                    conn: None,
+                },
+            );
+        }
+        assert!(cancel_wait(1001));
+        assert!(!cancel_wait(1001));
+        assert!(matches!(rx.try_recv(), Ok((1001, WaitEvent::Cancelled))));
+        assert_eq!(cancel_waits("wait-other"), 0);
+        assert_eq!(cancel_waits("wait-cancel"), 1);
+        assert!(matches!(rx.try_recv(), Ok((1002, WaitEvent::Cancelled))));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_cancel_frees_worker() {
+        let shim = StuckShim::new("wait-stuck");
+        shim.connect("wait-stuck", RuntimeHandler::default());
+        let pool = Pool::new(1);
+        let (tx, rx) = channel();
+        let id = wait_on(&pool, "wait-stuck", "", move |event| tx.send(event).unwrap()).unwrap();
+        // the only worker is in the call, the next wait is refused
+        assert!(wait_on(&pool, "wait-stuck", "", |_| {}).is_err());
+        let pending = |waits: &HashMap<u64, Pending>| waits.values().filter(|p| p.container_id == "wait-stuck").count();
+        assert_eq!(pending(&WAITS.lock().unwrap()), 1);
+
+        thread::sleep(Duration::from_millis(100));
+        assert!(cancel_wait(id));
+        assert!(matches!(rx.try_recv(), Ok(WaitEvent::Cancelled)));
+        // the call ended with the wait's connection and the worker is back
+        let start = Instant::now();
+        while pool.workers.lock().unwrap().idle == 0 {
+            assert!(start.elapsed() < Duration::from_secs(1));
+            thread::sleep(Duration::from_millis(10));
+        }
+        // the container's own connection is untouched
+        assert!(get_conn("wait-stuck").is_ok());
+        let id = wait_on(&pool, "wait-stuck", "", |_| {}).unwrap();
+        del_conn("wait-stuck");
+        assert!(!cancel_wait(id));
+    }
+}
@@ -13,17 +13,89 @@
 #![crate_type = "dylib"]
 pub mod client;
 pub mod protocols;
+pub mod stats;
+mod raw;
 
 use crate::Status::{
     CreatedStatus, DeletedStatus, PauseStatus, PausingStatus, RunningStatus, StoppedStatus,
     UnknownStatus,
 };
 use client::client::State as client_state;
+use client::error::Error;
+use client::options::CreateOptions as client_create_options;
+use client::options::Mount as client_mount;
+use client::options::RuncOptions as client_runc_options;
+use client::options::RuntimeOptions as client_runtime_options;
 use client::client::Status as client_status;
-use client::client::{del_conn, get_conn, new_conn};
+use client::batch::{stats_batch, BATCH_DEADLINE, BATCH_PARALLELISM};
+use client::cleanup::cleanup_dead_shim;
+use client::fallback::stats_with_fallback;
+use client::bundle::{inspect, BundleInfo as client_bundle_info};
+use client::cache::CacheCounters as client_cache_counters;
+use client::client::CacheStats as client_cache_stats;
+use client::client::{del_conn, get_conn, Store, new_conn, new_conn_with_options, new_conn_with_runtime, ConnectOptions as client_connect_options};
+use client::identifiers;
+use client::metadata::Metadata;
+use client::runtime::load_runtime_config;
+use client::spec::{
+    Capabilities as client_capabilities, ConsoleSize as client_console_size, Process as client_process,
+    Rlimit as client_rlimit, User as client_user,
+};
+use client::stats::{blkio_devices, CgroupMetrics};
+use client::subscribe::{subscribe_stats, unsubscribe_stats, StatsEvent};
+use client::top::ProcessTable as client_process_table;
+use client::trace::{self as client_trace, SpanExport, TraceScope};
+use client::wait::{cancel_wait, wait_async, WaitEvent};
 use std::ffi::{CStr, CString};
-use std::os::raw::{c_char, c_int, c_uint, c_ulonglong};
+use std::os::raw::{c_char, c_int, c_longlong, c_uint, c_ulonglong, c_void};
+use protobuf::well_known_types::any::Any;
 use protocols::metrics::Metrics;
+use raw::{free_raw_string, free_raw_strings, from_raw_array, into_raw_array, into_raw_string, into_raw_strings};
+use stats::{DerivedStats, Pressure, StatsSample, StatsV2};
+
+fn to_bytes(x: *const c_char) -> Vec<u8> {
+    unsafe {
+        if x.is_null() {
+            Vec::new()
+        } else {
+            CStr::from_ptr(x).to_bytes().to_vec()
+        }
+    }
+}
+
+fn to_slice<'a, T>(x: *const T, len: usize) -> &'a [T] {
+    if x.is_null() || len == 0 {
+        &[]
+    } else {
+        unsafe { std::slice::from_raw_parts(x, len) }
+    }
+}
+
+fn to_slice_mut<'a, T>(x: *mut T, len: usize) -> &'a mut [T] {
+    if x.is_null() || len == 0 {
+        &mut []
+    } else {
+        unsafe { std::slice::from_raw_parts_mut(x, len) }
+    }
+}
+
+fn to_strings(x: *const *const c_char, len: usize) -> Vec<String> {
+    to_slice(x, len).iter().map(|s| to_string(*s)).collect()
+}
+
+/// The container id argument of an entry point. Null and non-UTF-8 strings
+/// and ids containerd would reject are refused, with `op` in the log line.
+fn to_container_id(x: *const c_char, op: &str) -> Option<String> {
+    let id = if x.is_null() {
+        Err(Error::InvalidArgument("container id is null".to_string()))
+    } else {
+        unsafe { CStr::from_ptr(x) }
+            .to_str()
+            .map_err(|e| Error::InvalidArgument(format!("container id is not UTF-8: {}", e)))
+            .and_then(|id| identifiers::validate(id).map(|_| id.to_string()))
+    };
+    id.map_err(|e| println!("lib-shim-v2::{}:: failed, {}.", op, e)).ok()
+}
 
 fn to_string(x: *const c_char) -> String {
     unsafe {
@@ -37,7 +109,11 @@ fn to_string(x: *const c_char) -> String {
 
 #[no_mangle]
 pub extern "C" fn shim_v2_new(container_id: *const c_char, addr: *const c_char) -> c_int {
-    let (r_container_id, r_addr) = (to_string(container_id), to_string(addr));
+    let r_container_id = match to_container_id(container_id, "new") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_addr = to_string(addr);
     println!("lib-shim-v2::new::{}:: [{}]", r_container_id, r_addr);
     if let Err(e) = new_conn(&r_container_id, &r_addr) {
         println!("lib-shim-v2::new::{}:: failed, {}.", r_container_id, e);
@@ -48,9 +124,163 @@ pub extern "C" fn shim_v2_new(container_id: *const c_char, addr: *const c_char)
     0
 }
 
+#[no_mangle]
+pub extern "C" fn shim_v2_new_with_runtime(
+    container_id: *const c_char,
+    addr: *const c_char,
+    runtime: *const c_char,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "new_with_runtime") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_addr, r_runtime) = (to_string(addr), to_string(runtime));
+    println!(
+        "lib-shim-v2::new_with_runtime::{}:: [{} {}]",
+        r_container_id, r_addr, r_runtime
+    );
+    if let Err(e) = new_conn_with_runtime(&r_container_id, &r_addr, &r_runtime) {
+        println!(
+            "lib-shim-v2::new_with_runtime::{}:: failed, {}.",
+            r_container_id, e
+        );
+        return -1;
+    }
+
+    println!("lib-shim-v2::new_with_runtime::{}:: done.", r_container_id);
+    0
+}
+
+#[repr(C)]
+pub struct KeyValue {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+/// Settings of `shim_v2_new_ex`, null strings are unset.
+#[repr(C)]
+pub struct ConnectOptions {
+    runtime: *const c_char,
+    containerd_namespace: *const c_char,
+    metadata: *const KeyValue,
+    metadata_len: usize,
+}
+
+impl ConnectOptions {
+    fn to_options(&self) -> Result<client_connect_options, Error> {
+        let mut metadata = Metadata::new(&to_string(self.containerd_namespace))?;
+        for kv in to_slice(self.metadata, self.metadata_len) {
+            metadata.add(&to_string(kv.key), &to_string(kv.value))?;
+        }
+        Ok(client_connect_options {
+            runtime: to_string(self.runtime),
+            metadata,
+        })
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_new_ex(
+    container_id: *const c_char,
+    addr: *const c_char,
+    options: Option<&ConnectOptions>,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "new_ex") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_addr = to_string(addr);
+    let r_options = match options {
+        Some(options) => options.to_options(),
+        None => Ok(client_connect_options::default()),
+    };
+    let r_options = match r_options {
+        Ok(options) => options,
+        Err(e) => {
+            println!("lib-shim-v2::new_ex::{}:: failed, {}.", r_container_id, e);
+            return -1;
+        }
+    };
+    println!(
+        "lib-shim-v2::new_ex::{}:: [{} {} {}]",
+        r_container_id,
+        r_addr,
+        r_options.runtime,
+        r_options.metadata.namespace().unwrap_or_default()
+    );
+    if let Err(e) = new_conn_with_options(&r_container_id, &r_addr, r_options) {
+        println!("lib-shim-v2::new_ex::{}:: failed, {}.", r_container_id, e);
+        return -1;
+    }
+
+    println!("lib-shim-v2::new_ex::{}:: done.", r_container_id);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_load_runtime_config(path: *const c_char) -> c_int {
+    let r_path = to_string(path);
+    println!("lib-shim-v2::load_runtime_config:: [{}]", r_path);
+    if let Err(e) = load_runtime_config(&r_path) {
+        println!("lib-shim-v2::load_runtime_config:: failed, {}.", e);
+        return -1;
+    }
+
+    println!("lib-shim-v2::load_runtime_config:: done.");
+    0
+}
+
+pub const TRACE_EXPORT_OFF: c_int = 0;
+pub const TRACE_EXPORT_LOG: c_int = 1;
+pub const TRACE_EXPORT_FILE: c_int = 2;
+
+#[no_mangle]
+pub extern "C" fn shim_v2_set_trace_export(mode: c_int, path: *const c_char) -> c_int {
+    let r_path = to_string(path);
+    println!("lib-shim-v2::set_trace_export:: [{} {}]", mode, r_path);
+    let r_export = match mode {
+        TRACE_EXPORT_OFF => SpanExport::Off,
+        TRACE_EXPORT_LOG => SpanExport::Log,
+        TRACE_EXPORT_FILE if !r_path.is_empty() => SpanExport::File(r_path.into()),
+        _ => {
+            println!("lib-shim-v2::set_trace_export:: failed, invalid mode {}.", mode);
+            return -1;
+        }
+    };
+    if let Err(e) = client_trace::set_export(r_export) {
+        println!("lib-shim-v2::set_trace_export:: failed, {}.", e);
+        return -1;
+    }
+
+    println!("lib-shim-v2::set_trace_export:: done.");
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_set_trace_context(request_id: *const c_char, traceparent: *const c_char) -> c_int {
+    let (r_request_id, r_traceparent) = (to_string(request_id), to_string(traceparent));
+    if r_request_id.is_empty() && r_traceparent.is_empty() {
+        client_trace::set_current(None);
+        return 0;
+    }
+    match TraceScope::new(&r_request_id, &r_traceparent) {
+        Ok(scope) => {
+            client_trace::set_current(Some(scope));
+            0
+        }
+        Err(e) => {
+            println!("lib-shim-v2::set_trace_context:: failed, {}.", e);
+            -1
+        }
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn shim_v2_close(container_id: *const c_char) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "close") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::close::{}::", r_container_id);
     del_conn(&r_container_id);
     0
@@ -66,8 +296,11 @@ pub extern "C" fn shim_v2_create(
     stderr: *const c_char,
     pid: &mut c_int,
 ) -> c_int {
-    let (r_container_id, r_bundle, r_stdin, r_stdout, r_stderr) = (
-        to_string(container_id),
+    let r_container_id = match to_container_id(container_id, "create") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_bundle, r_stdin, r_stdout, r_stderr) = (
         to_string(bundle),
         to_string(stdin),
         to_string(stdout),
@@ -99,13 +332,257 @@ pub extern "C" fn shim_v2_create(
         })
 }
 
+#[repr(C)]
+pub struct RuncOptions {
+    no_pivot_root: bool,
+    no_new_keyring: bool,
+    shim_cgroup: *const c_char,
+    io_uid: c_uint,
+    io_gid: c_uint,
+    binary_name: *const c_char,
+    root: *const c_char,
+    criu_path: *const c_char,
+    systemd_cgroup: bool,
+    criu_image_path: *const c_char,
+    criu_work_path: *const c_char,
+}
+
+impl RuncOptions {
+    fn to_options(&self) -> client_runc_options {
+        client_runc_options {
+            no_pivot_root: self.no_pivot_root,
+            no_new_keyring: self.no_new_keyring,
+            shim_cgroup: to_string(self.shim_cgroup),
+            io_uid: self.io_uid,
+            io_gid: self.io_gid,
+            binary_name: to_string(self.binary_name),
+            root: to_string(self.root),
+            criu_path: to_string(self.criu_path),
+            systemd_cgroup: self.systemd_cgroup,
+            criu_image_path: to_string(self.criu_image_path),
+            criu_work_path: to_string(self.criu_work_path),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct Mount {
+    r#type: *const c_char,
+    source: *const c_char,
+    target: *const c_char,
+    options: *const *const c_char,
+    options_len: usize,
+}
+
+impl Mount {
+    fn to_mount(&self) -> client_mount {
+        client_mount {
+            r#type: to_string(self.r#type),
+            source: to_string(self.source),
+            target: to_string(self.target),
+            options: to_slice(self.options, self.options_len)
+                .iter()
+                .map(|o| to_string(*o))
+                .collect(),
+        }
+    }
+}
+
+#[repr(C)]
+pub struct CreateOptions<'a> {
+    runc_options: Option<&'a RuncOptions>,
+    config_path: *const c_char,
+    rootfs: *const Mount,
+    rootfs_len: usize,
+}
+
+impl CreateOptions<'_> {
+    fn to_options(&self) -> Result<client_create_options, Error> {
+        Ok(client_create_options {
+            options: self.shim_options()?,
+            rootfs: to_slice(self.rootfs, self.rootfs_len)
+                .iter()
+                .map(|m| m.to_mount())
+                .collect(),
+        })
+    }
+
+    fn shim_options(&self) -> Result<Option<Any>, Error> {
+        let config_path = to_string(self.config_path);
+        match (self.runc_options, config_path.is_empty()) {
+            (Some(_), false) => Err(Error::InvalidArgument(
+                "runc options and config path are exclusive".to_string(),
+            )),
+            (Some(runc_options), true) => runc_options.to_options().to_any().map(Some),
+            (None, false) => client_runtime_options {
+                config_path,
+                ..Default::default()
+            }
+            .to_any()
+            .map(Some),
+            (None, true) => Ok(None),
+        }
+    }
+}
+
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn shim_v2_create_ex(
+    container_id: *const c_char,
+    bundle: *const c_char,
+    terminal: bool,
+    stdin: *const c_char,
+    stdout: *const c_char,
+    stderr: *const c_char,
+    options: Option<&CreateOptions>,
+    pid: &mut c_int,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "create_ex") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_bundle, r_stdin, r_stdout, r_stderr) = (
+        to_string(bundle),
+        to_string(stdin),
+        to_string(stdout),
+        to_string(stderr),
+    );
+    println!(
+        "lib-shim-v2::create_ex::{}:: [{} {} {} {} {}]",
+        r_container_id, r_bundle, terminal, r_stdin, r_stdout, r_stderr
+    );
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            let options = match options {
+                Some(options) => options.to_options()?,
+                None => client_create_options::default(),
+            };
+            client
+                .create_with_options(
+                    &r_bundle,
+                    terminal,
+                    &r_stdin,
+                    &r_stdout,
+                    &r_stderr,
+                    options,
+                )
+                .map(|process_pid| {
+                    *pid = process_pid;
+                    println!("lib-shim-v2::create_ex::{}:: done.", r_container_id);
+                    0
+                })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::create_ex::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[repr(C)]
+pub struct Annotation {
+    key: *mut c_char,
+    value: *mut c_char,
+}
+
+/// What `shim_v2_inspect_bundle` found in a bundle, released with
+/// `shim_v2_inspect_bundle_free`.
+#[repr(C)]
+pub struct BundleInfo {
+    oci_version: *mut c_char,
+    rootfs: *mut c_char,
+    hostname: *mut c_char,
+    cgroups_path: *mut c_char,
+    sandbox_id: *mut c_char,
+    container_type: *mut c_char,
+    runtime_handler: *mut c_char,
+    annotations: *mut Annotation,
+    annotations_len: usize,
+}
+
+impl BundleInfo {
+    fn new(in_obj: client_bundle_info) -> BundleInfo {
+        let spec = &in_obj.spec;
+        let annotations: Vec<Annotation> = spec
+            .annotations
+            .iter()
+            .map(|(key, value)| Annotation {
+                key: into_raw_string(key),
+                value: into_raw_string(value),
+            })
+            .collect();
+        let (annotations, annotations_len) = into_raw_array(annotations);
+        BundleInfo {
+            oci_version: into_raw_string(&spec.oci_version),
+            rootfs: into_raw_string(&in_obj.rootfs.to_string_lossy()),
+            hostname: into_raw_string(&spec.hostname),
+            cgroups_path: into_raw_string(
+                spec.linux.as_ref().map(|l| l.cgroups_path.as_str()).unwrap_or_default(),
+            ),
+            sandbox_id: into_raw_string(&in_obj.sandbox_id),
+            container_type: into_raw_string(&in_obj.container_type),
+            runtime_handler: into_raw_string(&in_obj.runtime_handler),
+            annotations,
+            annotations_len,
+        }
+    }
+
+    unsafe fn free(&mut self) {
+        for s in [
+            &mut self.oci_version,
+            &mut self.rootfs,
+            &mut self.hostname,
+            &mut self.cgroups_path,
+            &mut self.sandbox_id,
+            &mut self.container_type,
+            &mut self.runtime_handler,
+        ] {
+            free_raw_string(*s);
+            *s = std::ptr::null_mut();
+        }
+        for a in from_raw_array(self.annotations, self.annotations_len) {
+            free_raw_string(a.key);
+            free_raw_string(a.value);
+        }
+        self.annotations = std::ptr::null_mut();
+        self.annotations_len = 0;
+    }
+}
+
+/// Checks a bundle's config.json and reports what it describes, see
+/// `bundle::inspect`.
+#[no_mangle]
+pub extern "C" fn shim_v2_inspect_bundle(bundle: *const c_char, info: &mut BundleInfo) -> c_int {
+    let r_bundle = to_string(bundle);
+    println!("lib-shim-v2::inspect_bundle::{}::", r_bundle);
+    inspect(&r_bundle)
+        .map(|bundle_info| {
+            *info = BundleInfo::new(bundle_info);
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::inspect_bundle::{}:: failed, {}.", r_bundle, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_inspect_bundle_free(info: Option<&mut BundleInfo>) {
+    if let Some(info) = info {
+        unsafe { info.free() };
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn shim_v2_start(
     container_id: *const c_char,
     exec_id: *const c_char,
     pid: &mut c_int,
 ) -> c_int {
-    let (r_container_id, r_exec_id) = (to_string(container_id), to_string(exec_id));
+    let r_container_id = match to_container_id(container_id, "start") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
     println!("lib-shim-v2::start::{}:: [{}]", r_container_id, r_exec_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -130,7 +607,11 @@ pub extern "C" fn shim_v2_kill(
     signal: u32,
     all: bool,
 ) -> c_int {
-    let (r_container_id, r_exec_id) = (to_string(container_id), to_string(exec_id));
+    let r_container_id = match to_container_id(container_id, "kill") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
     println!("lib-shim-v2::kill::{}:: [{}]", r_container_id, r_exec_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -159,7 +640,11 @@ pub extern "C" fn shim_v2_delete(
     exec_id: *const c_char,
     resp: &mut DeleteResponse,
 ) -> c_int {
-    let (r_container_id, r_exec_id) = (to_string(container_id), to_string(exec_id));
+    let r_container_id = match to_container_id(container_id, "delete") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
     println!("lib-shim-v2::delete::{}:: [{}]", r_container_id, r_exec_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -176,9 +661,57 @@ pub extern "C" fn shim_v2_delete(
         })
 }
 
+#[repr(C)]
+pub struct CleanupResponse {
+    exit_status: c_uint,
+    pid: c_uint,
+    exited_at: c_longlong,
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_cleanup_dead_shim(
+    runtime: *const c_char,
+    containerd_namespace: *const c_char,
+    container_id: *const c_char,
+    bundle: *const c_char,
+    resp: &mut CleanupResponse,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "cleanup_dead_shim") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_runtime, r_namespace, r_bundle) = (
+        to_string(runtime),
+        to_string(containerd_namespace),
+        to_string(bundle),
+    );
+    println!(
+        "lib-shim-v2::cleanup_dead_shim::{}:: [{} {} {}]",
+        r_container_id, r_runtime, r_namespace, r_bundle
+    );
+    cleanup_dead_shim(&r_runtime, &r_namespace, &r_container_id, &r_bundle)
+        .map(|response| {
+            resp.exit_status = response.exit_status;
+            resp.pid = response.pid;
+            resp.exited_at = response.exited_at;
+            println!("lib-shim-v2::cleanup_dead_shim::{}:: done.", r_container_id);
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!(
+                "lib-shim-v2::cleanup_dead_shim::{}:: failed, {}.",
+                r_container_id, e
+            );
+            -1
+        })
+}
+
 #[no_mangle]
 pub extern "C" fn shim_v2_shutdown(container_id: *const c_char) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "shutdown") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::shutdown::{}::", r_container_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -203,17 +736,17 @@ pub extern "C" fn shim_v2_exec(
     stderr: *const c_char,
     spec: *const c_char,
 ) -> c_int {
-    let (r_container_id, r_exec_id, r_stdin, r_stdout, r_stderr) = (
-        to_string(container_id),
+    let r_container_id = match to_container_id(container_id, "exec") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_exec_id, r_stdin, r_stdout, r_stderr) = (
         to_string(exec_id),
         to_string(stdin),
         to_string(stdout),
         to_string(stderr),
     );
-    let r_spec;
-    unsafe {
-        r_spec = CStr::from_ptr(spec).to_bytes();
-    }
+    let r_spec = to_bytes(spec);
     println!(
         "lib-shim-v2::exec::{}:: [{} {} {} {} {}]",
         r_container_id, r_exec_id, terminal, r_stdin, r_stdout, r_stderr
@@ -227,7 +760,7 @@ pub extern "C" fn shim_v2_exec(
                     &r_stdin,
                     &r_stdout,
                     &r_stderr,
-                    r_spec,
+                    &r_spec,
                 )
                 .map(|_| {
                     println!("lib-shim-v2::exec::{}:: done.", r_container_id);
@@ -240,6 +773,172 @@ pub extern "C" fn shim_v2_exec(
         })
 }
 
+#[repr(C)]
+pub struct Rlimit {
+    r#type: *const c_char,
+    hard: c_ulonglong,
+    soft: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct Capabilities {
+    bounding: *const *const c_char,
+    bounding_len: usize,
+    effective: *const *const c_char,
+    effective_len: usize,
+    inheritable: *const *const c_char,
+    inheritable_len: usize,
+    permitted: *const *const c_char,
+    permitted_len: usize,
+    ambient: *const *const c_char,
+    ambient_len: usize,
+}
+
+impl Capabilities {
+    fn to_capabilities(&self) -> client_capabilities {
+        client_capabilities {
+            bounding: to_strings(self.bounding, self.bounding_len),
+            effective: to_strings(self.effective, self.effective_len),
+            inheritable: to_strings(self.inheritable, self.inheritable_len),
+            permitted: to_strings(self.permitted, self.permitted_len),
+            ambient: to_strings(self.ambient, self.ambient_len),
+        }
+    }
+}
+
+/// OCI process spec of an exec, the C side of `client::spec::Process`.
+#[repr(C)]
+pub struct ExecSpec<'a> {
+    args: *const *const c_char,
+    args_len: usize,
+    env: *const *const c_char,
+    env_len: usize,
+    cwd: *const c_char,
+    uid: c_uint,
+    gid: c_uint,
+    additional_gids: *const c_uint,
+    additional_gids_len: usize,
+    username: *const c_char,
+    capabilities: Option<&'a Capabilities>,
+    rlimits: *const Rlimit,
+    rlimits_len: usize,
+    no_new_privileges: bool,
+    apparmor_profile: *const c_char,
+    selinux_label: *const c_char,
+    terminal: bool,
+    console_height: c_uint,
+    console_width: c_uint,
+    has_oom_score_adj: bool,
+    oom_score_adj: c_int,
+}
+
+impl ExecSpec<'_> {
+    fn to_process(&self) -> client_process {
+        client_process {
+            terminal: self.terminal,
+            console_size: if self.console_height == 0 && self.console_width == 0 {
+                None
+            } else {
+                Some(client_console_size {
+                    height: self.console_height,
+                    width: self.console_width,
+                })
+            },
+            user: client_user {
+                uid: self.uid,
+                gid: self.gid,
+                umask: None,
+                additional_gids: to_slice(self.additional_gids, self.additional_gids_len).to_vec(),
+                username: to_string(self.username),
+            },
+            args: to_strings(self.args, self.args_len),
+            env: to_strings(self.env, self.env_len),
+            cwd: to_string(self.cwd),
+            capabilities: self.capabilities.map(|c| c.to_capabilities()),
+            rlimits: to_slice(self.rlimits, self.rlimits_len)
+                .iter()
+                .map(|r| client_rlimit {
+                    r#type: to_string(r.r#type),
+                    hard: r.hard,
+                    soft: r.soft,
+                })
+                .collect(),
+            no_new_privileges: self.no_new_privileges,
+            apparmor_profile: to_string(self.apparmor_profile),
+            oom_score_adj: if self.has_oom_score_adj {
+                Some(self.oom_score_adj)
+            } else {
+                None
+            },
+            selinux_label: to_string(self.selinux_label),
+        }
+    }
+}
+
+/// Like `shim_v2_exec` with the process spec built from `spec` instead of
+/// JSON, `spec.terminal` selects whether the process gets a pty.
+#[no_mangle]
+pub extern "C" fn shim_v2_exec_ex(
+    container_id: *const c_char,
+    exec_id: *const c_char,
+    stdin: *const c_char,
+    stdout: *const c_char,
+    stderr: *const c_char,
+    spec: &ExecSpec,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "exec_ex") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let (r_exec_id, r_stdin, r_stdout, r_stderr) = (
+        to_string(exec_id),
+        to_string(stdin),
+        to_string(stdout),
+        to_string(stderr),
+    );
+    let process = spec.to_process();
+    println!(
+        "lib-shim-v2::exec_ex::{}:: [{} {:?} {} {} {}]",
+        r_container_id, r_exec_id, process.args, r_stdin, r_stdout, r_stderr
+    );
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client
+                .exec_process(&r_exec_id, &r_stdin, &r_stdout, &r_stderr, &process)
+                .map(|_| {
+                    println!("lib-shim-v2::exec_ex::{}:: done.", r_container_id);
+                    0
+                })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::exec_ex::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+/// The runtime-spec JSON of `spec`, released with `shim_v2_exec_spec_json_free`.
+#[no_mangle]
+pub extern "C" fn shim_v2_exec_spec_json(spec: &ExecSpec, out: &mut *mut c_char) -> c_int {
+    spec.to_process()
+        .to_json()
+        .and_then(|data| {
+            CString::new(data).map_err(|e| Error::InvalidArgument(format!("bad process spec: {}", e)))
+        })
+        .map(|data| {
+            *out = data.into_raw();
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::exec_spec_json:: failed, {}.", e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_exec_spec_json_free(data: *mut c_char) {
+    free_raw_string(data);
+}
+
 #[no_mangle]
 pub extern "C" fn shim_v2_resize_pty(
     container_id: *const c_char,
@@ -247,7 +946,11 @@ pub extern "C" fn shim_v2_resize_pty(
     height: u32,
     width: u32,
 ) -> c_int {
-    let (r_container_id, r_exec_id) = (to_string(container_id), to_string(exec_id));
+    let r_container_id = match to_container_id(container_id, "resize_pty") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
     println!(
         "lib-shim-v2::resize_pty::{}:: [{}]",
         r_container_id, r_exec_id
@@ -272,7 +975,10 @@ pub extern "C" fn shim_v2_resize_pty(
 
 #[no_mangle]
 pub extern "C" fn shim_v2_pause(container_id: *const c_char) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "pause") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::pause::{}::", r_container_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -289,7 +995,10 @@ pub extern "C" fn shim_v2_pause(container_id: *const c_char) -> c_int {
 
 #[no_mangle]
 pub extern "C" fn shim_v2_resume(container_id: *const c_char) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "resume") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::resume::{}::", r_container_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -356,7 +1065,10 @@ impl State {
 
 #[no_mangle]
 pub extern "C" fn shim_v2_state(container_id: *const c_char, state: &mut State) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "state") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::state::{}::", r_container_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -373,6 +1085,7 @@ pub extern "C" fn shim_v2_state(container_id: *const c_char, state: &mut State)
 }
 
 #[repr(C)]
+#[derive(Default)]
 pub struct Stats {
     pids_current: c_ulonglong,
     /* CPU usage */
@@ -411,28 +1124,30 @@ impl Stats {
         self.major_page_faults = in_obj.memory.pg_maj_fault;
         self.kmem_used = in_obj.memory.kernel.usage;
         self.kmem_limit = in_obj.memory.kernel.limit;
+        self.cache = in_obj.memory.cache;
+        self.cache_total = in_obj.memory.total_cache;
         self.inactive_file_total = in_obj.memory.total_inactive_file;
         self.swap_limit = in_obj.memory.swap.limit;
         self.swap_used = in_obj.memory.swap.usage;
-        for ele in in_obj.blkio.io_service_bytes_recursive.iter() {
-            if ele.op == "read" {
-                self.blkio_read += ele.value;
-            } else {
-                self.blkio_write += ele.value;
-            }
+        for dev in blkio_devices(&in_obj.blkio).iter() {
+            self.blkio_read += dev.bytes.read;
+            self.blkio_write += dev.bytes.write;
         }
     }
 }
 
 #[no_mangle]
 pub extern "C" fn shim_v2_stats(container_id: *const c_char, stats: &mut Stats) -> c_int {
-    let r_container_id = to_string(container_id);
+    let r_container_id = match to_container_id(container_id, "stats") {
+        Some(id) => id,
+        None => return -1,
+    };
     println!("lib-shim-v2::stats::{}::", r_container_id);
     get_conn(&r_container_id)
         .and_then(|client| {
             client.stats().map(|metrics| {
                 println!("lib-shim-v2::metrics data::{:?}", metrics);
-                stats.copy(metrics);
+                stats.copy(metrics.normalize());
                 0
             })
         })
@@ -442,15 +1157,389 @@ pub extern "C" fn shim_v2_stats(container_id: *const c_char, stats: &mut Stats)
         })
 }
 
+/// Like `shim_v2_stats`, but reads the container's cgroup directly when the
+/// shim does not answer, `fallback` tells which source the stats came from.
 #[no_mangle]
-pub extern "C" fn shim_v2_pids(container_id: *const c_char, pid: &mut c_int) -> c_int {
-    let r_container_id = to_string(container_id);
-    println!("in rutst::shim_v2_pids::{}:: start.", r_container_id);
-    get_conn(&r_container_id)
-        .and_then(|client| {
-            client.pids().map(|process_pid| {
-                *pid = process_pid;
-                println!("in rust::shim_v2_pids::{}:: done", r_container_id);
+pub extern "C" fn shim_v2_stats_with_fallback(
+    container_id: *const c_char,
+    bundle: *const c_char,
+    stats: &mut Stats,
+    fallback: &mut bool,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_with_fallback") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_bundle = to_string(bundle);
+    println!("lib-shim-v2::stats_with_fallback::{}::", r_container_id);
+    stats_with_fallback(&r_container_id, &r_bundle)
+        .map(|collected| {
+            stats.copy(collected.metrics.normalize());
+            *fallback = collected.fallback;
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!(
+                "lib-shim-v2::stats_with_fallback::{}:: failed, {}.",
+                r_container_id, e
+            );
+            -1
+        })
+}
+
+/// Stats of `n` containers, queried `parallelism` at a time. `results[i]` is
+/// filled and `errors[i]` set to 0 for every container that answered within
+/// `deadline_ms`, `errors[i]` is -1 for the others. 0 selects
+/// `BATCH_PARALLELISM` and `BATCH_DEADLINE`.
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_batch(
+    container_ids: *const *const c_char,
+    n: usize,
+    parallelism: c_uint,
+    deadline_ms: c_uint,
+    results: *mut Stats,
+    errors: *mut c_int,
+) -> c_int {
+    let r_container_ids: Vec<Option<String>> = to_slice(container_ids, n)
+        .iter()
+        .map(|id| to_container_id(*id, "stats_batch"))
+        .collect();
+    let results = to_slice_mut(results, n);
+    let errors = to_slice_mut(errors, n);
+    if r_container_ids.len() != n || results.len() != n || errors.len() != n {
+        println!("lib-shim-v2::stats_batch:: failed, invalid arguments.");
+        return -1;
+    }
+    println!("lib-shim-v2::stats_batch::{:?}::", r_container_ids);
+
+    // invalid ids are not queried, they fail on their own
+    let valid_ids: Vec<String> = r_container_ids.iter().flatten().cloned().collect();
+    let parallelism = match parallelism {
+        0 => BATCH_PARALLELISM,
+        n => n as usize,
+    };
+    let deadline = match deadline_ms {
+        0 => BATCH_DEADLINE,
+        ms => std::time::Duration::from_millis(ms as u64),
+    };
+    let mut batch = stats_batch(&valid_ids, parallelism, deadline).into_iter();
+    let mut failed = 0;
+    for (i, id) in r_container_ids.iter().enumerate() {
+        let result = match id {
+            Some(_) => batch
+                .next()
+                .unwrap_or_else(|| Err(Error::Other("no batch result".to_string()))),
+            None => Err(Error::InvalidArgument("invalid container id".to_string())),
+        };
+        match result {
+            Ok(metrics) => {
+                let mut stats = Stats::default();
+                stats.copy(metrics.normalize());
+                results[i] = stats;
+                errors[i] = 0;
+            }
+            Err(e) => {
+                println!(
+                    "lib-shim-v2::stats_batch::{}:: failed, {}.",
+                    id.as_deref().unwrap_or_default(),
+                    e
+                );
+                errors[i] = -1;
+                failed += 1;
+            }
+        }
+    }
+    println!("lib-shim-v2::stats_batch:: done, {} of {} failed.", failed, n);
+    if failed == 0 {
+        0
+    } else {
+        -1
+    }
+}
+
+// StatsV2 of the metrics with the pressure and network stats of the sources
+// the container's runtime selects
+fn stats_v2(client: &Store, metrics: &CgroupMetrics) -> StatsV2 {
+    let mut stats = StatsV2::new(metrics);
+    // both are optional, a host without PSI must not fail the stats
+    if let Ok(pressure) = client.pressure_of(metrics) {
+        stats.pressure = Pressure::new(&pressure);
+    }
+    if let Ok(network) = client.network_of(metrics) {
+        stats.set_network(&network);
+    }
+    stats
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_v2(container_id: *const c_char, stats: &mut StatsV2) -> c_int {
+    // whatever the struct held is not ours to free, a failed call leaves it
+    // empty for shim_v2_stats_v2_free
+    *stats = StatsV2::zeroed();
+    let r_container_id = match to_container_id(container_id, "stats_v2") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_v2::{}::", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.stats().map(|metrics| {
+                *stats = stats_v2(&client, &metrics);
+                println!("lib-shim-v2::stats_v2::{}:: done.", r_container_id);
+                0
+            })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::stats_v2::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_v2_free(stats: Option<&mut StatsV2>) {
+    if let Some(stats) = stats {
+        unsafe { stats.free() };
+    }
+}
+
+pub const STATS_SAMPLE_OK: c_int = 0;
+pub const STATS_SAMPLE_ERROR: c_int = -1;
+pub const STATS_SAMPLE_END: c_int = 1;
+
+pub type StatsCallback =
+    extern "C" fn(container_id: *const c_char, stats: *const StatsV2, status: c_int, user_data: *mut c_void);
+
+struct UserData(*mut c_void);
+
+// The pointer is only handed back to the callback, which is documented to
+// run on the sampler or wait worker thread.
+unsafe impl Send for UserData {}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_subscribe(
+    container_id: *const c_char,
+    interval_ms: c_uint,
+    callback: Option<StatsCallback>,
+    user_data: *mut c_void,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_subscribe") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_subscribe::{}:: [{}ms]", r_container_id, interval_ms);
+    let callback = match callback {
+        Some(callback) => callback,
+        None => {
+            println!("lib-shim-v2::stats_subscribe::{}:: failed, no callback.", r_container_id);
+            return -1;
+        }
+    };
+    let c_container_id = CString::new(r_container_id.clone()).unwrap_or_default();
+    let user_data = UserData(user_data);
+    let conn_id = r_container_id.clone();
+    let interval = std::time::Duration::from_millis(interval_ms as u64);
+    let deliver = move |event| {
+        match event {
+            StatsEvent::Stats(metrics) => {
+                // the fallbacks' calls give up after an interval like the
+                // sample's
+                let deadline = std::time::Instant::now() + interval;
+                let mut stats = match get_conn(&conn_id) {
+                    Ok(client) => stats_v2(&client.with_deadline(deadline), &metrics),
+                    Err(_) => StatsV2::new(&metrics),
+                };
+                callback(c_container_id.as_ptr(), &stats, STATS_SAMPLE_OK, user_data.0);
+                unsafe { stats.free() };
+            }
+            StatsEvent::Error(e) => {
+                println!("lib-shim-v2::stats_subscribe::{:?}:: sample failed, {}.", c_container_id, e);
+                callback(c_container_id.as_ptr(), std::ptr::null(), STATS_SAMPLE_ERROR, user_data.0);
+            }
+            StatsEvent::End => {
+                callback(c_container_id.as_ptr(), std::ptr::null(), STATS_SAMPLE_END, user_data.0);
+            }
+        }
+    };
+    subscribe_stats(&r_container_id, interval, deliver)
+    .map(|_| {
+        println!("lib-shim-v2::stats_subscribe::{}:: done.", r_container_id);
+        0
+    })
+    .unwrap_or_else(|e| {
+        println!("lib-shim-v2::stats_subscribe::{}:: failed, {}.", r_container_id, e);
+        -1
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_unsubscribe(container_id: *const c_char) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_unsubscribe") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_unsubscribe::{}::", r_container_id);
+    unsubscribe_stats(&r_container_id);
+    0
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_derived(container_id: *const c_char, stats: &mut DerivedStats) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_derived") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_derived::{}::", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.stats_derived().map(|derived| {
+                *stats = DerivedStats::new(&derived);
+                println!("lib-shim-v2::stats_derived::{}:: done.", r_container_id);
+                0
+            })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::stats_derived::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+/// Like `shim_v2_stats_derived`, with rates against the caller's `sample`,
+/// which is replaced with the new one on success.
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_derived_from(
+    container_id: *const c_char,
+    sample: &mut StatsSample,
+    stats: &mut DerivedStats,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_derived_from") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_derived_from::{}::", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.stats_derived_from(sample.to_sample().as_ref()).map(|(derived, cur)| {
+                *stats = DerivedStats::new(&derived);
+                *sample = StatsSample::new(&cur);
+                println!("lib-shim-v2::stats_derived_from::{}:: done.", r_container_id);
+                0
+            })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::stats_derived_from::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_json(container_id: *const c_char, out: &mut *mut c_char) -> c_int {
+    let r_container_id = match to_container_id(container_id, "stats_json") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::stats_json::{}::", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| client.stats())
+        .and_then(|metrics| metrics.to_json())
+        .and_then(|data| CString::new(data).map_err(other_error!(e, "bad stats JSON:")))
+        .map(|data| {
+            *out = data.into_raw();
+            println!("lib-shim-v2::stats_json::{}:: done.", r_container_id);
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::stats_json::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_stats_json_free(data: *mut c_char) {
+    free_raw_string(data);
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_pressure(container_id: *const c_char, pressure: &mut Pressure) -> c_int {
+    let r_container_id = match to_container_id(container_id, "pressure") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("lib-shim-v2::pressure::{}::", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.pressure().map(|container_pressure| {
+                *pressure = Pressure::new(&container_pressure);
+                println!("lib-shim-v2::pressure::{}:: done.", r_container_id);
+                0
+            })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::pressure::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct CacheCounters {
+    hits: c_ulonglong,
+    coalesced: c_ulonglong,
+    calls: c_ulonglong,
+}
+
+impl CacheCounters {
+    fn new(in_obj: client_cache_counters) -> CacheCounters {
+        CacheCounters {
+            hits: in_obj.hits,
+            coalesced: in_obj.coalesced,
+            calls: in_obj.calls,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct CacheStats {
+    stats: CacheCounters,
+    state: CacheCounters,
+}
+
+impl CacheStats {
+    fn copy(&mut self, in_obj: client_cache_stats) {
+        self.stats = CacheCounters::new(in_obj.stats);
+        self.state = CacheCounters::new(in_obj.state);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_cache_stats(container_id: *const c_char, stats: &mut CacheStats) -> c_int {
+    let r_container_id = match to_container_id(container_id, "cache_stats") {
+        Some(id) => id,
+        None => return -1,
+    };
+    get_conn(&r_container_id)
+        .map(|client| {
+            stats.copy(client.cache_stats());
+            0
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::cache_stats::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_pids(container_id: *const c_char, pid: &mut c_int) -> c_int {
+    let r_container_id = match to_container_id(container_id, "pids") {
+        Some(id) => id,
+        None => return -1,
+    };
+    println!("in rutst::shim_v2_pids::{}:: start.", r_container_id);
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.pids().map(|process_pid| {
+                *pid = process_pid;
+                println!("in rust::shim_v2_pids::{}:: done", r_container_id);
                 0
             })
         })
@@ -460,13 +1549,88 @@ pub extern "C" fn shim_v2_pids(container_id: *const c_char, pid: &mut c_int) ->
         })
 }
 
+/// Process table of `shim_v2_top`, `cells` holds `n_processes` rows of
+/// `n_titles` strings.
+#[repr(C)]
+pub struct ProcessTable {
+    titles: *mut *mut c_char,
+    n_titles: usize,
+    cells: *mut *mut c_char,
+    n_processes: usize,
+}
+
+impl ProcessTable {
+    fn copy(&mut self, in_obj: client_process_table) {
+        self.n_titles = in_obj.titles.len();
+        self.n_processes = in_obj.processes.len();
+        self.titles = into_raw_strings(in_obj.titles);
+        self.cells = into_raw_strings(in_obj.processes.into_iter().flatten().collect());
+    }
+
+    fn free(&mut self) {
+        unsafe {
+            free_raw_strings(self.titles, self.n_titles);
+            free_raw_strings(self.cells, self.n_titles * self.n_processes);
+        }
+        self.titles = std::ptr::null_mut();
+        self.cells = std::ptr::null_mut();
+        self.n_titles = 0;
+        self.n_processes = 0;
+    }
+}
+
+/// ps style process listing. `columns` is a comma separated list of ps
+/// format specifiers such as "pid,user,cmd", NULL or "" for the `ps -ef`
+/// columns. The table is released with `shim_v2_top_free`.
+#[no_mangle]
+pub extern "C" fn shim_v2_top(
+    container_id: *const c_char,
+    columns: *const c_char,
+    table: &mut ProcessTable,
+) -> c_int {
+    let r_container_id = match to_container_id(container_id, "top") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_columns = to_string(columns);
+    println!("lib-shim-v2::top::{}::{}::", r_container_id, r_columns);
+    let names: Vec<&str> = r_columns
+        .split(',')
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+        .collect();
+    get_conn(&r_container_id)
+        .and_then(|client| {
+            client.top(&names).map(|process_table| {
+                table.copy(process_table);
+                println!("lib-shim-v2::top::{}:: done.", r_container_id);
+                0
+            })
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::top::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_top_free(table: Option<&mut ProcessTable>) {
+    if let Some(table) = table {
+        table.free();
+    }
+}
+
 #[no_mangle]
 pub extern "C" fn shim_v2_wait(
     container_id: *const c_char,
     exec_id: *const c_char,
     exit_status: &mut c_int,
 ) -> c_int {
-    let (r_container_id, r_exec_id) = (to_string(container_id), to_string(exec_id));
+    let r_container_id = match to_container_id(container_id, "wait") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
     println!("lib-shim-v2::wait::{}:: [{}]", r_container_id, r_exec_id);
     get_conn(&r_container_id)
         .and_then(|client| {
@@ -482,9 +1646,85 @@ pub extern "C" fn shim_v2_wait(
         })
 }
 
+pub const WAIT_EXITED: c_int = 0;
+pub const WAIT_ERROR: c_int = -1;
+pub const WAIT_CANCELLED: c_int = 1;
+
+pub type WaitCallback = extern "C" fn(
+    container_id: *const c_char,
+    exec_id: *const c_char,
+    status: c_int,
+    exit_status: c_uint,
+    exited_at: c_longlong,
+    user_data: *mut c_void,
+);
+
+#[no_mangle]
+pub extern "C" fn shim_v2_wait_async(
+    container_id: *const c_char,
+    exec_id: *const c_char,
+    callback: Option<WaitCallback>,
+    user_data: *mut c_void,
+) -> c_longlong {
+    let r_container_id = match to_container_id(container_id, "wait_async") {
+        Some(id) => id,
+        None => return -1,
+    };
+    let r_exec_id = to_string(exec_id);
+    println!("lib-shim-v2::wait_async::{}:: [{}]", r_container_id, r_exec_id);
+    let callback = match callback {
+        Some(callback) => callback,
+        None => {
+            println!("lib-shim-v2::wait_async::{}:: failed, no callback.", r_container_id);
+            return -1;
+        }
+    };
+    let c_container_id = CString::new(r_container_id.clone()).unwrap_or_default();
+    let c_exec_id = CString::new(r_exec_id.clone()).unwrap_or_default();
+    let user_data = UserData(user_data);
+    let deliver = move |event| {
+        let (status, exit_status, exited_at) = match event {
+            WaitEvent::Exited(resp) => (WAIT_EXITED, resp.exit_status, resp.exited_at),
+            WaitEvent::Error(e) => {
+                println!("lib-shim-v2::wait_async::{:?}:: failed, {}.", c_container_id, e);
+                (WAIT_ERROR, 0, 0)
+            }
+            WaitEvent::Cancelled => (WAIT_CANCELLED, 0, 0),
+        };
+        callback(
+            c_container_id.as_ptr(),
+            c_exec_id.as_ptr(),
+            status,
+            exit_status,
+            exited_at,
+            user_data.0,
+        );
+    };
+    wait_async(&r_container_id, &r_exec_id, deliver)
+        .map(|id| {
+            println!("lib-shim-v2::wait_async::{}:: done, wait {}.", r_container_id, id);
+            id as c_longlong
+        })
+        .unwrap_or_else(|e| {
+            println!("lib-shim-v2::wait_async::{}:: failed, {}.", r_container_id, e);
+            -1
+        })
+}
+
+#[no_mangle]
+pub extern "C" fn shim_v2_wait_cancel(wait_id: c_longlong) -> c_int {
+    println!("lib-shim-v2::wait_cancel:: [{}]", wait_id);
+    if wait_id <= 0 || !cancel_wait(wait_id as u64) {
+        println!("lib-shim-v2::wait_cancel:: failed, wait {} is not pending.", wait_id);
+        return -1;
+    }
+    0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use client::testutil::TempDir;
     use std::ffi::CString;
     use std::os::raw::c_int;
 
@@ -496,153 +1736,643 @@ mod tests {
         assert_eq!(to_string(raw_ptr), String::from("hello"));
     }
 
+    #[test]
+    fn test_to_container_id() {
+        let valid = CString::new("abc-123").expect("CString::new failed");
+        assert_eq!(to_container_id(valid.as_ptr(), "test"), Some("abc-123".to_string()));
+
+        let non_utf8 = CString::new(vec![b'a', 0xff]).expect("CString::new failed");
+        for id in [
+            CString::new("").expect("CString::new failed"),
+            CString::new("-abc").expect("CString::new failed"),
+            CString::new("a/../b").expect("CString::new failed"),
+            non_utf8,
+        ]
+        .iter()
+        {
+            assert_eq!(to_container_id(id.as_ptr(), "test"), None);
+        }
+        assert_eq!(to_container_id(std::ptr::null(), "test"), None);
+    }
+
+    #[test]
+    fn test_invalid_container_id() {
+        // refused before any connection is looked up or made
+        let cid = CString::new("../12345").expect("CString::new failed");
+        let addr = CString::new("/nonexist/shim.sock").expect("CString::new failed");
+        assert_eq!(shim_v2_new(cid.as_ptr(), addr.as_ptr()), -1);
+        assert_eq!(shim_v2_new(std::ptr::null(), addr.as_ptr()), -1);
+        assert_eq!(shim_v2_close(cid.as_ptr()), -1);
+        assert_eq!(shim_v2_shutdown(std::ptr::null()), -1);
+        let mut pid = 0;
+        assert_eq!(shim_v2_pids(cid.as_ptr(), &mut pid), -1);
+
+        let ids = [cid.as_ptr(), std::ptr::null()];
+        let mut results = vec![Stats::default(), Stats::default()];
+        let mut errors = vec![0, 0];
+        assert_eq!(
+            shim_v2_stats_batch(ids.as_ptr(), 2, 0, 0, results.as_mut_ptr(), errors.as_mut_ptr()),
+            -1
+        );
+        assert_eq!(errors, vec![-1, -1]);
+    }
+
+    #[test]
+    fn test_shim_v2_new_ex_invalid_options() {
+        // refused before connecting
+        let cid = CString::new("12345").expect("CString::new failed");
+        let addr = CString::new("/nonexist/shim.sock").expect("CString::new failed");
+        let namespace = CString::new("k8s/io").expect("CString::new failed");
+        let mut options = ConnectOptions {
+            runtime: std::ptr::null(),
+            containerd_namespace: namespace.as_ptr(),
+            metadata: std::ptr::null(),
+            metadata_len: 0,
+        };
+        assert_eq!(shim_v2_new_ex(cid.as_ptr(), addr.as_ptr(), Some(&options)), -1);
+
+        let (key, value) = (
+            CString::new("X-Tenant").expect("CString::new failed"),
+            CString::new("a").expect("CString::new failed"),
+        );
+        let metadata = [KeyValue {
+            key: key.as_ptr(),
+            value: value.as_ptr(),
+        }];
+        options.containerd_namespace = std::ptr::null();
+        options.metadata = metadata.as_ptr();
+        options.metadata_len = metadata.len();
+        assert_eq!(shim_v2_new_ex(cid.as_ptr(), addr.as_ptr(), Some(&options)), -1);
+        assert!(get_conn("12345").is_err());
+    }
+
+    #[test]
+    fn test_shim_v2_new_with_runtime() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let addr = CString::new("/nonexist/shim.sock").expect("CString::new failed");
+        let runtime = CString::new("unknown").expect("CString::new failed");
+
+        assert_eq!(
+            shim_v2_new_with_runtime(cid.as_ptr(), addr.as_ptr(), runtime.as_ptr()),
+            -1
+        );
+    }
+
+    #[test]
+    fn test_shim_v2_wait_async() {
+        extern "C" fn callback(
+            _container_id: *const c_char,
+            _exec_id: *const c_char,
+            _status: c_int,
+            _exit_status: c_uint,
+            _exited_at: c_longlong,
+            _user_data: *mut c_void,
+        ) {
+        }
+        let cid = CString::new("12345").expect("CString::new failed");
+        let bad_cid = CString::new("../12345").expect("CString::new failed");
+        let exec_id = CString::new("exec1").expect("CString::new failed");
+        let user_data = std::ptr::null_mut();
+        assert_eq!(shim_v2_wait_async(bad_cid.as_ptr(), exec_id.as_ptr(), Some(callback), user_data), -1);
+        assert_eq!(shim_v2_wait_async(cid.as_ptr(), exec_id.as_ptr(), None, user_data), -1);
+        // no connection
+        assert_eq!(shim_v2_wait_async(cid.as_ptr(), exec_id.as_ptr(), Some(callback), user_data), -1);
+        assert_eq!(shim_v2_wait_cancel(0), -1);
+        assert_eq!(shim_v2_wait_cancel(i64::MAX), -1);
+    }
+
+    #[test]
+    fn test_shim_v2_set_trace_context() {
+        let request_id = CString::new("req-1").expect("CString::new failed");
+        let traceparent = CString::new("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01")
+            .expect("CString::new failed");
+        let garbage = CString::new("00-garbage").expect("CString::new failed");
+        assert_eq!(shim_v2_set_trace_context(request_id.as_ptr(), traceparent.as_ptr()), 0);
+        let scope = client_trace::current().unwrap();
+        assert_eq!(scope.request_id, "req-1");
+        assert!(scope.parent.is_some());
+        assert_eq!(shim_v2_set_trace_context(request_id.as_ptr(), garbage.as_ptr()), -1);
+        assert_eq!(client_trace::current(), Some(scope));
+        assert_eq!(shim_v2_set_trace_context(std::ptr::null(), std::ptr::null()), 0);
+        assert!(client_trace::current().is_none());
+
+        assert_eq!(shim_v2_set_trace_export(TRACE_EXPORT_FILE, std::ptr::null()), -1);
+        assert_eq!(shim_v2_set_trace_export(42, std::ptr::null()), -1);
+    }
+
+    #[test]
+    fn test_shim_v2_load_runtime_config() {
+        let path = CString::new("/nonexist/runtimes.json").expect("CString::new failed");
+
+        assert_eq!(shim_v2_load_runtime_config(path.as_ptr()), -1);
+    }
+
     #[test]
     fn test_shim_v2_create() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let bundle = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
-        let stdin = CString::new("stdin")
-            .expect("CString::new stdin failed")
-            .as_ptr();
-        let stdout = CString::new("stdout")
-            .expect("CString::new stdout failed")
-            .as_ptr();
-        let stderr = CString::new("stderr")
-            .expect("CString::new stderr failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let bundle = CString::new("666666").expect("CString::new failed");
+        let stdin = CString::new("stdin").expect("CString::new stdin failed");
+        let stdout = CString::new("stdout").expect("CString::new stdout failed");
+        let stderr = CString::new("stderr").expect("CString::new stderr failed");
         let mut pid: c_int = 0;
 
         assert_eq!(
-            shim_v2_create(cid, bundle, true, stdin, stdout, stderr, &mut pid),
+            shim_v2_create(
+                cid.as_ptr(),
+                bundle.as_ptr(),
+                true,
+                stdin.as_ptr(),
+                stdout.as_ptr(),
+                stderr.as_ptr(),
+                &mut pid
+            ),
             -1
         );
     }
 
+    #[test]
+    fn test_shim_v2_create_ex() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let bundle = CString::new("666666").expect("CString::new failed");
+        let stdin = CString::new("stdin").expect("CString::new stdin failed");
+        let stdout = CString::new("stdout").expect("CString::new stdout failed");
+        let stderr = CString::new("stderr").expect("CString::new stderr failed");
+        let runc = CString::new("runc").expect("CString::new failed");
+        let options = RuncOptions {
+            no_pivot_root: false,
+            no_new_keyring: false,
+            shim_cgroup: std::ptr::null(),
+            io_uid: 0,
+            io_gid: 0,
+            binary_name: runc.as_ptr(),
+            root: std::ptr::null(),
+            criu_path: std::ptr::null(),
+            systemd_cgroup: true,
+            criu_image_path: std::ptr::null(),
+            criu_work_path: std::ptr::null(),
+        };
+        let config_path = CString::new("/etc/kata.toml").expect("CString::new failed");
+        let overlay = CString::new("overlay").expect("CString::new failed");
+        let lowerdir = CString::new("lowerdir=/l").expect("CString::new failed");
+        let mount_options = [lowerdir.as_ptr()];
+        let rootfs = [Mount {
+            r#type: overlay.as_ptr(),
+            source: overlay.as_ptr(),
+            target: std::ptr::null(),
+            options: mount_options.as_ptr(),
+            options_len: mount_options.len(),
+        }];
+        let mut create_options = CreateOptions {
+            runc_options: Some(&options),
+            config_path: std::ptr::null(),
+            rootfs: rootfs.as_ptr(),
+            rootfs_len: rootfs.len(),
+        };
+        let mut pid: c_int = 0;
+
+        assert_eq!(options.to_options().binary_name, "runc");
+        let r_options = create_options.to_options().unwrap();
+        assert!(r_options.options.is_some());
+        assert_eq!(r_options.rootfs.len(), 1);
+        assert_eq!(r_options.rootfs[0].r#type, "overlay");
+        assert_eq!(r_options.rootfs[0].target, "");
+        assert_eq!(r_options.rootfs[0].options, vec!["lowerdir=/l".to_string()]);
+        create_options.config_path = config_path.as_ptr();
+        assert!(create_options.to_options().is_err());
+        create_options.runc_options = None;
+        assert_eq!(
+            create_options.to_options().unwrap().options.unwrap().type_url,
+            "runtimeoptions.v1.Options"
+        );
+        assert_eq!(
+            shim_v2_create_ex(
+                cid.as_ptr(),
+                bundle.as_ptr(),
+                true,
+                stdin.as_ptr(),
+                stdout.as_ptr(),
+                stderr.as_ptr(),
+                Some(&create_options),
+                &mut pid
+            ),
+            -1
+        );
+    }
+
+    #[test]
+    fn test_shim_v2_inspect_bundle() {
+        let bundle = TempDir::new("inspect");
+        std::fs::create_dir(bundle.join("rootfs")).unwrap();
+        bundle.write(
+            "config.json",
+            r#"{"ociVersion": "1.0.2", "root": {"path": "rootfs"},
+                "linux": {"cgroupsPath": "/isulad/abc"},
+                "annotations": {"io.kubernetes.cri.sandbox-id": "sandbox1", "a": "b"}}"#,
+        );
+        let c_bundle = CString::new(bundle.path().to_str().unwrap()).expect("CString::new failed");
+        let mut info = BundleInfo::new(client_bundle_info::default());
+        shim_v2_inspect_bundle_free(Some(&mut info));
+
+        assert_eq!(shim_v2_inspect_bundle(c_bundle.as_ptr(), &mut info), 0);
+        assert_eq!(to_string(info.sandbox_id), "sandbox1");
+        assert_eq!(to_string(info.cgroups_path), "/isulad/abc");
+        assert_eq!(to_string(info.container_type), "");
+        assert_eq!(info.annotations_len, 2);
+        let annotations = to_slice(info.annotations, info.annotations_len);
+        assert_eq!(to_string(annotations[0].key), "a");
+        shim_v2_inspect_bundle_free(Some(&mut info));
+        assert!(info.annotations.is_null());
+        shim_v2_inspect_bundle_free(None);
+
+        let missing = CString::new("/nonexistent-bundle").expect("CString::new failed");
+        assert_eq!(shim_v2_inspect_bundle(missing.as_ptr(), &mut info), -1);
+    }
+
     #[test]
     fn test_shim_v2_start() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
         let mut pid: c_int = 0;
 
-        assert_eq!(shim_v2_start(cid, exec_id, &mut pid), -1);
+        assert_eq!(shim_v2_start(cid.as_ptr(), exec_id.as_ptr(), &mut pid), -1);
     }
 
     #[test]
     fn test_shim_v2_kill() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
 
-        assert_eq!(shim_v2_kill(cid, exec_id, 10, true), -1);
+        assert_eq!(shim_v2_kill(cid.as_ptr(), exec_id.as_ptr(), 10, true), -1);
     }
 
     #[test]
     fn test_shim_v2_delete() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
         let mut resp = DeleteResponse {
             exit_status: 10,
             pid: 123,
         };
 
-        assert_eq!(shim_v2_delete(cid, exec_id, &mut resp), -1);
+        assert_eq!(shim_v2_delete(cid.as_ptr(), exec_id.as_ptr(), &mut resp), -1);
+    }
+
+    #[test]
+    fn test_shim_v2_cleanup_dead_shim() {
+        let runtime = CString::new("io.containerd.nonexist.v2").expect("CString::new failed");
+        let cid = CString::new("12345").expect("CString::new failed");
+        let bundle = CString::new("/").expect("CString::new failed");
+        let mut resp = CleanupResponse {
+            exit_status: 10,
+            pid: 123,
+            exited_at: 0,
+        };
+
+        assert_eq!(
+            shim_v2_cleanup_dead_shim(
+                runtime.as_ptr(),
+                std::ptr::null(),
+                cid.as_ptr(),
+                bundle.as_ptr(),
+                &mut resp
+            ),
+            -1
+        );
     }
 
     #[test]
     fn test_shim_v2_shutdown() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
 
-        assert_eq!(shim_v2_shutdown(cid), -1);
+        assert_eq!(shim_v2_shutdown(cid.as_ptr()), -1);
     }
 
     #[test]
     fn test_shim_v2_exec() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
-        let stdin = CString::new("stdin")
-            .expect("CString::new stdin failed")
-            .as_ptr();
-        let stdout = CString::new("stdout")
-            .expect("CString::new stdout failed")
-            .as_ptr();
-        let stderr = CString::new("stderr")
-            .expect("CString::new stderr failed")
-            .as_ptr();
-        let spec = CString::new("spec")
-            .expect("CString::new spec failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
+        let stdin = CString::new("stdin").expect("CString::new stdin failed");
+        let stdout = CString::new("stdout").expect("CString::new stdout failed");
+        let stderr = CString::new("stderr").expect("CString::new stderr failed");
+        let spec = CString::new("spec").expect("CString::new spec failed");
 
         assert_eq!(
-            shim_v2_exec(cid, exec_id, true, stdin, stdout, stderr, spec),
+            shim_v2_exec(
+                cid.as_ptr(),
+                exec_id.as_ptr(),
+                true,
+                stdin.as_ptr(),
+                stdout.as_ptr(),
+                stderr.as_ptr(),
+                spec.as_ptr()
+            ),
+            -1
+        );
+    }
+
+    #[test]
+    fn test_shim_v2_exec_ex() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
+        let strings: Vec<CString> = ["sh", "-c", "PATH=/bin", "/", "CAP_KILL", "RLIMIT_NOFILE"]
+            .iter()
+            .map(|s| CString::new(*s).expect("CString::new failed"))
+            .collect();
+        let args = [strings[0].as_ptr(), strings[1].as_ptr()];
+        let env = [strings[2].as_ptr()];
+        let caps = [strings[4].as_ptr()];
+        let gids = [10, 20];
+        let capabilities = Capabilities {
+            bounding: caps.as_ptr(),
+            bounding_len: 1,
+            effective: caps.as_ptr(),
+            effective_len: 1,
+            inheritable: std::ptr::null(),
+            inheritable_len: 0,
+            permitted: caps.as_ptr(),
+            permitted_len: 1,
+            ambient: std::ptr::null(),
+            ambient_len: 0,
+        };
+        let rlimits = [Rlimit {
+            r#type: strings[5].as_ptr(),
+            hard: 1024,
+            soft: 512,
+        }];
+        let spec = ExecSpec {
+            args: args.as_ptr(),
+            args_len: args.len(),
+            env: env.as_ptr(),
+            env_len: env.len(),
+            cwd: strings[3].as_ptr(),
+            uid: 1000,
+            gid: 1000,
+            additional_gids: gids.as_ptr(),
+            additional_gids_len: gids.len(),
+            username: std::ptr::null(),
+            capabilities: Some(&capabilities),
+            rlimits: rlimits.as_ptr(),
+            rlimits_len: rlimits.len(),
+            no_new_privileges: true,
+            apparmor_profile: std::ptr::null(),
+            selinux_label: std::ptr::null(),
+            terminal: true,
+            console_height: 24,
+            console_width: 80,
+            has_oom_score_adj: false,
+            oom_score_adj: 0,
+        };
+
+        let process = spec.to_process();
+        assert_eq!(process.args, vec!["sh", "-c"]);
+        assert_eq!(process.user.additional_gids, vec![10, 20]);
+        assert_eq!(process.capabilities.as_ref().unwrap().permitted, vec!["CAP_KILL"]);
+        assert_eq!(process.rlimits[0].soft, 512);
+        assert_eq!(process.console_size.unwrap().width, 80);
+        assert_eq!(process.oom_score_adj, None);
+
+        let mut out = std::ptr::null_mut();
+        assert_eq!(shim_v2_exec_spec_json(&spec, &mut out), 0);
+        assert_eq!(
+            client_process::from_json(to_string(out).as_bytes()).unwrap(),
+            process
+        );
+        shim_v2_exec_spec_json_free(out);
+
+        assert_eq!(
+            shim_v2_exec_ex(
+                cid.as_ptr(),
+                exec_id.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+                std::ptr::null(),
+                &spec
+            ),
             -1
         );
     }
 
     #[test]
     fn test_shim_v2_resize_pty() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
 
-        assert_eq!(shim_v2_resize_pty(cid, exec_id, 10, 10), -1);
+        assert_eq!(shim_v2_resize_pty(cid.as_ptr(), exec_id.as_ptr(), 10, 10), -1);
     }
 
     #[test]
     fn test_shim_v2_pause() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
 
-        assert_eq!(shim_v2_pause(cid), -1);
+        assert_eq!(shim_v2_pause(cid.as_ptr()), -1);
     }
 
     #[test]
     fn test_shim_v2_resume() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
 
-        assert_eq!(shim_v2_resume(cid), -1);
+        assert_eq!(shim_v2_resume(cid.as_ptr()), -1);
     }
 
     #[test]
     fn test_shim_v2_state() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let raw = CString::new("12345").expect("CString::new failed");
         let mut st = State {
-            id: CString::new("12345").expect("CString::new failed").as_ptr(),
+            id: raw.as_ptr(),
             pid: 123,
             status: UnknownStatus,
-            stdin: CString::new("12345").expect("CString::new failed").as_ptr(),
-            stdout: CString::new("12345").expect("CString::new failed").as_ptr(),
-            stderr: CString::new("12345").expect("CString::new failed").as_ptr(),
+            stdin: raw.as_ptr(),
+            stdout: raw.as_ptr(),
+            stderr: raw.as_ptr(),
             terminal: true,
             exit_status: 1,
         };
 
-        assert_eq!(shim_v2_state(cid, &mut st), -1);
+        assert_eq!(shim_v2_state(cid.as_ptr(), &mut st), -1);
+    }
+
+    #[test]
+    fn test_stats_copy_blkio() {
+        let mut m = Metrics::new();
+        for (op, value) in [("Read", 10), ("Write", 20), ("Sync", 5), ("Async", 25), ("Total", 30)] {
+            let mut e = protocols::metrics::BlkIOEntry::new();
+            e.major = 8;
+            e.op = op.to_string();
+            e.value = value;
+            m.blkio.mut_or_insert_default().io_service_bytes_recursive.push(e);
+        }
+        let mut stats: Stats = unsafe { std::mem::zeroed() };
+        stats.copy(m);
+
+        assert_eq!(stats.blkio_read, 10);
+        assert_eq!(stats.blkio_write, 20);
+    }
+
+    #[test]
+    fn test_shim_v2_stats_batch() {
+        let ids: Vec<CString> = ["12345", "67890"]
+            .iter()
+            .map(|id| CString::new(*id).expect("CString::new failed"))
+            .collect();
+        let id_ptrs: Vec<*const c_char> = ids.iter().map(|id| id.as_ptr()).collect();
+        let mut results = vec![Stats::default(), Stats::default()];
+        let mut errors = vec![0, 0];
+
+        assert_eq!(
+            shim_v2_stats_batch(id_ptrs.as_ptr(), 2, 1, 100, results.as_mut_ptr(), errors.as_mut_ptr()),
+            -1
+        );
+        assert_eq!(errors, vec![-1, -1]);
+        assert_eq!(
+            shim_v2_stats_batch(id_ptrs.as_ptr(), 2, 0, 0, std::ptr::null_mut(), errors.as_mut_ptr()),
+            -1
+        );
+        assert_eq!(
+            shim_v2_stats_batch(std::ptr::null(), 0, 0, 0, std::ptr::null_mut(), std::ptr::null_mut()),
+            0
+        );
+    }
+
+    #[test]
+    fn test_shim_v2_stats_with_fallback() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let bundle = CString::new("/nonexistent-bundle").expect("CString::new failed");
+        let mut stats = Stats::default();
+        let mut fallback = false;
+
+        assert_eq!(
+            shim_v2_stats_with_fallback(cid.as_ptr(), bundle.as_ptr(), &mut stats, &mut fallback),
+            -1
+        );
+        assert!(!fallback);
+    }
+
+    #[test]
+    fn test_shim_v2_top() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let columns = CString::new("pid,user,cmd").expect("CString::new failed");
+        let mut table = ProcessTable {
+            titles: std::ptr::null_mut(),
+            n_titles: 0,
+            cells: std::ptr::null_mut(),
+            n_processes: 0,
+        };
+
+        assert_eq!(shim_v2_top(cid.as_ptr(), columns.as_ptr(), &mut table), -1);
+        assert_eq!(shim_v2_top(cid.as_ptr(), std::ptr::null(), &mut table), -1);
+
+        table.copy(client_process_table {
+            titles: vec!["PID".to_string(), "CMD".to_string()],
+            processes: vec![
+                vec!["1".to_string(), "sh".to_string()],
+                vec!["2".to_string(), "sleep 10".to_string()],
+            ],
+        });
+        assert_eq!(table.n_titles, 2);
+        assert_eq!(table.n_processes, 2);
+        let cells = to_slice(table.cells as *const *mut c_char, 4);
+        assert_eq!(to_string(cells[3]), "sleep 10");
+        shim_v2_top_free(Some(&mut table));
+        assert!(table.cells.is_null());
+        shim_v2_top_free(None);
+    }
+
+    #[test]
+    fn test_shim_v2_stats_v2() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        // what an uninitialized struct may hold
+        let mut stats = StatsV2::zeroed();
+        stats.hugetlb = std::ptr::NonNull::dangling().as_ptr();
+        stats.hugetlb_len = 3;
+
+        assert_eq!(shim_v2_stats_v2(cid.as_ptr(), &mut stats), -1);
+        assert!(stats.hugetlb.is_null());
+        assert_eq!(stats.hugetlb_len, 0);
+        shim_v2_stats_v2_free(Some(&mut stats));
+        assert_eq!(shim_v2_stats_v2(std::ptr::null(), &mut stats), -1);
+        shim_v2_stats_v2_free(Some(&mut stats));
+        shim_v2_stats_v2_free(None);
+    }
+
+    extern "C" fn stats_callback(
+        _container_id: *const c_char,
+        _stats: *const StatsV2,
+        _status: c_int,
+        _user_data: *mut c_void,
+    ) {
+    }
+
+    #[test]
+    fn test_shim_v2_stats_subscribe() {
+        let cid = CString::new("12345").expect("CString::new failed");
+
+        assert_eq!(
+            shim_v2_stats_subscribe(cid.as_ptr(), 1000, Some(stats_callback), std::ptr::null_mut()),
+            -1
+        );
+        assert_eq!(
+            shim_v2_stats_subscribe(cid.as_ptr(), 1000, None, std::ptr::null_mut()),
+            -1
+        );
+        assert_eq!(shim_v2_stats_unsubscribe(cid.as_ptr()), 0);
+    }
+
+    #[test]
+    fn test_shim_v2_stats_derived() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let mut stats = DerivedStats::default();
+
+        assert_eq!(shim_v2_stats_derived(cid.as_ptr(), &mut stats), -1);
+        assert!(!stats.has_rates);
+
+        let mut sample = StatsSample::default();
+        assert_eq!(shim_v2_stats_derived_from(cid.as_ptr(), &mut sample, &mut stats), -1);
+        assert!(!sample.valid);
+    }
+
+    #[test]
+    fn test_shim_v2_stats_json() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let mut out: *mut c_char = std::ptr::null_mut();
+
+        assert_eq!(shim_v2_stats_json(cid.as_ptr(), &mut out), -1);
+        assert!(out.is_null());
+        shim_v2_stats_json_free(out);
+        shim_v2_stats_json_free(CString::new("{}").unwrap().into_raw());
+    }
+
+    #[test]
+    fn test_shim_v2_pressure() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let mut pressure = Pressure::default();
+
+        assert_eq!(shim_v2_pressure(cid.as_ptr(), &mut pressure), -1);
+        assert!(!pressure.has_cpu);
+    }
+
+    #[test]
+    fn test_shim_v2_cache_stats() {
+        let cid = CString::new("12345").expect("CString::new failed");
+        let mut stats = CacheStats::default();
+
+        assert_eq!(shim_v2_cache_stats(cid.as_ptr(), &mut stats), -1);
     }
 
     #[test]
     fn test_shim_v2_pids() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
         let mut pid: c_int = 0;
 
-        assert_eq!(shim_v2_pids(cid, &mut pid), -1);
+        assert_eq!(shim_v2_pids(cid.as_ptr(), &mut pid), -1);
     }
 
     #[test]
     fn test_shim_v2_wait() {
-        let cid = CString::new("12345").expect("CString::new failed").as_ptr();
-        let exec_id = CString::new("666666")
-            .expect("CString::new failed")
-            .as_ptr();
+        let cid = CString::new("12345").expect("CString::new failed");
+        let exec_id = CString::new("666666").expect("CString::new failed");
         let mut status: c_int = 0;
 
-        assert_eq!(shim_v2_wait(cid, exec_id, &mut status), -1);
+        assert_eq!(shim_v2_wait(cid.as_ptr(), exec_id.as_ptr(), &mut status), -1);
     }
 }
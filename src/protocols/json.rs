@@ -0,0 +1,214 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! JSON mapping of generated messages through protobuf reflection, so new
+//! proto fields show up without code changes.
+//!
+//! Objects are keyed by the proto field name. Every scalar is written, with
+//! its default when unset, integers are JSON numbers, except unsigned values
+//! above `i64::MAX`, e.g. an unlimited cgroup's `u64::MAX`, which are decimal
+//! strings since many JSON readers lose them as numbers. Enums are their
+//! value name. Unset message fields are `null`. Unknown keys are ignored when
+//! reading, which keeps older readers working with newer writers.
+
+use crate::client::error::{Error, Result};
+use protobuf::reflect::{
+    FieldDescriptor, ReflectValueBox, ReflectValueRef, RuntimeFieldType, RuntimeType,
+};
+use protobuf::MessageDyn;
+use serde_json::{Map, Number, Value};
+use std::convert::TryFrom;
+
+pub fn to_value(msg: &dyn MessageDyn) -> Result<Value> {
+    let mut object = Map::new();
+    for field in msg.descriptor_dyn().fields() {
+        let value = match field.runtime_field_type() {
+            RuntimeFieldType::Singular(RuntimeType::Message(_)) => {
+                if field.has_field(msg) {
+                    to_value(&*field.get_message(msg))?
+                } else {
+                    Value::Null
+                }
+            }
+            RuntimeFieldType::Singular(_) => {
+                scalar_to_value(&field, field.get_singular_field_or_default(msg))?
+            }
+            RuntimeFieldType::Repeated(_) => {
+                let repeated = field.get_repeated(msg);
+                let mut values = Vec::with_capacity(repeated.len());
+                for i in 0..repeated.len() {
+                    values.push(match repeated.get(i) {
+                        ReflectValueRef::Message(m) => to_value(&*m)?,
+                        v => scalar_to_value(&field, v)?,
+                    });
+                }
+                Value::Array(values)
+            }
+            RuntimeFieldType::Map(..) => return Err(unsupported(&field)),
+        };
+        object.insert(field.name().to_string(), value);
+    }
+    Ok(Value::Object(object))
+}
+
+pub fn from_value(value: &Value, msg: &mut dyn MessageDyn) -> Result<()> {
+    let object = value.as_object().ok_or_else(|| {
+        Error::InvalidArgument(format!(
+            "{} must be a JSON object",
+            msg.descriptor_dyn().full_name()
+        ))
+    })?;
+    let descriptor = msg.descriptor_dyn();
+    for (name, value) in object {
+        let field = match descriptor.field_by_name(name) {
+            Some(field) => field,
+            None => continue,
+        };
+        if value.is_null() {
+            continue;
+        }
+        match field.runtime_field_type() {
+            RuntimeFieldType::Singular(RuntimeType::Message(_)) => {
+                from_value(value, field.mut_message(msg))?
+            }
+            RuntimeFieldType::Singular(t) => {
+                field.set_singular_field(msg, value_to_box(&field, &t, value)?)
+            }
+            RuntimeFieldType::Repeated(t) => {
+                let values = value.as_array().ok_or_else(|| bad_value(&field))?;
+                let mut boxes = Vec::with_capacity(values.len());
+                for v in values {
+                    boxes.push(value_to_box(&field, &t, v)?);
+                }
+                let mut repeated = field.mut_repeated(msg);
+                for b in boxes {
+                    repeated.push(b);
+                }
+            }
+            RuntimeFieldType::Map(..) => return Err(unsupported(&field)),
+        }
+    }
+    Ok(())
+}
+
+fn scalar_to_value(field: &FieldDescriptor, v: ReflectValueRef) -> Result<Value> {
+    Ok(match v {
+        ReflectValueRef::U32(v) => Value::from(v),
+        ReflectValueRef::U64(v) if v > i64::MAX as u64 => Value::from(v.to_string()),
+        ReflectValueRef::U64(v) => Value::from(v),
+        ReflectValueRef::I32(v) => Value::from(v),
+        ReflectValueRef::I64(v) => Value::from(v),
+        ReflectValueRef::F32(v) => Number::from_f64(v as f64).map_or(Value::Null, Value::Number),
+        ReflectValueRef::F64(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        ReflectValueRef::Bool(v) => Value::from(v),
+        ReflectValueRef::String(v) => Value::from(v),
+        ReflectValueRef::Enum(d, v) => match d.value_by_number(v) {
+            Some(e) => Value::from(e.name()),
+            None => Value::from(v),
+        },
+        ReflectValueRef::Message(m) => to_value(&*m)?,
+        ReflectValueRef::Bytes(_) => return Err(unsupported(field)),
+    })
+}
+
+fn value_to_box(field: &FieldDescriptor, t: &RuntimeType, v: &Value) -> Result<ReflectValueBox> {
+    let bad = || bad_value(field);
+    Ok(match t {
+        RuntimeType::U32 => ReflectValueBox::U32(
+            v.as_u64().and_then(|v| u32::try_from(v).ok()).ok_or_else(bad)?,
+        ),
+        RuntimeType::U64 => ReflectValueBox::U64(
+            v.as_u64()
+                .or_else(|| v.as_str().and_then(|s| s.parse().ok()))
+                .ok_or_else(bad)?,
+        ),
+        RuntimeType::I32 => ReflectValueBox::I32(
+            v.as_i64().and_then(|v| i32::try_from(v).ok()).ok_or_else(bad)?,
+        ),
+        RuntimeType::I64 => ReflectValueBox::I64(v.as_i64().ok_or_else(bad)?),
+        RuntimeType::F32 => ReflectValueBox::F32(v.as_f64().ok_or_else(bad)? as f32),
+        RuntimeType::F64 => ReflectValueBox::F64(v.as_f64().ok_or_else(bad)?),
+        RuntimeType::Bool => ReflectValueBox::Bool(v.as_bool().ok_or_else(bad)?),
+        RuntimeType::String => ReflectValueBox::String(v.as_str().ok_or_else(bad)?.to_string()),
+        RuntimeType::Enum(d) => {
+            let number = match v {
+                Value::String(name) => d.value_by_name(name).map(|e| e.value()),
+                _ => v.as_i64().and_then(|v| i32::try_from(v).ok()),
+            };
+            ReflectValueBox::Enum(d.clone(), number.ok_or_else(bad)?)
+        }
+        RuntimeType::Message(d) => {
+            let mut msg = d.new_instance();
+            from_value(v, &mut *msg)?;
+            ReflectValueBox::Message(msg)
+        }
+        RuntimeType::VecU8 => return Err(unsupported(field)),
+    })
+}
+
+fn bad_value(field: &FieldDescriptor) -> Error {
+    Error::InvalidArgument(format!("bad JSON value for {}", field.full_name()))
+}
+
+fn unsupported(field: &FieldDescriptor) -> Error {
+    Error::InvalidArgument(format!("{} has no JSON mapping", field.full_name()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocols::{metrics, oci};
+    use protobuf::Message;
+
+    #[test]
+    fn test_round_trip() {
+        let mut m = metrics::Metrics::new();
+        m.pids.mut_or_insert_default().current = 5;
+        m.cpu.mut_or_insert_default().usage.mut_or_insert_default().per_cpu = vec![1, u64::MAX];
+        let mut entry = metrics::BlkIOEntry::new();
+        entry.op = "Read".to_string();
+        entry.major = 8;
+        entry.value = 4096;
+        m.blkio.mut_or_insert_default().io_service_bytes_recursive.push(entry);
+
+        let value = to_value(&m).unwrap();
+        assert_eq!(value["pids"]["current"], 5);
+        assert_eq!(value["pids"]["limit"], 0);
+        assert_eq!(value["memory"], Value::Null);
+        assert_eq!(value["cpu"]["usage"]["per_cpu"][0], 1);
+        assert_eq!(value["cpu"]["usage"]["per_cpu"][1], "18446744073709551615");
+        assert_eq!(value["blkio"]["io_service_bytes_recursive"][0]["op"], "Read");
+
+        let mut decoded = metrics::Metrics::new();
+        from_value(&value, &mut decoded).unwrap();
+        assert_eq!(decoded, m);
+        assert_eq!(decoded.write_to_bytes().unwrap(), m.write_to_bytes().unwrap());
+    }
+
+    #[test]
+    fn test_from_value() {
+        let mut options = oci::Options::new();
+        from_value(
+            &serde_json::json!({"root": "/run/runc", "io_uid": 1000, "added_later": true}),
+            &mut options,
+        )
+        .unwrap();
+        assert_eq!(options.root, "/run/runc");
+        assert_eq!(options.io_uid, 1000);
+
+        let mut options = oci::Options::new();
+        assert!(from_value(&serde_json::json!({"io_uid": -1}), &mut options).is_err());
+        assert!(from_value(&serde_json::json!({"io_uid": "1000"}), &mut options).is_err());
+        assert!(from_value(&serde_json::json!({"root": 1}), &mut options).is_err());
+        assert!(from_value(&serde_json::json!([]), &mut options).is_err());
+    }
+}
@@ -13,12 +13,17 @@
 pub mod any;
 pub mod empty;
 pub mod gogo;
+pub mod json;
 pub mod metrics;
+pub mod metrics_v2;
 pub mod mount;
+pub mod oci;
+pub mod runtimeoptions;
 pub mod shim;
 pub mod shim_ttrpc;
 pub mod task;
 pub mod timestamp;
+pub mod typeurl;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,196 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Type urls of the payloads carried in `google.protobuf.Any` fields, the
+//! same names containerd registers with its typeurl package.
+//!
+//! Protobuf messages are named by their full message name, optionally behind
+//! a host such as `type.googleapis.com/`. JSON payloads such as the OCI
+//! process spec are named by a full `types.containerd.io/...` path.
+
+use super::{metrics, metrics_v2, oci, runtimeoptions};
+use crate::client::error::{Error, Result};
+use crate::other_error;
+use protobuf::well_known_types::any::Any;
+use protobuf::Message;
+
+pub const PROCESS_SPEC_TYPE: &str = "types.containerd.io/opencontainers/runtime-spec/1/Process";
+pub const RUNC_OPTIONS_TYPE: &str = "containerd.runc.v1.Options";
+pub const RUNC_PROCESS_DETAILS_TYPE: &str = "containerd.runc.v1.ProcessDetails";
+pub const RUNTIME_OPTIONS_TYPE: &str = "runtimeoptions.v1.Options";
+pub const METRICS_V1_TYPE: &str = "io.containerd.cgroups.v1.Metrics";
+pub const METRICS_V2_TYPE: &str = "io.containerd.cgroups.v2.Metrics";
+
+const JSON_TYPES: [&str; 1] = [PROCESS_SPEC_TYPE];
+
+const MESSAGE_TYPES: [&str; 5] = [
+    RUNC_OPTIONS_TYPE,
+    RUNC_PROCESS_DETAILS_TYPE,
+    RUNTIME_OPTIONS_TYPE,
+    METRICS_V1_TYPE,
+    METRICS_V2_TYPE,
+];
+
+/// A generated message with a registered type url.
+pub trait TypeUrl: Message {
+    const TYPE_URL: &'static str;
+}
+
+impl TypeUrl for oci::Options {
+    const TYPE_URL: &'static str = RUNC_OPTIONS_TYPE;
+}
+
+impl TypeUrl for oci::ProcessDetails {
+    const TYPE_URL: &'static str = RUNC_PROCESS_DETAILS_TYPE;
+}
+
+impl TypeUrl for runtimeoptions::Options {
+    const TYPE_URL: &'static str = RUNTIME_OPTIONS_TYPE;
+}
+
+impl TypeUrl for metrics::Metrics {
+    const TYPE_URL: &'static str = METRICS_V1_TYPE;
+}
+
+impl TypeUrl for metrics_v2::Metrics {
+    const TYPE_URL: &'static str = METRICS_V2_TYPE;
+}
+
+/// Maps a type url to the registered one it names.
+pub fn resolve(type_url: &str) -> Result<&'static str> {
+    if let Some(url) = JSON_TYPES.iter().find(|url| **url == type_url) {
+        return Ok(url);
+    }
+    let name = type_url.rsplit('/').next().unwrap_or_default();
+    MESSAGE_TYPES
+        .iter()
+        .find(|url| **url == name)
+        .copied()
+        .ok_or_else(|| Error::InvalidArgument(format!("unknown type url {:?}", type_url)))
+}
+
+/// Whether `any` carries a message of type `M`.
+pub fn is<M: TypeUrl>(any: &Any) -> bool {
+    resolve(&any.type_url).is_ok_and(|url| url == M::TYPE_URL)
+}
+
+pub fn marshal<M: TypeUrl>(msg: &M) -> Result<Any> {
+    let mut any = Any::new();
+    any.type_url = M::TYPE_URL.to_string();
+    any.value = msg
+        .write_to_bytes()
+        .map_err(other_error!(e, format!("failed to encode {}:", M::TYPE_URL)))?;
+    Ok(any)
+}
+
+pub fn unmarshal<M: TypeUrl>(any: &Any) -> Result<M> {
+    let url = resolve(&any.type_url)?;
+    if url != M::TYPE_URL {
+        return Err(Error::InvalidArgument(format!(
+            "type url {:?} is not {}",
+            any.type_url,
+            M::TYPE_URL
+        )));
+    }
+    M::parse_from_bytes(&any.value)
+        .map_err(other_error!(e, format!("failed to decode {}:", M::TYPE_URL)))
+}
+
+/// Wraps a JSON payload, e.g. an OCI process spec, under a registered type url.
+pub fn marshal_json(type_url: &str, value: &[u8]) -> Result<Any> {
+    if !JSON_TYPES.contains(&type_url) {
+        return Err(Error::InvalidArgument(format!(
+            "unknown JSON type url {:?}",
+            type_url
+        )));
+    }
+    let mut any = Any::new();
+    any.type_url = type_url.to_string();
+    any.value = value.to_vec();
+    Ok(any)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use protobuf::MessageFull;
+
+    #[test]
+    fn test_type_urls_match_descriptors() {
+        assert_eq!(oci::Options::TYPE_URL, oci::Options::descriptor().full_name());
+        assert_eq!(
+            oci::ProcessDetails::TYPE_URL,
+            oci::ProcessDetails::descriptor().full_name()
+        );
+        assert_eq!(
+            runtimeoptions::Options::TYPE_URL,
+            runtimeoptions::Options::descriptor().full_name()
+        );
+        assert_eq!(
+            metrics::Metrics::TYPE_URL,
+            metrics::Metrics::descriptor().full_name()
+        );
+        assert_eq!(
+            metrics_v2::Metrics::TYPE_URL,
+            metrics_v2::Metrics::descriptor().full_name()
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(resolve(PROCESS_SPEC_TYPE).unwrap(), PROCESS_SPEC_TYPE);
+        assert_eq!(resolve("containerd.runc.v1.Options").unwrap(), RUNC_OPTIONS_TYPE);
+        assert_eq!(
+            resolve("type.googleapis.com/io.containerd.cgroups.v2.Metrics").unwrap(),
+            METRICS_V2_TYPE
+        );
+        assert!(resolve("types.containerd.io/opencontainers/runtime-spec/1/Spec").is_err());
+        assert!(resolve("Process").is_err());
+        assert!(resolve("").is_err());
+    }
+
+    #[test]
+    fn test_marshal_unmarshal() {
+        let mut details = oci::ProcessDetails::new();
+        details.exec_id = "exec1".to_string();
+        let any = marshal(&details).unwrap();
+        assert_eq!(any.type_url, RUNC_PROCESS_DETAILS_TYPE);
+        assert!(is::<oci::ProcessDetails>(&any));
+        assert!(!is::<oci::Options>(&any));
+
+        let decoded: oci::ProcessDetails = unmarshal(&any).unwrap();
+        assert_eq!(decoded.exec_id, "exec1");
+
+        let err = unmarshal::<oci::Options>(&any).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)));
+
+        let mut unknown = any.clone();
+        unknown.type_url = "example.com/Unknown".to_string();
+        let err = unmarshal::<oci::ProcessDetails>(&unknown).unwrap_err();
+        assert!(matches!(err, Error::InvalidArgument(_)));
+
+        let mut corrupt = any;
+        corrupt.value = vec![0x0a, 0x10];
+        assert!(matches!(
+            unmarshal::<oci::ProcessDetails>(&corrupt).unwrap_err(),
+            Error::Other(_)
+        ));
+    }
+
+    #[test]
+    fn test_marshal_json() {
+        let any = marshal_json(PROCESS_SPEC_TYPE, b"{}").unwrap();
+        assert_eq!(any.type_url, PROCESS_SPEC_TYPE);
+        assert_eq!(any.value, b"{}");
+        assert!(marshal_json(RUNC_OPTIONS_TYPE, b"{}").is_err());
+    }
+}
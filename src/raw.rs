@@ -0,0 +1,57 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! Arrays and strings handed to C, each released with the `free_*` or
+//! `from_*` counterpart of the function that allocated it.
+
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// A null pointer for an empty vector.
+pub(crate) fn into_raw_array<T>(v: Vec<T>) -> (*mut T, usize) {
+    if v.is_empty() {
+        return (ptr::null_mut(), 0);
+    }
+    let len = v.len();
+    (Box::into_raw(v.into_boxed_slice()) as *mut T, len)
+}
+
+/// Takes back an array of `into_raw_array`.
+pub(crate) unsafe fn from_raw_array<T>(ptr: *mut T, len: usize) -> Vec<T> {
+    if ptr.is_null() {
+        return Vec::new();
+    }
+    Box::from_raw(ptr::slice_from_raw_parts_mut(ptr, len)).into_vec()
+}
+
+/// A string with a NUL inside becomes "".
+pub(crate) fn into_raw_string(s: &str) -> *mut c_char {
+    CString::new(s).unwrap_or_default().into_raw()
+}
+
+/// Takes back a string of `into_raw_string`, null is ignored.
+pub(crate) fn free_raw_string(s: *mut c_char) {
+    if !s.is_null() {
+        unsafe { drop(CString::from_raw(s)) };
+    }
+}
+
+pub(crate) fn into_raw_strings(strings: Vec<String>) -> *mut *mut c_char {
+    into_raw_array(strings.iter().map(|s| into_raw_string(s)).collect()).0
+}
+
+pub(crate) unsafe fn free_raw_strings(strings: *mut *mut c_char, len: usize) {
+    for s in from_raw_array(strings, len) {
+        free_raw_string(s);
+    }
+}
@@ -0,0 +1,796 @@
+// Copyright (c) 2020 Huawei Technologies Co.,Ltd. All rights reserved.
+//
+// lib-shim-v2 is licensed under Mulan PSL v2.
+// You can use this software according to the terms and conditions of the Mulan
+// PSL v2.
+// You may obtain a copy of Mulan PSL v2 at:
+//         http://license.coscl.org.cn/MulanPSL2
+// THIS SOFTWARE IS PROVIDED ON AN "AS IS" BASIS, WITHOUT WARRANTIES OF ANY
+// KIND, EITHER EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO
+// NON-INFRINGEMENT, MERCHANTABILITY OR FIT FOR A PARTICULAR PURPOSE.
+// See the Mulan PSL v2 for more details.
+
+//! C layout of the complete container metrics, see `struct StatsV2` in
+//! shim_v2.h. Arrays and strings are allocated here and released with
+//! `shim_v2_stats_v2_free`.
+
+use crate::client::derived::{DerivedStats as client_derived_stats, Sample as client_sample};
+use crate::client::psi::{Pressure as client_pressure, PsiData as client_psi_data, PsiStats as client_psi_stats};
+use crate::client::stats::{blkio_devices, BlkioOps as client_blkio_ops, CgroupMetrics};
+use crate::protocols::metrics;
+use crate::raw::{free_raw_string, from_raw_array, into_raw_array, into_raw_string};
+use std::os::raw::{c_char, c_uint, c_ulonglong};
+use std::ptr;
+use std::time::Duration;
+
+/// Layout version written to `StatsV2.version`, bumped whenever fields are
+/// appended.
+pub const STATS_V2_VERSION: c_uint = 1;
+
+#[repr(C)]
+#[derive(Default)]
+pub struct PidsStats {
+    pub current: c_ulonglong,
+    pub limit: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct CpuStats {
+    /* values in nanoseconds */
+    pub usage_total: c_ulonglong,
+    pub usage_kernel: c_ulonglong,
+    pub usage_user: c_ulonglong,
+    pub per_cpu: *mut c_ulonglong,
+    pub per_cpu_len: usize,
+    pub throttling_periods: c_ulonglong,
+    pub throttled_periods: c_ulonglong,
+    pub throttled_time: c_ulonglong,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct MemoryEntry {
+    pub limit: c_ulonglong,
+    pub usage: c_ulonglong,
+    pub max: c_ulonglong,
+    pub failcnt: c_ulonglong,
+}
+
+impl MemoryEntry {
+    fn new(in_obj: &metrics::MemoryEntry) -> MemoryEntry {
+        MemoryEntry {
+            limit: in_obj.limit,
+            usage: in_obj.usage,
+            max: in_obj.max,
+            failcnt: in_obj.failcnt,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct MemoryStats {
+    pub cache: c_ulonglong,
+    pub rss: c_ulonglong,
+    pub rss_huge: c_ulonglong,
+    pub mapped_file: c_ulonglong,
+    pub dirty: c_ulonglong,
+    pub writeback: c_ulonglong,
+    pub pg_pg_in: c_ulonglong,
+    pub pg_pg_out: c_ulonglong,
+    pub pg_fault: c_ulonglong,
+    pub pg_maj_fault: c_ulonglong,
+    pub inactive_anon: c_ulonglong,
+    pub active_anon: c_ulonglong,
+    pub inactive_file: c_ulonglong,
+    pub active_file: c_ulonglong,
+    pub unevictable: c_ulonglong,
+    pub hierarchical_memory_limit: c_ulonglong,
+    pub hierarchical_swap_limit: c_ulonglong,
+    pub total_cache: c_ulonglong,
+    pub total_rss: c_ulonglong,
+    pub total_rss_huge: c_ulonglong,
+    pub total_mapped_file: c_ulonglong,
+    pub total_dirty: c_ulonglong,
+    pub total_writeback: c_ulonglong,
+    pub total_pg_pg_in: c_ulonglong,
+    pub total_pg_pg_out: c_ulonglong,
+    pub total_pg_fault: c_ulonglong,
+    pub total_pg_maj_fault: c_ulonglong,
+    pub total_inactive_anon: c_ulonglong,
+    pub total_active_anon: c_ulonglong,
+    pub total_inactive_file: c_ulonglong,
+    pub total_active_file: c_ulonglong,
+    pub total_unevictable: c_ulonglong,
+    pub usage: MemoryEntry,
+    pub swap: MemoryEntry,
+    pub kernel: MemoryEntry,
+    pub kernel_tcp: MemoryEntry,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct MemoryOomControl {
+    pub oom_kill_disable: c_ulonglong,
+    pub under_oom: c_ulonglong,
+    pub oom_kill: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct HugetlbStat {
+    pub pagesize: *mut c_char,
+    pub usage: c_ulonglong,
+    pub max: c_ulonglong,
+    pub failcnt: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct BlkioEntry {
+    pub op: *mut c_char,
+    pub device: *mut c_char,
+    pub major: c_ulonglong,
+    pub minor: c_ulonglong,
+    pub value: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct BlkioEntries {
+    pub entries: *mut BlkioEntry,
+    pub len: usize,
+}
+
+impl BlkioEntries {
+    fn new(in_obj: &[metrics::BlkIOEntry]) -> BlkioEntries {
+        let (entries, len) = into_raw_array(
+            in_obj
+                .iter()
+                .map(|e| BlkioEntry {
+                    op: into_raw_string(&e.op),
+                    device: into_raw_string(&e.device),
+                    major: e.major,
+                    minor: e.minor,
+                    value: e.value,
+                })
+                .collect(),
+        );
+        BlkioEntries { entries, len }
+    }
+
+    unsafe fn free(&mut self) {
+        for e in from_raw_array(self.entries, self.len) {
+            free_raw_string(e.op);
+            free_raw_string(e.device);
+        }
+        self.entries = ptr::null_mut();
+        self.len = 0;
+    }
+}
+
+#[repr(C)]
+pub struct BlkioStats {
+    pub io_service_bytes_recursive: BlkioEntries,
+    pub io_serviced_recursive: BlkioEntries,
+    pub io_queued_recursive: BlkioEntries,
+    pub io_service_time_recursive: BlkioEntries,
+    pub io_wait_time_recursive: BlkioEntries,
+    pub io_merged_recursive: BlkioEntries,
+    pub io_time_recursive: BlkioEntries,
+    pub sectors_recursive: BlkioEntries,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct BlkioOps {
+    pub read: c_ulonglong,
+    pub write: c_ulonglong,
+    pub sync: c_ulonglong,
+    pub r#async: c_ulonglong,
+    pub discard: c_ulonglong,
+    pub total: c_ulonglong,
+}
+
+impl BlkioOps {
+    fn new(in_obj: &client_blkio_ops) -> BlkioOps {
+        BlkioOps {
+            read: in_obj.read,
+            write: in_obj.write,
+            sync: in_obj.sync,
+            r#async: in_obj.r#async,
+            discard: in_obj.discard,
+            total: in_obj.total,
+        }
+    }
+}
+
+/// The blkio tables of one block device (major:minor), see
+/// `client::stats::BlkioDevice`.
+#[repr(C)]
+pub struct BlkioDeviceStats {
+    pub major: c_ulonglong,
+    pub minor: c_ulonglong,
+    pub device: *mut c_char,
+    pub bytes: BlkioOps,
+    pub ops: BlkioOps,
+    pub queued: BlkioOps,
+    pub service_time: BlkioOps,
+    pub wait_time: BlkioOps,
+    pub merged: BlkioOps,
+    pub time: c_ulonglong,
+    pub sectors: c_ulonglong,
+}
+
+#[repr(C)]
+pub struct RdmaEntry {
+    pub device: *mut c_char,
+    pub hca_handles: c_uint,
+    pub hca_objects: c_uint,
+}
+
+#[repr(C)]
+pub struct RdmaStats {
+    pub current: *mut RdmaEntry,
+    pub current_len: usize,
+    pub limit: *mut RdmaEntry,
+    pub limit_len: usize,
+}
+
+fn rdma_entries(in_obj: &[metrics::RdmaEntry]) -> (*mut RdmaEntry, usize) {
+    into_raw_array(
+        in_obj
+            .iter()
+            .map(|e| RdmaEntry {
+                device: into_raw_string(&e.device),
+                hca_handles: e.hca_handles,
+                hca_objects: e.hca_objects,
+            })
+            .collect(),
+    )
+}
+
+unsafe fn free_rdma_entries(entries: *mut RdmaEntry, len: usize) {
+    for e in from_raw_array(entries, len) {
+        free_raw_string(e.device);
+    }
+}
+
+#[repr(C)]
+pub struct NetworkStat {
+    pub name: *mut c_char,
+    pub rx_bytes: c_ulonglong,
+    pub rx_packets: c_ulonglong,
+    pub rx_errors: c_ulonglong,
+    pub rx_dropped: c_ulonglong,
+    pub tx_bytes: c_ulonglong,
+    pub tx_packets: c_ulonglong,
+    pub tx_errors: c_ulonglong,
+    pub tx_dropped: c_ulonglong,
+}
+
+fn network_stats(network: &[metrics::NetworkStat]) -> (*mut NetworkStat, usize) {
+    into_raw_array(
+        network
+            .iter()
+            .map(|n| NetworkStat {
+                name: into_raw_string(&n.name),
+                rx_bytes: n.rx_bytes,
+                rx_packets: n.rx_packets,
+                rx_errors: n.rx_errors,
+                rx_dropped: n.rx_dropped,
+                tx_bytes: n.tx_bytes,
+                tx_packets: n.tx_packets,
+                tx_errors: n.tx_errors,
+                tx_dropped: n.tx_dropped,
+            })
+            .collect(),
+    )
+}
+
+unsafe fn free_network_stats(network: *mut NetworkStat, len: usize) {
+    for n in from_raw_array(network, len) {
+        free_raw_string(n.name);
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct CgroupStats {
+    pub nr_sleeping: c_ulonglong,
+    pub nr_running: c_ulonglong,
+    pub nr_stopped: c_ulonglong,
+    pub nr_uninterruptible: c_ulonglong,
+    pub nr_io_wait: c_ulonglong,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct PsiData {
+    pub avg10: f64,
+    pub avg60: f64,
+    pub avg300: f64,
+    pub total: c_ulonglong,
+}
+
+impl PsiData {
+    fn new(in_obj: &client_psi_data) -> PsiData {
+        PsiData {
+            avg10: in_obj.avg10,
+            avg60: in_obj.avg60,
+            avg300: in_obj.avg300,
+            total: in_obj.total,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct PsiStats {
+    pub some: PsiData,
+    pub full: PsiData,
+}
+
+impl PsiStats {
+    fn new(in_obj: &Option<client_psi_stats>) -> (bool, PsiStats) {
+        match in_obj {
+            Some(psi) => (
+                true,
+                PsiStats {
+                    some: PsiData::new(&psi.some),
+                    full: PsiData::new(&psi.full),
+                },
+            ),
+            None => (false, PsiStats::default()),
+        }
+    }
+}
+
+/// Pressure stall information, the `has_*` flags tell which resources the
+/// kernel reported.
+#[repr(C)]
+#[derive(Default)]
+pub struct Pressure {
+    pub has_cpu: bool,
+    pub cpu: PsiStats,
+    pub has_memory: bool,
+    pub memory: PsiStats,
+    pub has_io: bool,
+    pub io: PsiStats,
+}
+
+impl Pressure {
+    pub fn new(in_obj: &client_pressure) -> Pressure {
+        let (has_cpu, cpu) = PsiStats::new(&in_obj.cpu);
+        let (has_memory, memory) = PsiStats::new(&in_obj.memory);
+        let (has_io, io) = PsiStats::new(&in_obj.io);
+        Pressure {
+            has_cpu,
+            cpu,
+            has_memory,
+            memory,
+            has_io,
+            io,
+        }
+    }
+}
+
+/// Stats derived from consecutive samples, see `client::derived`.
+#[repr(C)]
+#[derive(Default)]
+pub struct DerivedStats {
+    pub has_rates: bool,
+    pub interval_ns: c_ulonglong,
+    pub cpu_percent: f64,
+    pub cpu_nano_cores: c_ulonglong,
+    pub memory_usage: c_ulonglong,
+    pub memory_working_set: c_ulonglong,
+    pub blkio_read_bps: f64,
+    pub blkio_write_bps: f64,
+    pub blkio_read_iops: f64,
+    pub blkio_write_iops: f64,
+    pub net_rx_bps: f64,
+    pub net_tx_bps: f64,
+}
+
+impl DerivedStats {
+    pub fn new(in_obj: &client_derived_stats) -> DerivedStats {
+        DerivedStats {
+            has_rates: in_obj.has_rates,
+            interval_ns: in_obj.interval.as_nanos() as c_ulonglong,
+            cpu_percent: in_obj.cpu_percent,
+            cpu_nano_cores: in_obj.cpu_nano_cores,
+            memory_usage: in_obj.memory_usage,
+            memory_working_set: in_obj.memory_working_set,
+            blkio_read_bps: in_obj.blkio_read_bps,
+            blkio_write_bps: in_obj.blkio_write_bps,
+            blkio_read_iops: in_obj.blkio_read_iops,
+            blkio_write_iops: in_obj.blkio_write_iops,
+            net_rx_bps: in_obj.net_rx_bps,
+            net_tx_bps: in_obj.net_tx_bps,
+        }
+    }
+}
+
+/// A sample kept by the caller of `shim_v2_stats_derived_from`, unset until
+/// `valid` is.
+#[repr(C)]
+#[derive(Default)]
+pub struct StatsSample {
+    pub valid: bool,
+    pub at_ns: c_ulonglong,
+    pub cpu_total: c_ulonglong,
+    pub blkio_read_bytes: c_ulonglong,
+    pub blkio_write_bytes: c_ulonglong,
+    pub blkio_read_ops: c_ulonglong,
+    pub blkio_write_ops: c_ulonglong,
+    pub net_rx_bytes: c_ulonglong,
+    pub net_tx_bytes: c_ulonglong,
+}
+
+impl StatsSample {
+    pub fn new(in_obj: &client_sample) -> StatsSample {
+        StatsSample {
+            valid: true,
+            at_ns: in_obj.at.as_nanos() as c_ulonglong,
+            cpu_total: in_obj.cpu_total,
+            blkio_read_bytes: in_obj.blkio_read_bytes,
+            blkio_write_bytes: in_obj.blkio_write_bytes,
+            blkio_read_ops: in_obj.blkio_read_ops,
+            blkio_write_ops: in_obj.blkio_write_ops,
+            net_rx_bytes: in_obj.net_rx_bytes,
+            net_tx_bytes: in_obj.net_tx_bytes,
+        }
+    }
+
+    pub fn to_sample(&self) -> Option<client_sample> {
+        if !self.valid {
+            return None;
+        }
+        Some(client_sample {
+            at: Duration::from_nanos(self.at_ns),
+            cpu_total: self.cpu_total,
+            blkio_read_bytes: self.blkio_read_bytes,
+            blkio_write_bytes: self.blkio_write_bytes,
+            blkio_read_ops: self.blkio_read_ops,
+            blkio_write_ops: self.blkio_write_ops,
+            net_rx_bytes: self.net_rx_bytes,
+            net_tx_bytes: self.net_tx_bytes,
+        })
+    }
+}
+
+#[repr(C)]
+pub struct StatsV2 {
+    pub version: c_uint,
+    pub pids: PidsStats,
+    pub cpu: CpuStats,
+    pub memory: MemoryStats,
+    pub memory_oom_control: MemoryOomControl,
+    pub hugetlb: *mut HugetlbStat,
+    pub hugetlb_len: usize,
+    pub blkio: BlkioStats,
+    pub rdma: RdmaStats,
+    pub network: *mut NetworkStat,
+    pub network_len: usize,
+    pub cgroup_stats: CgroupStats,
+    pub blkio_devices: *mut BlkioDeviceStats,
+    pub blkio_devices_len: usize,
+    pub cgroup_version: c_uint,
+    pub pressure: Pressure,
+}
+
+impl StatsV2 {
+    /// No values and no arrays, safe to pass to `free`.
+    pub fn zeroed() -> StatsV2 {
+        // null pointers, zero numbers and false are valid for every field
+        unsafe { std::mem::zeroed() }
+    }
+
+    /// Fills the v1 layout from metrics of either cgroup hierarchy, see
+    /// `CgroupMetrics::normalize`.
+    pub fn new(cgroup_metrics: &CgroupMetrics) -> StatsV2 {
+        let normalized = cgroup_metrics.normalize();
+        let in_obj = &normalized;
+        let cpu = &in_obj.cpu;
+        let (per_cpu, per_cpu_len) = into_raw_array(cpu.usage.per_cpu.clone());
+        let mem = &in_obj.memory;
+        let (hugetlb, hugetlb_len) = into_raw_array(
+            in_obj
+                .hugetlb
+                .iter()
+                .map(|h| HugetlbStat {
+                    pagesize: into_raw_string(&h.pagesize),
+                    usage: h.usage,
+                    max: h.max,
+                    failcnt: h.failcnt,
+                })
+                .collect(),
+        );
+        let blkio = &in_obj.blkio;
+        let (rdma_current, rdma_current_len) = rdma_entries(&in_obj.rdma.current);
+        let (rdma_limit, rdma_limit_len) = rdma_entries(&in_obj.rdma.limit);
+        let (network, network_len) = network_stats(&in_obj.network);
+        let cgroup = &in_obj.cgroup_stats;
+        let oom = &in_obj.memory_oom_control;
+        let (blkio_devices, blkio_devices_len) = into_raw_array(
+            blkio_devices(blkio)
+                .iter()
+                .map(|d| BlkioDeviceStats {
+                    major: d.major,
+                    minor: d.minor,
+                    device: into_raw_string(&d.device),
+                    bytes: BlkioOps::new(&d.bytes),
+                    ops: BlkioOps::new(&d.ops),
+                    queued: BlkioOps::new(&d.queued),
+                    service_time: BlkioOps::new(&d.service_time),
+                    wait_time: BlkioOps::new(&d.wait_time),
+                    merged: BlkioOps::new(&d.merged),
+                    time: d.time,
+                    sectors: d.sectors,
+                })
+                .collect(),
+        );
+
+        StatsV2 {
+            version: STATS_V2_VERSION,
+            pids: PidsStats {
+                current: in_obj.pids.current,
+                limit: in_obj.pids.limit,
+            },
+            cpu: CpuStats {
+                usage_total: cpu.usage.total,
+                usage_kernel: cpu.usage.kernel,
+                usage_user: cpu.usage.user,
+                per_cpu,
+                per_cpu_len,
+                throttling_periods: cpu.throttling.periods,
+                throttled_periods: cpu.throttling.throttled_periods,
+                throttled_time: cpu.throttling.throttled_time,
+            },
+            memory: MemoryStats {
+                cache: mem.cache,
+                rss: mem.rss,
+                rss_huge: mem.rss_huge,
+                mapped_file: mem.mapped_file,
+                dirty: mem.dirty,
+                writeback: mem.writeback,
+                pg_pg_in: mem.pg_pg_in,
+                pg_pg_out: mem.pg_pg_out,
+                pg_fault: mem.pg_fault,
+                pg_maj_fault: mem.pg_maj_fault,
+                inactive_anon: mem.inactive_anon,
+                active_anon: mem.active_anon,
+                inactive_file: mem.inactive_file,
+                active_file: mem.active_file,
+                unevictable: mem.unevictable,
+                hierarchical_memory_limit: mem.hierarchical_memory_limit,
+                hierarchical_swap_limit: mem.hierarchical_swap_limit,
+                total_cache: mem.total_cache,
+                total_rss: mem.total_rss,
+                total_rss_huge: mem.total_rss_huge,
+                total_mapped_file: mem.total_mapped_file,
+                total_dirty: mem.total_dirty,
+                total_writeback: mem.total_writeback,
+                total_pg_pg_in: mem.total_pg_pg_in,
+                total_pg_pg_out: mem.total_pg_pg_out,
+                total_pg_fault: mem.total_pg_fault,
+                total_pg_maj_fault: mem.total_pg_maj_fault,
+                total_inactive_anon: mem.total_inactive_anon,
+                total_active_anon: mem.total_active_anon,
+                total_inactive_file: mem.total_inactive_file,
+                total_active_file: mem.total_active_file,
+                total_unevictable: mem.total_unevictable,
+                usage: MemoryEntry::new(&mem.usage),
+                swap: MemoryEntry::new(&mem.swap),
+                kernel: MemoryEntry::new(&mem.kernel),
+                kernel_tcp: MemoryEntry::new(&mem.kernel_tcp),
+            },
+            memory_oom_control: MemoryOomControl {
+                oom_kill_disable: oom.oom_kill_disable,
+                under_oom: oom.under_oom,
+                oom_kill: oom.oom_kill,
+            },
+            hugetlb,
+            hugetlb_len,
+            blkio: BlkioStats {
+                io_service_bytes_recursive: BlkioEntries::new(&blkio.io_service_bytes_recursive),
+                io_serviced_recursive: BlkioEntries::new(&blkio.io_serviced_recursive),
+                io_queued_recursive: BlkioEntries::new(&blkio.io_queued_recursive),
+                io_service_time_recursive: BlkioEntries::new(&blkio.io_service_time_recursive),
+                io_wait_time_recursive: BlkioEntries::new(&blkio.io_wait_time_recursive),
+                io_merged_recursive: BlkioEntries::new(&blkio.io_merged_recursive),
+                io_time_recursive: BlkioEntries::new(&blkio.io_time_recursive),
+                sectors_recursive: BlkioEntries::new(&blkio.sectors_recursive),
+            },
+            rdma: RdmaStats {
+                current: rdma_current,
+                current_len: rdma_current_len,
+                limit: rdma_limit,
+                limit_len: rdma_limit_len,
+            },
+            network,
+            network_len,
+            cgroup_stats: CgroupStats {
+                nr_sleeping: cgroup.nr_sleeping,
+                nr_running: cgroup.nr_running,
+                nr_stopped: cgroup.nr_stopped,
+                nr_uninterruptible: cgroup.nr_uninterruptible,
+                nr_io_wait: cgroup.nr_io_wait,
+            },
+            blkio_devices,
+            blkio_devices_len,
+            cgroup_version: cgroup_metrics.cgroup_version(),
+            pressure: Pressure::new(&cgroup_metrics.pressure().unwrap_or_default()),
+        }
+    }
+
+    /// Replaces the network stats, e.g. with the ones of the task's network
+    /// namespace when the shim sends none.
+    pub fn set_network(&mut self, network: &[metrics::NetworkStat]) {
+        unsafe { free_network_stats(self.network, self.network_len) };
+        let (network, network_len) = network_stats(network);
+        self.network = network;
+        self.network_len = network_len;
+    }
+
+    /// Releases the arrays and strings and leaves empty ones behind, so a
+    /// second call is harmless.
+    ///
+    /// # Safety
+    ///
+    /// The pointers must have been filled by `StatsV2::new`.
+    pub unsafe fn free(&mut self) {
+        drop(from_raw_array(self.cpu.per_cpu, self.cpu.per_cpu_len));
+        self.cpu.per_cpu = ptr::null_mut();
+        self.cpu.per_cpu_len = 0;
+
+        for h in from_raw_array(self.hugetlb, self.hugetlb_len) {
+            free_raw_string(h.pagesize);
+        }
+        self.hugetlb = ptr::null_mut();
+        self.hugetlb_len = 0;
+
+        self.blkio.io_service_bytes_recursive.free();
+        self.blkio.io_serviced_recursive.free();
+        self.blkio.io_queued_recursive.free();
+        self.blkio.io_service_time_recursive.free();
+        self.blkio.io_wait_time_recursive.free();
+        self.blkio.io_merged_recursive.free();
+        self.blkio.io_time_recursive.free();
+        self.blkio.sectors_recursive.free();
+
+        free_rdma_entries(self.rdma.current, self.rdma.current_len);
+        free_rdma_entries(self.rdma.limit, self.rdma.limit_len);
+        self.rdma.current = ptr::null_mut();
+        self.rdma.current_len = 0;
+        self.rdma.limit = ptr::null_mut();
+        self.rdma.limit_len = 0;
+
+        free_network_stats(self.network, self.network_len);
+        self.network = ptr::null_mut();
+        self.network_len = 0;
+
+        for d in from_raw_array(self.blkio_devices, self.blkio_devices_len) {
+            free_raw_string(d.device);
+        }
+        self.blkio_devices = ptr::null_mut();
+        self.blkio_devices_len = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn test_stats_v2() {
+        let mut m = metrics::Metrics::new();
+        m.pids.mut_or_insert_default().limit = 1024;
+        m.cpu.mut_or_insert_default().usage.mut_or_insert_default().per_cpu = vec![1, 2, 3];
+        m.cpu.mut_or_insert_default().throttling.mut_or_insert_default().throttled_time = 7;
+        m.memory.mut_or_insert_default().cache = 4096;
+        m.memory.mut_or_insert_default().kernel_tcp.mut_or_insert_default().usage = 8;
+        m.memory_oom_control.mut_or_insert_default().oom_kill = 2;
+        let mut hugetlb = metrics::HugetlbStat::new();
+        hugetlb.pagesize = "2MB".to_string();
+        hugetlb.usage = 2097152;
+        m.hugetlb.push(hugetlb);
+        let mut blkio = metrics::BlkIOEntry::new();
+        blkio.op = "Read".to_string();
+        blkio.major = 8;
+        blkio.value = 512;
+        m.blkio.mut_or_insert_default().io_serviced_recursive.push(blkio);
+        let mut sectors = metrics::BlkIOEntry::new();
+        sectors.major = 8;
+        sectors.value = 16;
+        m.blkio.mut_or_insert_default().sectors_recursive.push(sectors);
+        let mut rdma = metrics::RdmaEntry::new();
+        rdma.device = "mlx5_0".to_string();
+        rdma.hca_handles = 3;
+        m.rdma.mut_or_insert_default().limit.push(rdma);
+        let mut net = metrics::NetworkStat::new();
+        net.name = "eth0".to_string();
+        net.rx_bytes = 100;
+        m.network.push(net);
+        m.cgroup_stats.mut_or_insert_default().nr_running = 1;
+
+        let mut stats = StatsV2::new(&CgroupMetrics::V1(m));
+        assert_eq!(stats.version, STATS_V2_VERSION);
+        assert_eq!(stats.cgroup_version, 1);
+        assert!(!stats.pressure.has_cpu);
+        assert_eq!(stats.pids.limit, 1024);
+        assert_eq!(stats.cpu.per_cpu_len, 3);
+        assert_eq!(unsafe { *stats.cpu.per_cpu.add(2) }, 3);
+        assert_eq!(stats.cpu.throttled_time, 7);
+        assert_eq!(stats.memory.cache, 4096);
+        assert_eq!(stats.memory.kernel_tcp.usage, 8);
+        assert_eq!(stats.memory_oom_control.oom_kill, 2);
+        assert_eq!(stats.hugetlb_len, 1);
+        unsafe {
+            let h = &*stats.hugetlb;
+            assert_eq!(CStr::from_ptr(h.pagesize).to_str().unwrap(), "2MB");
+            assert_eq!(h.usage, 2097152);
+            let e = &*stats.blkio.io_serviced_recursive.entries;
+            assert_eq!(CStr::from_ptr(e.op).to_str().unwrap(), "Read");
+            assert_eq!(e.value, 512);
+            let r = &*stats.rdma.limit;
+            assert_eq!(CStr::from_ptr(r.device).to_str().unwrap(), "mlx5_0");
+            let n = &*stats.network;
+            assert_eq!(CStr::from_ptr(n.name).to_str().unwrap(), "eth0");
+        }
+        assert_eq!(stats.blkio.io_service_bytes_recursive.len, 0);
+        assert_eq!(stats.blkio_devices_len, 1);
+        unsafe {
+            let d = &*stats.blkio_devices;
+            assert_eq!((d.major, d.minor), (8, 0));
+            assert_eq!(d.ops.read, 512);
+            assert_eq!(d.bytes.total, 0);
+            assert_eq!(d.sectors, 16);
+            assert_eq!(d.wait_time.total, 0);
+        }
+        assert!(stats.rdma.current.is_null());
+        assert_eq!(stats.cgroup_stats.nr_running, 1);
+
+        unsafe {
+            stats.free();
+            stats.free();
+        }
+        assert!(stats.hugetlb.is_null());
+        assert!(stats.network.is_null());
+        assert!(stats.blkio_devices.is_null());
+    }
+
+    #[test]
+    fn test_stats_sample() {
+        assert!(StatsSample::default().to_sample().is_none());
+        let sample = client_sample {
+            at: Duration::new(12, 345),
+            cpu_total: 1,
+            blkio_read_bytes: 2,
+            blkio_write_bytes: 3,
+            blkio_read_ops: 4,
+            blkio_write_ops: 5,
+            net_rx_bytes: 6,
+            net_tx_bytes: 7,
+        };
+        let c_sample = StatsSample::new(&sample);
+        assert!(c_sample.valid);
+        assert_eq!(c_sample.at_ns, 12_000_000_345);
+        assert_eq!(c_sample.to_sample(), Some(sample));
+    }
+
+    #[test]
+    fn test_stats_v2_pressure() {
+        let mut m = crate::protocols::metrics_v2::Metrics::new();
+        let psi = m.io.mut_or_insert_default().psi.mut_or_insert_default();
+        psi.some.mut_or_insert_default().avg10 = 2.5;
+        psi.full.mut_or_insert_default().total = 42;
+
+        let mut stats = StatsV2::new(&CgroupMetrics::V2(m));
+        assert_eq!(stats.cgroup_version, 2);
+        assert!(stats.pressure.has_io);
+        assert!(!stats.pressure.has_cpu && !stats.pressure.has_memory);
+        assert_eq!(stats.pressure.io.some.avg10, 2.5);
+        assert_eq!(stats.pressure.io.full.total, 42);
+        unsafe { stats.free() };
+    }
+}